@@ -0,0 +1,142 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Log files the output of launched apps is written to.
+//!
+//! Each app has its own log file in `logs` in the cache dir. When a log file
+//! grows over `MAX_LOG_SIZE` it is rotated to `<app>.log.1` and so on, up to
+//! `KEPT_LOGS` old files are kept.
+use crate::{App, DIRS};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read as _, Seek as _, SeekFrom, Write as _};
+use std::path::{Path, PathBuf};
+
+/// Size a log file can grow to before it's rotated, 1 MiB.
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+/// Number of rotated log files kept per app.
+const KEPT_LOGS: usize = 2;
+
+/// Directory the app log files are stored in.
+pub fn log_dir() -> PathBuf {
+    DIRS.cache_dir().join("logs")
+}
+
+/// Path to the log file of `app` in `dir`.
+///
+/// Named after the desktop file ID of the app, or its name if it doesn't
+/// have one.
+pub fn log_path(dir: &Path, app: &App) -> PathBuf {
+    let name = if app.id.is_empty() {
+        &app.name
+    } else {
+        app.id.trim_end_matches(".desktop")
+    };
+    let name: String = name
+        .chars()
+        .map(|c| if c == '/' || c.is_control() { '_' } else { c })
+        .collect();
+    dir.join(format!("{}.log", name))
+}
+
+/// Open the log file of `app` in `dir` for appending, rotating it first if
+/// it is too large.
+///
+/// A header with the command being run is written to the log.
+pub fn open(dir: &Path, app: &App, exec: &[String]) -> io::Result<File> {
+    fs::create_dir_all(dir)?;
+    let path = log_path(dir, app);
+    if fs::metadata(&path).is_ok_and(|meta| meta.len() > MAX_LOG_SIZE) {
+        rotate(&path)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "==> Running {:?}", exec)?;
+    Ok(file)
+}
+
+/// Read the last `lines` lines written to the log file at `path` after
+/// `offset`.
+pub fn read_tail(path: &Path, offset: u64, lines: usize) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut output = Vec::new();
+    file.read_to_end(&mut output)?;
+    let output = String::from_utf8_lossy(&output);
+    let start = output
+        .trim_end_matches('\n')
+        .rmatch_indices('\n')
+        .nth(lines.saturating_sub(1))
+        .map_or(0, |(idx, _)| idx + 1);
+    Ok(output[start..].to_owned())
+}
+
+/// Rotate the log file at `path`, removing the oldest log file.
+fn rotate(path: &Path) -> io::Result<()> {
+    let rotated = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    for n in (1..KEPT_LOGS).rev() {
+        let from = rotated(n);
+        if from.exists() {
+            fs::rename(&from, rotated(n + 1))?;
+        }
+    }
+    fs::rename(path, rotated(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn rotation() {
+        let dir = TempDir::new("logs");
+        let mut app = App::new(
+            "Test/App".to_owned(),
+            "icon".to_owned(),
+            vec!["/bin/test".to_owned()],
+            false,
+        );
+        assert_eq!(log_path(&dir, &app), dir.join("Test_App.log"));
+        app.id = "org.test.App.desktop".to_owned();
+        let path = log_path(&dir, &app);
+        assert_eq!(path, dir.join("org.test.App.log"));
+
+        let exec = vec!["/bin/test".to_owned()];
+        for _ in 0..4 {
+            let mut file = open(&dir, &app, &exec).unwrap();
+            file.write_all(&vec![b'x'; MAX_LOG_SIZE as usize + 1])
+                .unwrap();
+        }
+        let logs: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(logs.len(), KEPT_LOGS + 1);
+        assert!(dir.join("org.test.App.log.2").exists());
+        let log = fs::read_to_string(&path).unwrap();
+        assert!(log.starts_with("==> Running [\"/bin/test\"]\n"));
+    }
+
+    #[test]
+    fn tail() {
+        let dir = TempDir::new("log-tail");
+        let path = dir.join("test.log");
+        fs::write(&path, "old\n1\n2\n3\n").unwrap();
+        assert_eq!(read_tail(&path, 4, 2).unwrap(), "2\n3\n");
+        assert_eq!(read_tail(&path, 4, 5).unwrap(), "1\n2\n3\n");
+        assert_eq!(read_tail(&path, 12, 5).unwrap(), "");
+    }
+}
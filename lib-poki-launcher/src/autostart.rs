@@ -0,0 +1,253 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Starting apps on login, see the XDG "Desktop Application Autostart"
+//! spec.
+//!
+//! Entries are read from `autostart` in $XDG_CONFIG_HOME and in each of
+//! $XDG_CONFIG_DIRS, ex. `/etc/xdg/autostart`. An entry shadows the entries
+//! with the same file name in lower precedence dirs, so a user entry with
+//! `Hidden=true` disables a system entry.
+use crate::desktop_entry::{parse_autostart_entry, EntryParseError};
+use crate::error::{RunError, ScanError};
+use crate::locale::Locale;
+use crate::scan::{current_desktops, filter_app};
+use crate::{xdg, App, Config};
+use log::warn;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// The autostart dirs, highest precedence first.
+pub fn dirs() -> Vec<PathBuf> {
+    xdg::config_home()
+        .into_iter()
+        .chain(xdg::config_dirs())
+        .map(|dir| dir.join("autostart"))
+        .collect()
+}
+
+/// An entry found in the autostart dirs.
+#[derive(Debug)]
+pub struct AutostartEntry {
+    /// File name of the entry, ex. `nm-applet.desktop`.
+    pub name: String,
+    /// Path to the entry.
+    pub path: PathBuf,
+    /// The app of the entry, `None` if the entry is hidden or invalid.
+    pub app: Option<App>,
+    /// Whether the entry is started.
+    pub status: AutostartStatus,
+}
+
+impl AutostartEntry {
+    /// Check if the entry is started.
+    pub fn should_start(&self) -> bool {
+        matches!(self.status, AutostartStatus::Start)
+    }
+}
+
+/// Whether an autostart entry is started, and why not.
+#[derive(Debug)]
+pub enum AutostartStatus {
+    /// The entry is started.
+    Start,
+    /// The entry has `Hidden=true`.
+    Hidden,
+    /// The entry has `X-GNOME-Autostart-enabled=false` or `0`.
+    Disabled,
+    /// The `OnlyShowIn`, `NotShowIn` or `TryExec` key of the entry excludes
+    /// it.
+    Excluded(ScanError),
+    /// The entry couldn't be parsed.
+    Invalid(EntryParseError),
+}
+
+impl fmt::Display for AutostartStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AutostartStatus::Start => write!(f, "start"),
+            AutostartStatus::Hidden => write!(f, "hidden"),
+            AutostartStatus::Disabled => write!(f, "disabled"),
+            AutostartStatus::Excluded(err) => write!(f, "{}", err),
+            AutostartStatus::Invalid(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Find the entries in the autostart dirs for the current desktops.
+///
+/// Nothing is started, so this can be used for a dry run. See `entries_in`.
+pub fn entries() -> Vec<AutostartEntry> {
+    entries_in(&dirs(), &current_desktops())
+}
+
+/// Find the entries in `dirs`, highest precedence first, for the desktops
+/// `desktops`.
+///
+/// The entries are sorted by name. Entries shadowed by an entry with the
+/// same name in a dir with higher precedence are left out.
+pub fn entries_in(
+    dirs: &[PathBuf],
+    desktops: &[String],
+) -> Vec<AutostartEntry> {
+    let locale = Locale::from_env();
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    for dir in dirs {
+        let files = match fs::read_dir(dir) {
+            Ok(files) => files,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                warn!(
+                    "Failed to read autostart dir {}: {}",
+                    dir.display(),
+                    err
+                );
+                continue;
+            }
+        };
+        for file in files.filter_map(Result::ok) {
+            let name = match file.file_name().into_string() {
+                Ok(name) if name.ends_with(".desktop") => name,
+                _ => continue,
+            };
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let path = file.path();
+            let (app, status) =
+                match parse_autostart_entry(&path, locale.as_ref()) {
+                    Err(err) => (None, AutostartStatus::Invalid(err)),
+                    Ok((None, _)) => (None, AutostartStatus::Hidden),
+                    Ok((Some(mut app), enabled)) => {
+                        app.id = name.clone();
+                        let status = if !enabled {
+                            AutostartStatus::Disabled
+                        } else {
+                            match filter_app(&app, desktops) {
+                                Ok(()) => AutostartStatus::Start,
+                                Err(err) => AutostartStatus::Excluded(err),
+                            }
+                        };
+                        (Some(app), status)
+                    }
+                };
+            entries.push(AutostartEntry {
+                name,
+                path,
+                app,
+                status,
+            });
+        }
+    }
+    entries.sort_by(|left, right| left.name.cmp(&right.name));
+    entries
+}
+
+/// Start the apps of the entries that should be started with `App::run`.
+///
+/// Returns the entries that failed to start with the errors.
+pub fn start<'a>(
+    entries: &'a [AutostartEntry],
+    config: &Config,
+) -> Vec<(&'a AutostartEntry, RunError)> {
+    entries
+        .iter()
+        .filter(|entry| entry.should_start())
+        .filter_map(|entry| {
+            let app = entry.app.as_ref()?;
+            app.run(config).err().map(|err| (entry, err))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::path::Path;
+
+    fn write_entry(dir: &Path, name: &str, contents: &str) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(name), contents).unwrap();
+    }
+
+    #[test]
+    fn find_entries() {
+        let dir = TempDir::new("autostart");
+        let user = dir.join("config/autostart");
+        let system = dir.join("xdg/autostart");
+        let entry = |extra: &str| {
+            format!("[Desktop Entry]\nName=App\nExec=app\n{}", extra)
+        };
+        write_entry(&user, "applet.desktop", &entry("NoDisplay=true"));
+        write_entry(&user, "hidden.desktop", "[Desktop Entry]\nHidden=true");
+        write_entry(&user, "invalid.desktop", "[Desktop Entry]\nName=App");
+        write_entry(&user, "readme.txt", "Not an entry");
+        write_entry(&system, "hidden.desktop", &entry(""));
+        write_entry(&system, "applet.desktop", &entry("Hidden=true"));
+        write_entry(
+            &system,
+            "disabled.desktop",
+            &entry("X-GNOME-Autostart-enabled=false"),
+        );
+        write_entry(
+            &system,
+            "off.desktop",
+            &entry("X-GNOME-Autostart-enabled=0"),
+        );
+        write_entry(
+            &system,
+            "typo.desktop",
+            &entry("X-GNOME-Autostart-enabled=yes"),
+        );
+        write_entry(&system, "gnome.desktop", &entry("OnlyShowIn=GNOME;"));
+        write_entry(&system, "sway.desktop", &entry("NotShowIn=GNOME;"));
+        write_entry(
+            &system,
+            "missing.desktop",
+            &entry("TryExec=/nonexistent/poki-test"),
+        );
+
+        let entries = entries_in(&[user, system], &["sway".to_owned()]);
+        let statuses: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.status.to_string()))
+            .collect();
+        assert_eq!(statuses.len(), 9);
+        assert_eq!(statuses[0], ("applet.desktop", "start".to_owned()));
+        assert_eq!(statuses[1], ("disabled.desktop", "disabled".to_owned()));
+        assert_eq!(statuses[3], ("hidden.desktop", "hidden".to_owned()));
+        assert_eq!(statuses[6], ("off.desktop", "disabled".to_owned()));
+        assert_eq!(statuses[7], ("sway.desktop", "start".to_owned()));
+        assert_eq!(statuses[8], ("typo.desktop", "start".to_owned()));
+        assert!(matches!(
+            entries[2].status,
+            AutostartStatus::Excluded(ScanError::OnlyShowIn { .. })
+        ));
+        assert!(matches!(entries[4].status, AutostartStatus::Invalid(_)));
+        assert!(matches!(
+            entries[5].status,
+            AutostartStatus::Excluded(ScanError::TryExec { .. })
+        ));
+        assert!(entries[0].should_start());
+        assert_eq!(entries[0].app.as_ref().unwrap().id, "applet.desktop");
+        assert!(entries[3].app.is_none());
+    }
+}
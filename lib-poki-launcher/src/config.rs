@@ -14,7 +14,11 @@
  * You should have received a copy of the GNU General Public License
  * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
  */
-use crate::DIRS;
+use crate::theme::{ColorScheme, Theme, ThemeError};
+use crate::{
+    matcher::MatcherKind, profile::Profile, rank::RankerKind, search, xdg, App,
+};
+use log::warn;
 use serde::{Deserialize, Serialize};
 use shellexpand::LookupError;
 use std::default::Default;
@@ -33,20 +37,88 @@ use thiserror::Error;
 pub struct Config {
     /// The list of directories to search for desktop entries in.
     ///
+    /// Directories earlier in the list take precedence, an entry shadows
+    /// entries with the same desktop file ID in later directories.
+    ///
     /// Default:
-    /// - /usr/share/applications
-    /// - ~/.local/share/applications/
-    /// - /var/lib/snapd/desktop/applications
+    /// - $XDG_DATA_HOME/applications
+    /// - applications in each of $XDG_DATA_DIRS
     /// - /var/lib/flatpak/exports/share/applications
+    /// - /var/lib/snapd/desktop/applications
     pub app_paths: Vec<PathBuf>,
-    /// Command to use to run terminal apps
+    /// Command to use to run terminal apps, `{}` is replaced by the command
+    /// of the app or it is added at the end if there is no `{}`
+    ///
+    /// Ex. "alacritty -e {}"
     ///
-    /// Default: None which uses "$TERM -e {}"
+    /// Default: None which finds a terminal like xdg-terminal-exec does, from
+    /// the `xdg-terminals.list` files or the installed terminals
     pub term_cmd: Option<String>,
-    /// Frecency half life
+    /// Frecency half life in days
     ///
-    /// Warning: Changing the half life will reset the app scores
+    /// Changing the half life recomputes the app scores from the launch log.
+    /// Apps last launched before the launch log was added keep their current
+    /// score.
     pub half_life: f32,
+    /// Include desktop actions (ex. "Firefox: New Private Window") in search
+    /// results
+    ///
+    /// Default: true
+    pub search_actions: bool,
+    /// Icon theme to look up app icons in
+    ///
+    /// Default: None which uses the system's icon theme
+    pub icon_theme: Option<String>,
+    /// Prefer SVG app icons over PNG ones
+    ///
+    /// Default: false
+    pub prefer_svg_icons: bool,
+    /// Algorithm used to rank apps
+    ///
+    /// One of:
+    /// - "frecency": how often and recently apps were launched
+    /// - "time_of_day": also favor apps launched around the current time of
+    ///   day
+    /// - "prefix": also favor apps launched from similar searches
+    ///
+    /// Default: "frecency"
+    pub ranker: RankerKind,
+    /// Algorithm used to match searches against the apps
+    ///
+    /// One of:
+    /// - "skim": fuzzy matching like the skim fuzzy finder
+    /// - "clangd": fuzzy matching favoring the start of words
+    /// - "prefix": the search at the start of a word
+    /// - "substring": the search anywhere
+    ///
+    /// Default: "skim"
+    pub matcher: MatcherKind,
+    /// Search providers to search, results with the same score are listed in
+    /// the order of this list
+    ///
+    /// The built in providers are:
+    /// - "apps": the installed apps
+    /// - "calculator": evaluate arithmetic, triggered by "="
+    /// - "files": browse files, triggered by "/"
+    /// - "shell": run a shell command, triggered by ">"
+    ///
+    /// Providers added with `AppsDB::add_search_provider` have to be listed
+    /// here too.
+    ///
+    /// Default: ["apps", "calculator", "files", "shell"]
+    pub search_providers: Vec<String>,
+    /// Environment variables to set when running apps, by desktop file ID
+    ///
+    /// Ex.
+    /// app_env: [
+    ///   {
+    ///     app: firefox.desktop
+    ///     env: ["MOZ_ENABLE_WAYLAND=1"]
+    ///   }
+    /// ]
+    ///
+    /// Default: []
+    pub app_env: Vec<AppEnv>,
 
     /// Hight of the launcher window pxs
     ///
@@ -56,92 +128,148 @@ pub struct Config {
     ///
     /// Default: 500
     pub window_width: i32,
+    /// Name of the theme of the launcher window
+    ///
+    /// Themes are loaded from `<name>.hjson` in the theme dir of the
+    /// profile, `themes` next to this file for the default profile, or in
+    /// `poki-launcher/themes` in $XDG_DATA_DIRS. When the system
+    /// prefers a dark or light color scheme the `<name>-dark` or
+    /// `<name>-light` variant of the theme is used if there is one.
+    ///
+    /// Default: "default"
+    pub theme: String,
+
     /// Launcher window background color
     ///
-    /// Default: #282a36
-    pub background_color: String,
+    /// Deprecated: set `background_color` in a theme instead. Overrides the
+    /// value of the theme.
+    pub background_color: Option<String>,
     /// Launcher window border color
     ///
-    /// Default: #2e303b
-    pub border_color: String,
+    /// Deprecated: set `border_color` in a theme instead. Overrides the value
+    /// of the theme.
+    pub border_color: Option<String>,
     /// Launcher input box background color
     ///
-    /// Default: #44475a
-    pub input_box_color: String,
+    /// Deprecated: set `input_box_color` in a theme instead. Overrides the
+    /// value of the theme.
+    pub input_box_color: Option<String>,
     /// Launcher input box text color
     ///
-    /// Default: #f8f8f2
-    pub input_text_color: String,
+    /// Deprecated: set `input_text_color` in a theme instead. Overrides the
+    /// value of the theme.
+    pub input_text_color: Option<String>,
     /// Launcher app list selected app background color
     ///
-    /// Default: #44475a
-    pub selected_app_color: String,
+    /// Deprecated: set `selected_app_color` in a theme instead. Overrides the
+    /// value of the theme.
+    pub selected_app_color: Option<String>,
     /// Launcher app list text color
     ///
-    /// Default: #f8f8f2
-    pub app_text_color: String,
+    /// Deprecated: set `app_text_color` in a theme instead. Overrides the value
+    /// of the theme.
+    pub app_text_color: Option<String>,
     /// Launcher app list separator color
     ///
-    /// Default: #bd93f9
-    pub app_separator_color: String,
+    /// Deprecated: set `app_separator_color` in a theme instead. Overrides the
+    /// value of the theme.
+    pub app_separator_color: Option<String>,
     /// Launcher input box font size
     ///
-    /// Default: 13
-    pub input_font_size: i32,
+    /// Deprecated: set `input_font_size` in a theme instead. Overrides the
+    /// value of the theme.
+    pub input_font_size: Option<i32>,
     /// Launcher app list font size
     ///
-    /// Default: 20
-    pub app_font_size: i32,
+    /// Deprecated: set `app_font_size` in a theme instead. Overrides the
+    /// value of the theme.
+    pub app_font_size: Option<i32>,
     /// Ratio between launcher input box height and total height
     ///
-    /// Default: 0.1
-    /// Ex. 0.1 == 10% of total window height
-    pub input_box_ratio: f32,
+    /// Deprecated: set `input_box_ratio` in a theme instead. Overrides the
+    /// value of the theme.
+    pub input_box_ratio: Option<f32>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
-            app_paths: vec![
-                "/usr/share/applications".into(),
-                "~/.local/share/applications/".into(),
-                "/var/lib/snapd/desktop/applications".into(),
-                "/var/lib/flatpak/exports/share/applications".into(),
-            ],
+            app_paths: default_app_paths(),
             term_cmd: None,
             // Default half life of 7 days
             half_life: 7.0,
+            search_actions: true,
+            icon_theme: None,
+            prefer_svg_icons: false,
+            ranker: RankerKind::Frecency,
+            matcher: MatcherKind::Skim,
+            search_providers: search::builtin_provider_names(),
+            app_env: Vec::new(),
 
             window_height: 500,
             window_width: 500,
 
-            background_color: "#282a36".into(),
-            border_color: "#2e303b".into(),
-            input_box_color: "#44475a".into(),
-            input_text_color: "#f8f8f2".into(),
-            selected_app_color: "#44475a".into(),
-            app_text_color: "#f8f8f2".into(),
-            app_separator_color: "#bd93f9".into(),
-
-            input_font_size: 13,
-            app_font_size: 20,
-            input_box_ratio: 0.1,
+            theme: "default".into(),
+
+            background_color: None,
+            border_color: None,
+            input_box_color: None,
+            input_text_color: None,
+            selected_app_color: None,
+            app_text_color: None,
+            app_separator_color: None,
+            input_font_size: None,
+            app_font_size: None,
+            input_box_ratio: None,
         }
     }
 }
 
+/// Get the default list of directories to search for desktop entries in,
+/// highest precedence first.
+fn default_app_paths() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = xdg::data_home()
+        .into_iter()
+        .chain(xdg::data_dirs())
+        .map(|dir| dir.join("applications"))
+        .collect();
+    for extra in &[
+        "/var/lib/flatpak/exports/share/applications",
+        "/var/lib/snapd/desktop/applications",
+    ] {
+        let extra = PathBuf::from(extra);
+        if !paths.contains(&extra) {
+            paths.push(extra);
+        }
+    }
+    paths
+}
+
 impl Config {
-    /// Load the app config.
-    pub fn load() -> Result<Config, ConfigError> {
+    /// Load the app config of `profile`.
+    ///
+    /// The default config is used if the profile has no config.
+    pub fn load(profile: &Profile) -> Result<Config, ConfigError> {
         let mut cfg = config::Config::default();
-        let config_dir = DIRS.config_dir();
-        let file_path = config_dir.join("poki-launcher.hjson");
-
-        let mut config = if file_path.as_path().exists() {
-            cfg.merge(config::File::with_name(file_path.to_str().unwrap()))?;
-            cfg.try_into()?
-        } else {
-            Self::default()
+        let contents = profile.read_config().map_err(|err| {
+            ConfigError::Read(
+                profile
+                    .config_path()
+                    .map(Path::to_owned)
+                    .unwrap_or_default(),
+                err,
+            )
+        })?;
+
+        let mut config = match contents {
+            Some(contents) => {
+                cfg.merge(config::File::from_str(
+                    &contents,
+                    config::FileFormat::Hjson,
+                ))?;
+                cfg.try_into()?
+            }
+            None => Self::default(),
         };
 
         let mut expanded_paths = Vec::with_capacity(config.app_paths.len());
@@ -160,14 +288,147 @@ impl Config {
         const DAYS_TO_SECS: f32 = 24. * 60. * 60.;
         config.half_life *= DAYS_TO_SECS;
 
+        for (key, _) in config.theme_overrides() {
+            warn!(
+                "The `{}` setting is deprecated, set it in a theme instead",
+                key
+            );
+        }
+
         Ok(config)
     }
+
+    /// Load the theme selected in the config from the theme dirs of
+    /// `profile`, in the variant for the color scheme preferred by the
+    /// system.
+    ///
+    /// The deprecated theme settings of the config override the values of
+    /// the theme.
+    pub fn theme(&self, profile: &Profile) -> Result<Theme, ThemeError> {
+        let mut theme =
+            Theme::load(profile, &self.theme, ColorScheme::from_system())?;
+        for (key, value) in self.theme_overrides() {
+            theme.set(key, value).map_err(|message| ThemeError::Key {
+                path: profile
+                    .config_path()
+                    .map_or_else(|| PathBuf::from("<memory>"), Path::to_owned),
+                key: key.to_owned(),
+                message,
+            })?;
+        }
+        Ok(theme)
+    }
+
+    /// The deprecated theme settings that are set, as theme keys and values.
+    fn theme_overrides(&self) -> Vec<(&'static str, config::Value)> {
+        let colors = [
+            ("background_color", &self.background_color),
+            ("border_color", &self.border_color),
+            ("input_box_color", &self.input_box_color),
+            ("input_text_color", &self.input_text_color),
+            ("selected_app_color", &self.selected_app_color),
+            ("app_text_color", &self.app_text_color),
+            ("app_separator_color", &self.app_separator_color),
+        ];
+        let sizes = [
+            ("input_font_size", self.input_font_size),
+            ("app_font_size", self.app_font_size),
+        ];
+        let colors = colors.iter().filter_map(|(key, value)| {
+            let value = value.as_ref()?.as_str();
+            Some((*key, config::Value::from(value)))
+        });
+        let sizes = sizes.iter().filter_map(|(key, value)| {
+            Some((*key, config::Value::from(i64::from((*value)?))))
+        });
+        let ratio = self.input_box_ratio.map(|value| {
+            ("input_box_ratio", config::Value::from(f64::from(value)))
+        });
+        colors.chain(sizes).chain(ratio).collect()
+    }
+
+    /// The environment variables configured in `app_env` for `app`.
+    ///
+    /// Invalid variables are logged and skipped.
+    pub fn app_env(&self, app: &App) -> Vec<(String, String)> {
+        self.app_env
+            .iter()
+            .filter(|app_env| !app.id.is_empty() && app_env.app == app.id)
+            .flat_map(|app_env| &app_env.env)
+            .filter_map(|var| match var.split_once('=') {
+                Some((key, value)) if !key.is_empty() => {
+                    Some((key.to_owned(), value.to_owned()))
+                }
+                _ => {
+                    warn!("Invalid environment variable `{}` for {}", var, app);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Environment variables to set when running an app.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppEnv {
+    /// Desktop file ID of the app, ex. `firefox.desktop`.
+    pub app: String,
+    /// Variables as `KEY=VALUE`.
+    pub env: Vec<String>,
 }
 
 #[derive(Debug, Error)]
 pub enum ConfigError {
     #[error("Error loading config: {0}")]
     Parse(#[from] config::ConfigError),
+    #[error("Error reading config file {}: {1}", .0.display())]
+    Read(PathBuf, std::io::Error),
     #[error("Error expanding app_path value `{0}`: {1}")]
     ExpandPath(String, LookupError<VarError>),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::fs;
+
+    #[test]
+    fn deprecated_theme_settings() {
+        let dir = TempDir::new("config-theme");
+        let profile =
+            Profile::files(dir.join("apps.db"), dir.join("config.hjson"));
+        fs::create_dir_all(profile.theme_dir().unwrap()).unwrap();
+        fs::write(
+            profile.theme_dir().unwrap().join("mine.hjson"),
+            "{\nbackground_color: \"#111\"\npadding: 8\n}",
+        )
+        .unwrap();
+        let write_config = |source: &str| {
+            let source = format!("{{\ntheme: mine\n{}\n}}", source);
+            fs::write(profile.config_path().unwrap(), source).unwrap();
+            Config::load(&profile).unwrap()
+        };
+
+        let theme = write_config("").theme(&profile).unwrap();
+        assert_eq!(theme.background_color, "#111".parse().unwrap());
+        let config = write_config(
+            "background_color: \"#000\"\napp_font_size: 30\n\
+             input_box_ratio: 0.2",
+        );
+        let theme = config.theme(&profile).unwrap();
+        assert_eq!(theme.background_color, "#000".parse().unwrap());
+        assert_eq!(theme.app_font_size, 30);
+        assert_eq!(theme.input_box_ratio, 0.2);
+        assert_eq!(theme.padding, 8);
+
+        let config = write_config("border_color: blue");
+        match config.theme(&profile).unwrap_err() {
+            ThemeError::Key { path, key, .. } => {
+                assert_eq!(path, dir.join("config.hjson"));
+                assert_eq!(key, "border_color");
+            }
+            err => panic!("Unexpected error {}", err),
+        }
+    }
+}
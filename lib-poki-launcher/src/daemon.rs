@@ -0,0 +1,442 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! A resident launcher daemon.
+//!
+//! The daemon keeps an `AppsDB` in memory, keeps it current with
+//! `hot_reload` and answers the requests of `ipc::Client`s, so launchers
+//! don't have to load the DB and scan the desktop entries on every start.
+use crate::hot_reload::{self, Event, HotReloadHandle};
+use crate::ipc::{
+    decode, read_frame, write_frame, IpcError, Request, RequestFrame, Response,
+    VersionFrame, PROTOCOL_VERSION,
+};
+use crate::scan_cache::scan_desktop_entries;
+use crate::{App, AppsDB, RunOptions, START_GRACE_PERIOD};
+use log::{debug, error, warn};
+use std::fs;
+use std::io;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use thiserror::Error;
+
+/// Most clients served at the same time, further clients are disconnected.
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// The launcher daemon.
+///
+/// The socket file is removed when the daemon is dropped.
+pub struct Daemon {
+    apps_db: Arc<Mutex<AppsDB>>,
+    listener: UnixListener,
+    socket_path: PathBuf,
+    hot_reload: Arc<Mutex<Option<HotReloadHandle>>>,
+}
+
+impl Daemon {
+    /// Serve `apps_db` on the socket at `socket_path`, ex.
+    /// `ipc::socket_path()`.
+    ///
+    /// A socket file left behind by a daemon that didn't exit cleanly is
+    /// replaced, but it's an error if another daemon is listening on it.
+    pub fn bind(
+        apps_db: AppsDB,
+        socket_path: &Path,
+    ) -> Result<Daemon, DaemonError> {
+        let bind_error = |err| DaemonError::Bind {
+            path: socket_path.to_owned(),
+            err,
+        };
+        if let Some(dir) = socket_path.parent() {
+            fs::create_dir_all(dir).map_err(bind_error)?;
+        }
+        let listener = match UnixListener::bind(socket_path) {
+            Ok(listener) => listener,
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+                if UnixStream::connect(socket_path).is_ok() {
+                    return Err(DaemonError::AlreadyRunning(
+                        socket_path.to_owned(),
+                    ));
+                }
+                debug!("Removing stale socket {}", socket_path.display());
+                fs::remove_file(socket_path).map_err(bind_error)?;
+                UnixListener::bind(socket_path).map_err(bind_error)?
+            }
+            Err(err) => return Err(bind_error(err)),
+        };
+        Ok(Daemon {
+            apps_db: Arc::new(Mutex::new(apps_db)),
+            listener,
+            socket_path: socket_path.to_owned(),
+            hot_reload: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Keep the apps and config current by watching the config file and
+    /// the desktop entries with `hot_reload`.
+    ///
+    /// The config file is only watched if it exists.
+    pub fn watch(&mut self) -> Result<(), DaemonError> {
+        let mut builder = hot_reload::build();
+        {
+            let apps_db = self.apps_db.lock().unwrap();
+            let profile = apps_db.profile();
+            if profile.config_path().is_some_and(Path::exists) {
+                builder.config(profile);
+            }
+            builder.apps(&apps_db);
+        }
+        let apps_db = Arc::clone(&self.apps_db);
+        let handle = builder.start(move |event| match event {
+            Event::ConfigChanged(config) => {
+                apps_db.lock().unwrap().set_config(*config)
+            }
+            Event::AppsChanged {
+                added,
+                removed,
+                modified,
+            } => {
+                apps_db
+                    .lock()
+                    .unwrap()
+                    .merge_app_changes(added, &removed, modified);
+                if let Err(err) = AppsDB::save_shared(&apps_db) {
+                    error!("{}", err);
+                }
+            }
+        })?;
+        *self.hot_reload.lock().unwrap() = Some(handle);
+        Ok(())
+    }
+
+    /// Accept clients and answer their requests, each client is served from
+    /// its own thread.
+    ///
+    /// Clients connecting while `MAX_CONNECTIONS` clients are served are
+    /// disconnected. Only returns if accepting a client fails.
+    pub fn run(&self) -> io::Result<()> {
+        let connections = Arc::new(AtomicUsize::new(0));
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                warn!("Too many daemon connections, disconnecting a client");
+                continue;
+            }
+            let apps_db = Arc::clone(&self.apps_db);
+            let hot_reload = Arc::clone(&self.hot_reload);
+            let connections = Arc::clone(&connections);
+            thread::spawn(move || {
+                if let Err(err) = serve(&apps_db, &hot_reload, stream) {
+                    warn!("Closing daemon connection: {}", err);
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        Ok(())
+    }
+
+    /// Answer `request`.
+    pub fn handle(&self, request: Request) -> Response {
+        handle(&self.apps_db, &self.hot_reload, request)
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        // Stop watching before the apps DB is dropped
+        self.hot_reload.lock().unwrap().take();
+        let _ = fs::remove_file(&self.socket_path);
+    }
+}
+
+/// Answer the requests of the client connected with `stream` until it
+/// disconnects.
+fn serve(
+    apps_db: &Mutex<AppsDB>,
+    hot_reload: &Mutex<Option<HotReloadHandle>>,
+    mut stream: UnixStream,
+) -> Result<(), IpcError> {
+    while let Some(buf) = read_frame(&mut stream)? {
+        let version: VersionFrame = decode(&buf)?;
+        let response = if version.version == PROTOCOL_VERSION {
+            let frame: RequestFrame = decode(&buf)?;
+            handle(apps_db, hot_reload, frame.request)
+        } else {
+            Response::UnsupportedVersion(PROTOCOL_VERSION)
+        };
+        write_frame(&mut stream, &response)?;
+    }
+    Ok(())
+}
+
+/// Answer `request` with the apps in `apps_db`, rescans go through
+/// `hot_reload` while it watches the apps.
+///
+/// `apps_db` isn't locked while apps are started, desktop entries are
+/// scanned or the DB is saved, so other clients aren't blocked meanwhile.
+fn handle(
+    apps_db: &Mutex<AppsDB>,
+    hot_reload: &Mutex<Option<HotReloadHandle>>,
+    request: Request,
+) -> Response {
+    match request {
+        Request::Search { query, limit } => Response::Apps(
+            apps_db
+                .lock()
+                .unwrap()
+                .get_ranked_list(query.as_deref(), limit),
+        ),
+        Request::Launch {
+            key,
+            query,
+            activation_token,
+        } => {
+            let (app, config) = {
+                let apps_db = apps_db.lock().unwrap();
+                (apps_db.app(&key), apps_db.config.clone())
+            };
+            let app = match app {
+                Some(app) => app,
+                None => return Response::Error(format!("No app `{}`", key)),
+            };
+            let options = RunOptions {
+                activation_token,
+                ..RunOptions::default()
+            };
+            let running = match app.run_with_options(&[], &options, &config) {
+                Ok(running) => running,
+                Err(err) => return Response::Error(err.to_string()),
+            };
+            // The client doesn't wait for the app to start, so failures are
+            // only logged
+            thread::spawn(move || {
+                if let Err(err) = running.check(START_GRACE_PERIOD) {
+                    error!("{}", err);
+                }
+            });
+            record_launch(apps_db, &app, query.as_deref())
+        }
+        Request::Rescan => {
+            // The watcher has to know about the apps found by the rescan to
+            // report when they're removed
+            let errors = hot_reload
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(HotReloadHandle::rescan);
+            if let Some(errors) = errors {
+                return Response::Rescanned {
+                    apps: apps_db.lock().unwrap().apps.len(),
+                    errors: errors.iter().map(ToString::to_string).collect(),
+                };
+            }
+            let (app_paths, cache_path) = {
+                let apps_db = apps_db.lock().unwrap();
+                (
+                    apps_db.config.app_paths.clone(),
+                    apps_db.profile().scan_cache_path(),
+                )
+            };
+            let (apps, errors) =
+                scan_desktop_entries(&app_paths, cache_path.as_deref());
+            let apps = {
+                let mut apps_db = apps_db.lock().unwrap();
+                apps_db.merge_new_entries(apps);
+                apps_db.apps.len()
+            };
+            match AppsDB::save_shared(apps_db) {
+                Ok(()) => Response::Rescanned {
+                    apps,
+                    errors: errors.iter().map(ToString::to_string).collect(),
+                },
+                Err(err) => Response::Error(err.to_string()),
+            }
+        }
+    }
+}
+
+/// Record the launch of `app` from the search `query` in `apps_db`.
+///
+/// The app may have been removed from `apps_db` while it was started, ex. by
+/// a rescan, then the launch isn't recorded.
+fn record_launch(
+    apps_db: &Mutex<AppsDB>,
+    app: &App,
+    query: Option<&str>,
+) -> Response {
+    if let Err(err) = apps_db.lock().unwrap().add_launch(app, query) {
+        return Response::Error(format!(
+            "{} was removed while launching: {}",
+            app.name, err
+        ));
+    }
+    if let Err(err) = AppsDB::save_shared(apps_db) {
+        error!("{}", err);
+    }
+    Response::Launched
+}
+
+/// Error starting the daemon.
+#[derive(Debug, Error)]
+pub enum DaemonError {
+    #[error("A daemon is already listening on {}", .0.display())]
+    AlreadyRunning(PathBuf),
+    #[error("Failed to listen on {}: {err}", .path.display())]
+    Bind { path: PathBuf, err: io::Error },
+    // The Display impl of notify errors only prints a deprecation notice
+    #[error("Failed to watch for changes: {0:?}")]
+    HotReload(#[from] notify::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::Client;
+    use crate::test_util::{app, TempDir};
+    use crate::{AppKey, Config, Profile};
+
+    #[test]
+    fn serve_clients() {
+        let dir = TempDir::new("daemon");
+        let socket_path = dir.join("daemon.sock");
+        let app = app("true.desktop", "True", &["true"]);
+        let config = Config {
+            app_paths: vec![],
+            ..Config::default()
+        };
+        let apps_db =
+            AppsDB::new(config, vec![app]).with_profile(Profile::in_memory());
+        let daemon = Arc::new(Daemon::bind(apps_db, &socket_path).unwrap());
+        assert!(matches!(
+            Daemon::bind(AppsDB::new(Config::default(), vec![]), &socket_path),
+            Err(DaemonError::AlreadyRunning(_))
+        ));
+        {
+            let daemon = Arc::clone(&daemon);
+            thread::spawn(move || daemon.run());
+        }
+
+        let mut client = Client::connect_to(&socket_path).unwrap();
+        let results = client.search(Some("tru"), None).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].match_indices, vec![0, 1, 2]);
+        let key = results[0].app.key();
+        client.launch(key, Some("tru"), None).unwrap();
+        assert!(matches!(
+            client.launch(AppKey::default(), None, None),
+            Err(IpcError::Daemon(_))
+        ));
+        assert_eq!(daemon.apps_db.lock().unwrap().launches().len(), 1);
+        let (apps, _) = client.rescan().unwrap();
+        // The app isn't in any of the app paths
+        assert_eq!(apps, 0);
+
+        let mut stream = UnixStream::connect(&socket_path).unwrap();
+        let frame = RequestFrame {
+            version: PROTOCOL_VERSION + 1,
+            request: Request::Rescan,
+        };
+        write_frame(&mut stream, &frame).unwrap();
+        let response = read_frame(&mut stream).unwrap().unwrap();
+        assert!(matches!(
+            decode(&response).unwrap(),
+            Response::UnsupportedVersion(PROTOCOL_VERSION)
+        ));
+    }
+
+    #[test]
+    fn record_removed_app() {
+        let app = app("true.desktop", "True", &["true"]);
+        let apps_db = AppsDB::new(Config::default(), vec![app.clone()])
+            .with_profile(Profile::in_memory());
+        let apps_db = Mutex::new(apps_db);
+        // A rescan removes the app after it was started
+        apps_db.lock().unwrap().merge_new_entries(vec![]);
+        assert!(matches!(
+            record_launch(&apps_db, &app, None),
+            Response::Error(_)
+        ));
+        let apps_db = apps_db.lock().unwrap();
+        assert!(apps_db.launches().is_empty());
+    }
+
+    #[test]
+    fn rescan_while_watching() {
+        let dir = TempDir::new("daemon-rescan");
+        let apps_dir = dir.join("applications");
+        fs::create_dir_all(&apps_dir).unwrap();
+        let config = Config {
+            app_paths: vec![apps_dir.clone()],
+            ..Config::default()
+        };
+        let apps_db =
+            AppsDB::new(config, vec![]).with_profile(Profile::in_memory());
+        // Only a rescan finds the entry
+        let entry = apps_dir.join("true.desktop");
+        fs::write(&entry, "[Desktop Entry]\nName=True\nExec=true").unwrap();
+        let mut daemon =
+            Daemon::bind(apps_db, &dir.join("daemon.sock")).unwrap();
+        daemon.watch().unwrap();
+        assert!(matches!(
+            daemon.handle(Request::Rescan),
+            Response::Rescanned { apps: 1, .. }
+        ));
+        // The watcher knows the app found by the rescan, so it removes it
+        fs::remove_file(&entry).unwrap();
+        let removed = (0..100).any(|_| {
+            thread::sleep(std::time::Duration::from_millis(50));
+            daemon.apps_db.lock().unwrap().apps.is_empty()
+        });
+        assert!(removed);
+    }
+
+    #[test]
+    fn connection_limit() {
+        let dir = TempDir::new("daemon-limit");
+        let socket_path = dir.join("daemon.sock");
+        let apps_db = AppsDB::new(Config::default(), vec![])
+            .with_profile(Profile::in_memory());
+        let daemon = Arc::new(Daemon::bind(apps_db, &socket_path).unwrap());
+        {
+            let daemon = Arc::clone(&daemon);
+            thread::spawn(move || daemon.run());
+        }
+
+        // The clients are served once they got an answer
+        let mut clients: Vec<_> = (0..MAX_CONNECTIONS)
+            .map(|_| {
+                let mut client = Client::connect_to(&socket_path).unwrap();
+                client.search(None, None).unwrap();
+                client
+            })
+            .collect();
+        let mut client = Client::connect_to(&socket_path).unwrap();
+        assert!(client.search(None, None).is_err());
+
+        clients.pop();
+        let served = (0..100).any(|_| {
+            thread::sleep(std::time::Duration::from_millis(10));
+            Client::connect_to(&socket_path)
+                .and_then(|mut client| client.search(None, None))
+                .is_ok()
+        });
+        assert!(served);
+    }
+}
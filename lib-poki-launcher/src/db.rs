@@ -14,26 +14,32 @@
  * You should have received a copy of the GNU General Public License
  * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
  */
-use std::fs::File;
+use std::collections::HashSet;
 use std::io;
-use std::io::Write as _;
 use std::process;
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
-use std::{cmp::Ordering, fs::create_dir, path::PathBuf};
+use std::{cmp::Ordering, path::PathBuf};
 
 use crate::{
     config::Config,
-    scan::{scan_desktop_entries, ScanError},
+    format::{self, FormatError},
+    history::{score_from_launches, AppHistory, Launch},
+    icon::{find_icon, IconCache},
+    matcher::MatchField,
+    mime::{MimeError, MimeIndex},
+    profile::Profile,
+    rank::{RankContext, Ranker},
+    scan::ScanError,
+    scan_cache::scan_desktop_entries,
+    search::{self, SearchItem, SearchProvider},
 };
-use file_locker::FileLock;
-use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
 use log::*;
-use rmp_serde as rmp;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{App, DB_PATH, DIRS};
+use crate::{App, AppKey};
 
 /// An apps database.
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -41,179 +47,714 @@ pub struct AppsDB {
     /// The list of apps.
     pub apps: Vec<App>,
     /// The reference time used in the ranking calculations.
-    reference_time: f64,
+    pub(crate) reference_time: f64,
     /// The half life of the app launches
-    half_life: f32,
+    pub(crate) half_life: f32,
+    /// Apps that disappeared in a recent rescan.
+    pub(crate) removed: Vec<RemovedApp>,
+    /// Log of the app launches, oldest first. See
+    /// `AppsDB::compact_launches`.
+    pub(crate) launches: Vec<Launch>,
+    /// Launches recorded since the DB was last saved.
+    #[serde(skip_serializing, skip_deserializing)]
+    pending: Vec<Launch>,
     /// App config
     #[serde(skip_serializing, skip_deserializing)]
     pub config: Config,
+    /// Where the DB is stored
+    #[serde(skip_serializing, skip_deserializing)]
+    profile: Profile,
+    /// Cache of icon lookups
+    #[serde(skip_serializing, skip_deserializing)]
+    icon_cache: IconCache,
+    /// Ranker used to order the ranked list
+    #[serde(skip_serializing, skip_deserializing, default = "default_ranker")]
+    ranker: Arc<dyn Ranker>,
+    /// Search providers that can be enabled in the config
+    #[serde(
+        skip_serializing,
+        skip_deserializing,
+        default = "search::builtin_providers"
+    )]
+    search_providers: Vec<Arc<dyn SearchProvider>>,
+}
+
+/// The ranker of an `AppsDB` deserialized without a config.
+fn default_ranker() -> Arc<dyn Ranker> {
+    Config::default().ranker.build()
+}
+
+/// An app in the list returned by `AppsDB::get_ranked_list`, with how it
+/// matched and was scored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    /// The app.
+    pub app: App,
+    /// The field of the app the search matched, `None` when not searching.
+    pub match_field: Option<MatchField>,
+    /// Indices of the characters of the matched field matched by the
+    /// search, ex. to highlight them. These are character indices, not byte
+    /// indices.
+    pub match_indices: Vec<usize>,
+    /// Match score of the search, weighted by the matched field, or 0 when
+    /// not searching.
+    pub fuzzy_score: f32,
+    /// Current frecency of the app.
+    pub frecency: f32,
+    /// Score of the ranker the list is sorted by.
+    pub total: f32,
+}
+
+impl SearchResult {
+    /// Text of the field the search matched, the text `match_indices`
+    /// index into.
+    pub fn matched_text(&self) -> Option<&str> {
+        Some(match self.match_field? {
+            MatchField::Name => &self.app.name,
+            MatchField::GenericName => &self.app.generic_name,
+            MatchField::Keyword(i) => self.app.keywords.get(i)?,
+            MatchField::Comment => &self.app.comment,
+        })
+    }
 }
 
+/// An app that disappeared in a rescan.
+///
+/// Removed apps are remembered for `REMOVED_RETENTION` so they keep their
+/// score if they come back, ex. after being briefly missing during a package
+/// upgrade.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RemovedApp {
+    /// The app as it was last seen.
+    pub app: App,
+    /// Time the app was removed in seconds since the epoch.
+    pub removed_at: f64,
+}
+
+/// How long removed apps are remembered, 30 days in seconds.
+const REMOVED_RETENTION: f64 = 30. * 24. * 60. * 60.;
+
+/// Most launches kept in the launch log, the oldest are dropped first.
+const MAX_LAUNCHES: usize = 10_000;
+
 /// Main methods used to manage thr AppsDB
 impl AppsDB {
     /// Initialize the AppsDB
     ///
-    /// Load the DB and rescan if it exists or initialize a new one if it
-    /// doesn't. On successful initialization, returns an AppsDB and a list of
+    /// Load the DB of `profile` and rescan if it exists or initialize a new
+    /// one if it doesn't. On successful initialization, returns an AppsDB and a list of
     /// scan errors that where encountered while scanning for apps. Scan errors
     /// can generally be ignored but you might want to log them. If the half
-    /// life in the config and the half life in the AppsDB on load, the AppsDB
-    /// will be reinitialized.
+    /// life in the config and the half life in the AppsDB differ on load, the
+    /// scores are recomputed with `AppsDB::recompute_scores`.
+    ///
+    /// If the DB file is corrupted it is moved out of the way and a new DB is
+    /// built, keeping the scores that could be recovered from the old file.
     pub fn init(
         config: Config,
+        profile: Profile,
     ) -> Result<(AppsDB, Vec<ScanError>), AppDBError> {
-        let (apps_db, errors) = if Self::exists() {
-            let mut apps_db = Self::load(config.clone())?;
-            if (config.half_life - apps_db.half_life).abs() < f32::EPSILON {
-                let errors = apps_db.rescan_desktop_entries()?;
-                (apps_db, errors)
-            } else {
-                info!(
-                    "Resetting AppsDB due to altered half life {} => {}",
-                    apps_db.half_life, config.half_life
-                );
-                Self::from_desktop_entries(config)
+        let (mut apps_db, errors) = if profile.db_exists() {
+            match Self::load(config.clone(), profile.clone()) {
+                Ok(mut apps_db) => {
+                    if (config.half_life - apps_db.half_life).abs()
+                        >= f32::EPSILON
+                    {
+                        info!(
+                            "Recomputing scores due to altered half life {} => {}",
+                            apps_db.half_life, config.half_life
+                        );
+                        apps_db.recompute_scores(config.half_life);
+                    }
+                    let errors = apps_db.rescan_desktop_entries()?;
+                    (apps_db, errors)
+                }
+                Err(err @ AppDBError::ParseDB { .. }) => {
+                    warn!("{}, rebuilding it", err);
+                    Self::rebuild_corrupted(config, profile)?
+                }
+                Err(err) => return Err(err),
             }
         } else {
-            Self::from_desktop_entries(config)
+            Self::from_desktop_entries(config, profile)
         };
         apps_db.save()?;
         Ok((apps_db, errors))
     }
 
     /// This ranks the apps both by frecency score and fuzzy search.
+    ///
+    /// The apps are scored by the ranker of the DB, see `AppsDB::set_ranker`,
+    /// and sorted by score. Apps with the same score are sorted by match
+    /// score, then by name, so the order is stable.
+    ///
+    /// The search is matched with the matcher picked in the config against
+    /// the name, generic name, keywords and comment of the apps. Matches on
+    /// fields other than the name are weighted lower.
+    ///
+    /// When searching, the desktop actions of the apps are matched too if
+    /// `search_actions` is set in the config. They are returned as apps
+    /// named "App Name: Action Name" with `App::action` set.
     pub fn get_ranked_list(
         &self,
         search: Option<&str>,
         num_items: Option<usize>,
-    ) -> Vec<App> {
-        let matcher = SkimMatcherV2::default();
-        let iter = self.apps.iter();
-        let mut app_list = match search {
-            Some(search) => iter
-                .filter_map(|app| {
-                    match matcher.fuzzy_match(&app.name, search) {
-                        Some(score) if score > 0 => {
-                            let mut app = app.clone();
-                            app.score =
-                                self.get_frecency(&app) + score as f32 / 100.;
-                            Some(app)
+    ) -> Vec<SearchResult> {
+        let matcher = self.config.matcher.build();
+        let ctx = RankContext::new(
+            search,
+            current_time_secs(),
+            self.secs_elapsed(),
+            self.half_life,
+            &self.launches,
+        );
+        let rank = |app: App, found: Option<Match>| {
+            let fuzzy_score = found.as_ref().map_or(0., |found| found.score);
+            let (match_field, match_indices) = match found {
+                Some(found) => (Some(found.field), found.indices),
+                None => (None, Vec::new()),
+            };
+            SearchResult {
+                total: self.ranker.rank(&app, fuzzy_score, &ctx),
+                frecency: ctx.frecency(&app),
+                app,
+                match_field,
+                match_indices,
+                fuzzy_score,
+            }
+        };
+        let mut results = match search {
+            Some(search) => {
+                let mut results = Vec::new();
+                for app in &self.apps {
+                    if let Some(found) = search_match(&*matcher, app, search) {
+                        results.push(rank(app.clone(), Some(found)));
+                    }
+                    if !self.config.search_actions {
+                        continue;
+                    }
+                    for action in &app.actions {
+                        let action_app = app.with_action(action);
+                        match matcher.fuzzy_indices(&action_app.name, search) {
+                            Some((score, indices)) if score > 0 => {
+                                let found = Match {
+                                    score: score as f32,
+                                    field: MatchField::Name,
+                                    indices,
+                                };
+                                results.push(rank(action_app, Some(found)));
+                            }
+                            _ => {}
                         }
-                        _ => None,
                     }
-                })
-                .collect::<Vec<App>>(),
-            None => iter.cloned().collect::<Vec<App>>(),
+                }
+                results
+            }
+            None => self
+                .apps
+                .iter()
+                .map(|app| rank(app.clone(), None))
+                .collect(),
         };
-        app_list.sort_unstable_by(|left, right| {
-            right.score.partial_cmp(&left.score).unwrap()
+        results.sort_by(|left, right| {
+            let lowercase = |app: &App| {
+                app.name
+                    .chars()
+                    .flat_map(char::to_lowercase)
+                    .collect::<String>()
+            };
+            right
+                .total
+                .total_cmp(&left.total)
+                .then_with(|| right.fuzzy_score.total_cmp(&left.fuzzy_score))
+                .then_with(|| lowercase(&left.app).cmp(&lowercase(&right.app)))
+                .then_with(|| left.app.id.cmp(&right.app.id))
+                .then_with(|| left.app.action.cmp(&right.app.action))
         });
         if let Some(n) = num_items {
-            app_list = app_list.into_iter().take(n).collect();
+            results.truncate(n);
         }
-        app_list
+        results
     }
 
-    /// Increment to score for app `to_update` by 1 launch and save to DB.
+    /// Search with the search providers enabled in the config and merge
+    /// their results by score.
+    ///
+    /// See the `search` module for how the providers are picked.
+    pub fn search(
+        &self,
+        query: &str,
+        num_items: Option<usize>,
+    ) -> Vec<SearchItem> {
+        let providers =
+            self.config.search_providers.iter().filter_map(|name| {
+                self.search_providers
+                    .iter()
+                    .find(|provider| provider.name() == name)
+                    .map(|provider| provider.as_ref())
+            });
+        let mut items = search::search(providers, query, self);
+        if let Some(n) = num_items {
+            items.truncate(n);
+        }
+        items
+    }
+
+    /// Add a search provider.
+    ///
+    /// The provider is only searched if its name is listed in the
+    /// `search_providers` setting of the config.
+    pub fn add_search_provider(&mut self, provider: Arc<dyn SearchProvider>) {
+        self.search_providers.push(provider);
+    }
+
+    /// Build the index of the apps that open each MIME type from the
+    /// desktop entries in the `app_paths` of the config.
+    ///
+    /// The entries parsed by earlier scans are reused from the scan cache.
+    /// See `MimeIndex::load`.
+    pub fn mime_index(&self) -> (MimeIndex, Vec<MimeError>) {
+        MimeIndex::load_cached(
+            &self.config.app_paths,
+            self.profile.scan_cache_path().as_deref(),
+        )
+    }
+
+    /// Find the file for the icon of `app`.
+    ///
+    /// Uses the icon theme and format preference from the config, see
+    /// `App::icon_path`. Lookups are cached until the next rescan.
+    pub fn icon_path(
+        &self,
+        app: &App,
+        size: u16,
+        scale: u16,
+    ) -> Option<PathBuf> {
+        self.icon_cache
+            .get_or_insert_with(&app.icon, size, scale, || {
+                find_icon(
+                    &app.icon,
+                    size,
+                    scale,
+                    self.config.icon_theme.as_deref(),
+                    self.config.prefer_svg_icons,
+                )
+            })
+    }
+
+    /// Increment to score for app `to_update` by 1 launch, record the launch
+    /// in the launch log and save to DB.
     pub fn update(&mut self, to_update: &App) -> Result<(), AppDBError> {
-        self.update_score(&to_update.uuid, 1.0);
-        self.sort();
+        self.record_launch(to_update, None)
+    }
+
+    /// Like `AppsDB::update`, but also record the search `app` was launched
+    /// from so rankers can learn which app a search leads to.
+    pub fn record_launch(
+        &mut self,
+        app: &App,
+        query: Option<&str>,
+    ) -> Result<(), AppDBError> {
+        self.add_launch(app, query)?;
         self.save()
     }
 
+    /// Record a launch like `AppsDB::record_launch` without saving it.
+    pub(crate) fn add_launch(
+        &mut self,
+        app: &App,
+        query: Option<&str>,
+    ) -> Result<(), AppDBError> {
+        self.update_score(&app.uuid, 1.0)?;
+        let launch = Launch {
+            uuid: app.uuid.clone(),
+            action: app.action.clone(),
+            time: current_time_secs(),
+            query: query.map(str::to_owned),
+        };
+        self.launches.push(launch.clone());
+        self.pending.push(launch);
+        self.sort();
+        Ok(())
+    }
+
+    /// Set the ranker used by `AppsDB::get_ranked_list`.
+    ///
+    /// Overrides the `ranker` setting of the config.
+    pub fn set_ranker(&mut self, ranker: Arc<dyn Ranker>) {
+        self.ranker = ranker;
+    }
+
+    /// Find the app identified by `key`.
+    ///
+    /// The desktop file ID of the key can leave out the `.desktop` suffix,
+    /// an app with the exact ID is preferred. Returns the entry for the
+    /// desktop action if the key has an action.
+    pub fn app(&self, key: &AppKey) -> Option<App> {
+        let find = |id: &str| {
+            self.apps
+                .iter()
+                .find(|app| !app.id.is_empty() && app.id == id)
+        };
+        let app =
+            find(&key.id).or_else(|| find(&format!("{}.desktop", key.id)))?;
+        match &key.action {
+            Some(action) => app.action(action),
+            None => Some(app.clone()),
+        }
+    }
+
+    /// Replace the config, ex. with a `hot_reload::Event::ConfigChanged`.
+    ///
+    /// The ranker is rebuilt from the new config, replacing one set with
+    /// `AppsDB::set_ranker`, and the scores are recomputed if the half life
+    /// changed. Apps aren't rescanned if `app_paths` changed.
+    pub fn set_config(&mut self, config: Config) {
+        if (config.half_life - self.half_life).abs() >= f32::EPSILON {
+            self.recompute_scores(config.half_life);
+        }
+        self.ranker = config.ranker.build();
+        self.config = config;
+    }
+
+    /// Get the launch history of `app`.
+    ///
+    /// Launches of the app's desktop actions count as launches of the app.
+    pub fn history(&self, app: &App) -> AppHistory {
+        AppHistory::from_log(&self.launches, &app.uuid)
+    }
+
+    /// The log of the recorded app launches, oldest first.
+    ///
+    /// Only the last `MAX_LAUNCHES` launches are kept when the DB is saved.
+    pub fn launches(&self) -> &[Launch] {
+        &self.launches
+    }
+
+    /// Recompute the scores of all apps for a new half life.
+    ///
+    /// The scores of apps are recomputed from the launch log. Apps without
+    /// launches in the log, ex. from databases created before the log was
+    /// added, keep their current frecency.
+    pub fn recompute_scores(&mut self, half_life: f32) {
+        let now = current_time_secs();
+        let elapsed = self.secs_elapsed();
+        let old_half_life = self.half_life;
+        let launches = &self.launches;
+        let recompute = |app: &mut App| {
+            let times: Vec<f64> = launches
+                .iter()
+                .filter(|launch| launch.uuid == app.uuid)
+                .map(|launch| launch.time)
+                .collect();
+            app.score = if times.is_empty() {
+                app.get_frecency(elapsed, old_half_life)
+            } else {
+                score_from_launches(times, now, half_life)
+            };
+        };
+        self.apps.iter_mut().for_each(recompute);
+        self.removed
+            .iter_mut()
+            .for_each(|removed| recompute(&mut removed.app));
+        self.reference_time = now;
+        self.half_life = half_life;
+        self.sort();
+    }
+
     /// Update self with new desktop entries.
     ///
-    /// Scan the desktop entries again then merge the new list into self with
-    /// `AppsDB.merge` then saves those changes. Returns a list of scan errors
-    /// on success and an AppDBError id saving the new DB failed. Scan errors
-    /// can generally be ignored.
+    /// Scan the desktop entries again, reusing the entries that didn't change
+    /// since the last scan, then merge the new list into self with
+    /// `AppsDB::merge_new_entries` then saves those changes. Returns a list of
+    /// scan errors on success and an AppDBError id saving the new DB failed.
+    /// Scan errors can generally be ignored.
     pub fn rescan_desktop_entries(
         &mut self,
     ) -> Result<Vec<ScanError>, AppDBError> {
-        let (apps, errors) = scan_desktop_entries(&self.config.app_paths);
+        let (apps, errors) = scan_desktop_entries(
+            &self.config.app_paths,
+            self.profile.scan_cache_path().as_deref(),
+        );
         self.merge_new_entries(apps);
         self.save()?;
         Ok(errors)
     }
+
+    /// Update self with changed desktop entries, ex. from a
+    /// `hot_reload::Event::AppsChanged`, then save those changes.
+    ///
+    /// See `AppsDB::merge_app_changes`.
+    pub fn apply_app_changes(
+        &mut self,
+        added: Vec<App>,
+        removed: &[App],
+        modified: Vec<App>,
+    ) -> Result<(), AppDBError> {
+        self.merge_app_changes(added, removed, modified);
+        self.save()
+    }
 }
 
 /// Other lower level methods that may be required sometimes
 impl AppsDB {
     /// Create a new app.
+    ///
+    /// The DB is stored in the default profile, see `AppsDB::with_profile`.
     pub fn new(config: Config, apps: Vec<App>) -> Self {
         AppsDB {
             apps,
             reference_time: current_time_secs(),
             half_life: config.half_life,
+            removed: Vec::new(),
+            launches: Vec::new(),
+            pending: Vec::new(),
+            icon_cache: IconCache::default(),
+            ranker: config.ranker.build(),
+            search_providers: search::builtin_providers(),
+            profile: Profile::default(),
             config,
         }
     }
 
-    /// Create an `AppsDB` from the desktop entries.
+    /// Store the DB in `profile`.
+    pub fn with_profile(mut self, profile: Profile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// The profile the DB is stored in.
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Create an `AppsDB` from the desktop entries, stored in `profile`.
     ///
     /// Returns and scan errors encountered while finding apps in addition
     /// to the AppsDB.
-    pub fn from_desktop_entries(config: Config) -> (AppsDB, Vec<ScanError>) {
-        let (apps, errors) = scan_desktop_entries(&config.app_paths);
-        (AppsDB::new(config, apps), errors)
+    pub fn from_desktop_entries(
+        config: Config,
+        profile: Profile,
+    ) -> (AppsDB, Vec<ScanError>) {
+        let (apps, errors) = scan_desktop_entries(
+            &config.app_paths,
+            profile.scan_cache_path().as_deref(),
+        );
+        (AppsDB::new(config, apps).with_profile(profile), errors)
     }
 
-    /// Load database file.
-    pub fn load(config: Config) -> Result<AppsDB, AppDBError> {
-        let lock = FileLock::lock(&*DB_PATH, true, false).map_err(|err| {
-            AppDBError::FileOpen {
-                file_path: DB_PATH.to_owned(),
+    /// Load the DB of `profile`.
+    ///
+    /// Files written with an older schema version are migrated.
+    pub fn load(
+        config: Config,
+        profile: Profile,
+    ) -> Result<AppsDB, AppDBError> {
+        let bytes = profile.read_db()?;
+        let contents = format::decode(&bytes).map_err(|err| match err {
+            FormatError::Decode(err) => AppDBError::ParseDB {
+                file_path: profile.db_file(),
                 err,
+            },
+            FormatError::UnsupportedVersion(version) => {
+                AppDBError::UnsupportedVersion {
+                    file_path: profile.db_file(),
+                    version,
+                }
             }
         })?;
-        let mut apps: AppsDB =
-            rmp::from_read(&lock.file).map_err(|err| AppDBError::ParseDB {
-                file_path: DB_PATH.to_owned(),
-                err,
-            })?;
-        apps.config = config;
-        Ok(apps)
+        Ok(AppsDB {
+            apps: contents.apps,
+            reference_time: contents.reference_time,
+            half_life: contents.half_life,
+            removed: contents.removed,
+            launches: contents.launches,
+            pending: Vec::new(),
+            icon_cache: IconCache::default(),
+            ranker: config.ranker.build(),
+            search_providers: search::builtin_providers(),
+            profile,
+            config,
+        })
     }
 
     /// Save database file.
-    pub fn save(&self) -> Result<(), AppDBError> {
-        let data_dir = DIRS.data_dir();
-        if !data_dir.exists() {
-            create_dir(&data_dir).map_err(|err| AppDBError::DirCreate {
-                dir_path: data_dir.to_owned(),
-                err,
-            })?;
+    ///
+    /// The DB is read again under an exclusive lock and the scores and
+    /// launches saved by other processes, ex. another launcher instance, are
+    /// merged into self with `AppsDB::merge_saved` before it is written.
+    /// The DB is written to a temporary file which then replaces the DB
+    /// file, so the DB file is never left partially written. The launch log
+    /// is compacted with `AppsDB::compact_launches` first.
+    pub fn save(&mut self) -> Result<(), AppDBError> {
+        let profile = self.profile.clone();
+        profile.update_db(|saved| self.merge_and_encode(saved))?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Save the DB shared in `apps_db` like `AppsDB::save`.
+    ///
+    /// `apps_db` is only locked while the saved DB is merged into it, not
+    /// while the DB file is read and written, so the DB can be used by
+    /// other threads in the meantime.
+    pub(crate) fn save_shared(
+        apps_db: &Mutex<AppsDB>,
+    ) -> Result<(), AppDBError> {
+        let profile = apps_db.lock().unwrap().profile.clone();
+        let mut saved_pending = Vec::new();
+        let result = profile.update_db(|saved| {
+            let mut apps_db = apps_db.lock().unwrap();
+            let buf = apps_db.merge_and_encode(saved);
+            saved_pending = std::mem::take(&mut apps_db.pending);
+            buf
+        });
+        if result.is_err() {
+            // Save the launches again with the next save
+            let mut apps_db = apps_db.lock().unwrap();
+            saved_pending.append(&mut apps_db.pending);
+            apps_db.pending = saved_pending;
         }
-        let buf = rmp::to_vec(&self).expect("Failed to encode apps db");
-        if DB_PATH.exists() {
-            let mut lock =
-                FileLock::lock(&*DB_PATH, true, true).map_err(|err| {
-                    AppDBError::FileOpen {
-                        file_path: DB_PATH.to_owned(),
-                        err,
-                    }
-                })?;
-            lock.file
-                .write_all(&buf)
-                .map_err(|err| AppDBError::FileWrite {
-                    file_path: DB_PATH.to_owned(),
-                    err,
-                })?;
-        } else {
-            let mut file = File::create(&*DB_PATH).map_err(|err| {
-                AppDBError::FileCreate {
-                    file_path: DB_PATH.to_owned(),
-                    err,
-                }
-            })?;
-            file.write_all(&buf).map_err(|err| AppDBError::FileWrite {
-                file_path: DB_PATH.to_owned(),
-                err,
-            })?;
+        result
+    }
+
+    /// Merge the DB saved by other processes, `saved` if it exists, into
+    /// self and encode self to be saved.
+    fn merge_and_encode(&mut self, saved: Option<&[u8]>) -> Vec<u8> {
+        if let Some(saved) = saved {
+            match format::decode(saved) {
+                Ok(saved) => self.merge_saved(saved),
+                Err(err) => warn!(
+                    "Overwriting apps database file {}: {}",
+                    self.profile.db_file().display(),
+                    err
+                ),
+            }
         }
+        self.compact_launches();
+        format::encode(self)
+    }
+
+    /// Replace the saved DB with self, ex. to import a DB.
+    ///
+    /// Unlike `AppsDB::save` the scores and launches saved by other
+    /// processes are discarded.
+    pub fn overwrite(&mut self) -> Result<(), AppDBError> {
+        self.compact_launches();
+        self.profile.update_db(|_| format::encode(self))?;
+        self.pending.clear();
         Ok(())
     }
 
+    /// Merge the scores and launches of a saved DB into self.
+    ///
+    /// The saved DB is taken as the base and the launches recorded by self
+    /// since it was last saved are added on top, so launches recorded by
+    /// other processes in the meantime aren't lost. Apps are matched by
+    /// uuid, apps that aren't in the saved DB keep their score.
+    fn merge_saved(&mut self, saved: format::DbContents) {
+        let saved_elapsed = (current_time_secs() - saved.reference_time) as f32;
+        let saved_apps: Vec<&App> = saved
+            .apps
+            .iter()
+            .chain(saved.removed.iter().map(|removed| &removed.app))
+            .collect();
+        let elapsed = self.secs_elapsed();
+        let half_life = self.half_life;
+        let pending = &self.pending;
+        let merge = |app: &mut App| {
+            let saved_app = match saved_apps.iter().find(|s| s.uuid == app.uuid)
+            {
+                Some(saved_app) => saved_app,
+                None => return,
+            };
+            let launches =
+                pending.iter().filter(|l| l.uuid == app.uuid).count();
+            let frecency = saved_app
+                .get_frecency(saved_elapsed, saved.half_life)
+                + launches as f32;
+            app.set_frecency(frecency, elapsed, half_life);
+        };
+        self.apps.iter_mut().for_each(merge);
+        self.removed
+            .iter_mut()
+            .for_each(|removed| merge(&mut removed.app));
+
+        let mut launches = saved.launches;
+        launches.extend(self.pending.iter().cloned());
+        launches.sort_by(|left, right| {
+            left.time
+                .partial_cmp(&right.time)
+                .unwrap_or(Ordering::Equal)
+        });
+        self.launches = launches;
+        self.sort();
+    }
+
+    /// Drop the oldest launches past `MAX_LAUNCHES` from the launch log, so
+    /// it doesn't grow forever.
+    ///
+    /// The scores aren't changed, but the dropped launches no longer count
+    /// in `AppsDB::history` or when the scores are recomputed.
+    fn compact_launches(&mut self) {
+        let excess = self.launches.len().saturating_sub(MAX_LAUNCHES);
+        self.launches.drain(..excess);
+    }
+
+    /// Build a new DB to replace a corrupted DB file.
+    ///
+    /// The corrupted file is renamed to `apps.db.corrupt-<time>` and the
+    /// scores of the apps that can be decoded from it are kept.
+    fn rebuild_corrupted(
+        config: Config,
+        profile: Profile,
+    ) -> Result<(AppsDB, Vec<ScanError>), AppDBError> {
+        let recovered = format::recover(&profile.read_db()?);
+        let time = current_time_secs() as u64;
+        if let Some(quarantine_path) = profile.quarantine_db(time)? {
+            warn!(
+                "Moved corrupted apps database to {}",
+                quarantine_path.display()
+            );
+        }
+        let (mut apps_db, errors) = Self::from_desktop_entries(config, profile);
+        apps_db.restore_recovered(recovered);
+        Ok((apps_db, errors))
+    }
+
+    /// Copy the scores and uuids of apps recovered from a corrupted DB file
+    /// to the matching apps in self.
+    fn restore_recovered(&mut self, recovered: format::Recovered) {
+        let same_half_life = recovered.half_life.is_some_and(|half_life| {
+            (half_life - self.half_life).abs() < f32::EPSILON
+        });
+        let reference_time = match recovered.reference_time {
+            Some(reference_time) if same_half_life => reference_time,
+            _ => return,
+        };
+        self.reference_time = reference_time;
+        let mut restored = 0;
+        for app in &mut self.apps {
+            // Apps saved before they had IDs can only be matched by content
+            let old = recovered
+                .apps
+                .iter()
+                .find(|old| same_key(old, app))
+                .or_else(|| {
+                    recovered
+                        .apps
+                        .iter()
+                        .find(|old| old.id.is_empty() && same_content(old, app))
+                });
+            if let Some(old) = old {
+                app.score = old.score;
+                app.uuid = old.uuid.clone();
+                restored += 1;
+            }
+        }
+        info!("Recovered the scores of {} apps", restored);
+    }
+
     /// Sort the apps database by score.
     fn sort(&mut self) {
         self.apps.sort_unstable_by(|left, right| {
@@ -232,50 +773,131 @@ impl AppsDB {
     ///
     /// Note: Does not save the DB change.
     ///
+    /// Fails if the app isn't in the DB, ex. because a rescan removed it.
+    ///
     /// # Arguments
     ///
     /// * `uuid` - The uuid of the app to update.
     /// * `weight` - The amount to update to score by.
-    pub fn update_score(&mut self, uuid: &str, weight: f32) {
+    pub fn update_score(
+        &mut self,
+        uuid: &str,
+        weight: f32,
+    ) -> Result<(), AppDBError> {
         let elapsed = self.secs_elapsed();
-        self.apps
+        let app = self
+            .apps
             .iter_mut()
             .find(|app| app.uuid == *uuid)
-            .unwrap()
-            .update_frecency(weight, elapsed, self.half_life);
+            .ok_or_else(|| AppDBError::UnknownApp {
+                uuid: uuid.to_owned(),
+            })?;
+        app.update_frecency(weight, elapsed, self.half_life);
+        Ok(())
     }
 
     /// Merge the apps from a re-scan into the database.
     ///
-    /// * Apps in `self` that are not in `apps_to_merge` will be removed from `self`
+    /// Apps are matched by their `AppKey`, or by their name and exec if the
+    /// key changed, ex. when the desktop entry was moved, or the app was
+    /// saved before apps had keys. Matched apps are updated from the new
+    /// entry but keep their score and uuid.
+    ///
+    /// * Apps in `self` that are not in `apps_to_merge` will be removed from
+    ///   `self` and remembered for a while in case they come back
     /// * Apps in `apps_to_merge` not in `self` will be added to `self`
-    pub fn merge_new_entries(&mut self, mut apps_to_merge: Vec<App>) {
-        let apps = std::mem::take(&mut self.apps);
-        self.apps = apps
-            .into_iter()
-            .filter(|app| apps_to_merge.contains(app))
-            .collect();
-        apps_to_merge = apps_to_merge
-            .into_iter()
-            .filter(|app| !self.apps.contains(app))
-            .collect();
-        self.apps.extend(apps_to_merge);
+    ///
+    /// The cached icon lookups are cleared as the icons may have changed.
+    pub fn merge_new_entries(&mut self, apps_to_merge: Vec<App>) {
+        fn take(
+            old_apps: &mut Vec<App>,
+            removed: &mut Vec<RemovedApp>,
+            new: &App,
+            matches: fn(&App, &App) -> bool,
+        ) -> Option<App> {
+            if let Some(idx) = old_apps.iter().position(|old| matches(old, new))
+            {
+                return Some(old_apps.swap_remove(idx));
+            }
+            let idx = removed.iter().position(|old| matches(&old.app, new))?;
+            Some(removed.swap_remove(idx).app)
+        }
+
+        let now = current_time_secs();
+        let mut old_apps = std::mem::take(&mut self.apps);
+        let mut removed = std::mem::take(&mut self.removed);
+        removed.retain(|old| now - old.removed_at < REMOVED_RETENTION);
+
+        let mut merged = Vec::with_capacity(apps_to_merge.len());
+        let mut unmatched = Vec::new();
+        for new in apps_to_merge {
+            match take(&mut old_apps, &mut removed, &new, same_key) {
+                Some(mut old) => {
+                    old.merge(&new);
+                    merged.push(old);
+                }
+                None => unmatched.push(new),
+            }
+        }
+        // Only fall back to matching by content once every app that kept its
+        // key has been matched
+        for new in unmatched {
+            match take(&mut old_apps, &mut removed, &new, same_content) {
+                Some(mut old) => {
+                    old.merge(&new);
+                    merged.push(old);
+                }
+                None => merged.push(new),
+            }
+        }
+
+        removed.extend(old_apps.into_iter().filter(|app| app.score > 0.).map(
+            |app| RemovedApp {
+                app,
+                removed_at: now,
+            },
+        ));
+        self.apps = merged;
+        self.removed = removed;
+        self.icon_cache.clear();
     }
 
-    /// DB file exists
-    pub fn exists() -> bool {
-        DB_PATH.exists()
+    /// Merge changed desktop entries into self.
+    ///
+    /// Apps are matched by their desktop file ID. The changes are merged
+    /// like `AppsDB::merge_new_entries` does, so modified apps keep their
+    /// score and removed apps are remembered.
+    pub fn merge_app_changes(
+        &mut self,
+        added: Vec<App>,
+        removed: &[App],
+        modified: Vec<App>,
+    ) {
+        let changed: HashSet<&str> = removed
+            .iter()
+            .chain(&modified)
+            .map(|app| app.id.as_str())
+            .collect();
+        let mut apps: Vec<App> = self
+            .apps
+            .iter()
+            .filter(|app| app.id.is_empty() || !changed.contains(&*app.id))
+            .cloned()
+            .collect();
+        apps.extend(modified);
+        apps.extend(added);
+        self.merge_new_entries(apps);
     }
 
-    /// Get app frecency
-    fn get_frecency(&self, app: &App) -> f32 {
+    /// Get the current frecency of `app`.
+    pub fn get_frecency(&self, app: &App) -> f32 {
         app.get_frecency(self.secs_elapsed(), self.half_life)
     }
 }
 
 impl App {
     /// Get app frecency
-    fn get_frecency(&self, elapsed: f32, half_life: f32) -> f32 {
+    pub(crate) fn get_frecency(&self, elapsed: f32, half_life: f32) -> f32 {
         self.score / 2.0f32.powf(elapsed / half_life)
     }
 
@@ -294,6 +916,78 @@ impl App {
     }
 }
 
+/// Weight of a fuzzy match on the name of an app.
+const NAME_WEIGHT: f32 = 1.0;
+/// Weight of a fuzzy match on the generic name of an app.
+const GENERIC_NAME_WEIGHT: f32 = 0.7;
+/// Weight of a fuzzy match on one of the keywords of an app.
+const KEYWORD_WEIGHT: f32 = 0.7;
+/// Weight of a fuzzy match on the comment of an app.
+const COMMENT_WEIGHT: f32 = 0.5;
+
+/// A match of a search on one of the fields of an app.
+struct Match {
+    /// Weighted match score.
+    score: f32,
+    field: MatchField,
+    /// Indices of the matched characters of the field.
+    indices: Vec<usize>,
+}
+
+/// Check if `old` and `new` are the same app, matched by desktop file ID and
+/// action.
+fn same_key(old: &App, new: &App) -> bool {
+    !new.id.is_empty() && old.id == new.id && old.action == new.action
+}
+
+/// Check if `old` and `new` look like the same app, for apps saved before
+/// they had desktop file IDs.
+fn same_content(old: &App, new: &App) -> bool {
+    old.name == new.name && old.exec == new.exec
+}
+
+/// Get the best weighted match of `search` across the searchable fields of
+/// `app`.
+///
+/// On equal scores the field listed first wins, the name before the others.
+fn search_match(
+    matcher: &dyn FuzzyMatcher,
+    app: &App,
+    search: &str,
+) -> Option<Match> {
+    let fields =
+        std::iter::once((MatchField::Name, app.name.as_str(), NAME_WEIGHT))
+            .chain(std::iter::once((
+                MatchField::GenericName,
+                app.generic_name.as_str(),
+                GENERIC_NAME_WEIGHT,
+            )))
+            .chain(app.keywords.iter().enumerate().map(|(i, keyword)| {
+                (MatchField::Keyword(i), keyword.as_str(), KEYWORD_WEIGHT)
+            }))
+            .chain(std::iter::once((
+                MatchField::Comment,
+                app.comment.as_str(),
+                COMMENT_WEIGHT,
+            )));
+    fields
+        .filter(|(_, text, _)| !text.is_empty())
+        .filter_map(|(field, text, weight)| {
+            match matcher.fuzzy_indices(text, search) {
+                Some((score, indices)) if score > 0 => Some(Match {
+                    score: score as f32 * weight,
+                    field,
+                    indices,
+                }),
+                _ => None,
+            }
+        })
+        .fold(None, |best: Option<Match>, found| match best {
+            Some(best) if best.score >= found.score => Some(best),
+            _ => Some(found),
+        })
+}
+
 /// Return the current time in seconds as a float
 pub fn current_time_secs() -> f64 {
     match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
@@ -330,11 +1024,58 @@ pub enum AppDBError {
         file_path: PathBuf,
         err: rmp_serde::decode::Error,
     },
+    /// DB file was written by a newer version
+    #[error("Apps database file {file_path} has unsupported schema version {version}")]
+    UnsupportedVersion { file_path: PathBuf, version: u32 },
+    /// Error moving a corrupted DB file out of the way
+    #[error("Failed to move corrupted apps database file {file_path}: {err}")]
+    Quarantine { file_path: PathBuf, err: io::Error },
+    /// App to update isn't in the DB
+    #[error("App {uuid} is not in the apps database")]
+    UnknownApp { uuid: String },
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::matcher::MatcherKind;
+    use crate::rank::{RankContext, Ranker};
+    use crate::test_util::{app, TempDir};
+    use crate::AppAction;
+    use std::fs;
+    use std::thread;
+
+    #[test]
+    fn app_by_key() {
+        let mut firefox = app("firefox.desktop", "Firefox", &["firefox"]);
+        firefox.actions.push(AppAction {
+            id: "new-window".to_owned(),
+            name: "New Window".to_owned(),
+            exec: vec!["firefox".to_owned(), "--new-window".to_owned()],
+            ..AppAction::default()
+        });
+        let plain = app("firefox", "Plain", &["firefox"]);
+        let apps_db =
+            AppsDB::new(Config::default(), vec![firefox.clone(), plain]);
+
+        let name =
+            |key: &str| apps_db.app(&AppKey::from(key)).map(|app| app.name);
+        assert_eq!(name("firefox.desktop").as_deref(), Some("Firefox"));
+        assert_eq!(name("firefox").as_deref(), Some("Plain"));
+        assert_eq!(
+            name("firefox.desktop:new-window").as_deref(),
+            Some("Firefox: New Window")
+        );
+        assert_eq!(name("firefox:new-window"), None);
+        assert_eq!(name("firefox.desktop:missing"), None);
+        assert_eq!(name("chromium"), None);
+
+        let apps_db = AppsDB::new(Config::default(), vec![firefox]);
+        let app = apps_db.app(&AppKey::from("firefox")).unwrap();
+        assert_eq!(app.id, "firefox.desktop");
+        let app = apps_db.app(&AppKey::from("firefox:new-window")).unwrap();
+        assert_eq!(app.action.as_deref(), Some("new-window"));
+    }
 
     #[test]
     fn merge_new_entries_identical() {
@@ -397,4 +1138,637 @@ mod tests {
         apps_db.merge_new_entries(apps.clone());
         assert_eq!(apps, apps_db.apps);
     }
+
+    #[test]
+    fn init_in_memory() {
+        let dir = TempDir::new("db-profile");
+        fs::write(
+            dir.join("test.desktop"),
+            "[Desktop Entry]\nName=Test\nExec=/bin/test",
+        )
+        .unwrap();
+        let config = Config {
+            app_paths: vec![dir.to_path_buf()],
+            ..Config::default()
+        };
+        let profile = Profile::in_memory();
+        let (mut apps_db, _) =
+            AppsDB::init(config.clone(), profile.clone()).unwrap();
+        let app = apps_db.apps[0].clone();
+        apps_db.update(&app).unwrap();
+
+        let (mut apps_db, _) = AppsDB::init(config, profile).unwrap();
+        assert_eq!(apps_db.apps.len(), 1);
+        assert_eq!(apps_db.apps[0].uuid, app.uuid);
+        assert!(apps_db.apps[0].score > 0.);
+        assert_eq!(apps_db.launches().len(), 1);
+
+        apps_db.launches.clear();
+        apps_db.overwrite().unwrap();
+        let apps_db =
+            AppsDB::load(Config::default(), apps_db.profile.clone()).unwrap();
+        assert!(apps_db.launches().is_empty());
+    }
+
+    #[test]
+    fn save_merges_launches() {
+        let app = App::new(
+            "Test".to_owned(),
+            "icon".to_owned(),
+            vec!["/bin/test".to_owned()],
+            false,
+        );
+        let profile = Profile::in_memory();
+        let config = Config {
+            half_life: 1e6,
+            ..Config::default()
+        };
+        let mut first = AppsDB::new(config, vec![app.clone()])
+            .with_profile(profile.clone());
+        first.save().unwrap();
+        let mut second =
+            AppsDB::load(Config::default(), profile.clone()).unwrap();
+        first.update(&app).unwrap();
+        second.update(&app).unwrap();
+        second.update(&app).unwrap();
+        assert_eq!(second.launches().len(), 3);
+
+        let apps_db = AppsDB::load(Config::default(), profile).unwrap();
+        assert_eq!(apps_db.launches().len(), 3);
+        assert!((apps_db.get_frecency(&apps_db.apps[0]) - 3.).abs() < 0.01);
+    }
+
+    /// Env var with the DB `save_from_processes` runs `record_launches` on.
+    const LAUNCHES_DB_VAR: &str = "POKI_TEST_LAUNCHES_DB";
+    /// Number of processes started by `save_from_processes`.
+    const PROCESSES: usize = 4;
+    /// Number of threads recording launches in each process.
+    const PROCESS_THREADS: usize = 2;
+    /// Number of launches recorded by each thread.
+    const THREAD_LAUNCHES: usize = 15;
+
+    /// Run in other processes by `save_from_processes`.
+    ///
+    /// Launches are recorded from several threads while another thread
+    /// keeps reading the DB.
+    #[test]
+    #[ignore]
+    fn record_launches() {
+        let db_path = PathBuf::from(
+            std::env::var_os(LAUNCHES_DB_VAR)
+                .expect("Only run by save_from_processes"),
+        );
+        let profile =
+            Profile::files(db_path.clone(), db_path.with_extension("hjson"));
+        let writers: Vec<_> = (0..PROCESS_THREADS)
+            .map(|_| {
+                let profile = profile.clone();
+                thread::spawn(move || {
+                    let mut apps_db =
+                        AppsDB::load(Config::default(), profile).unwrap();
+                    let app = apps_db.apps[0].clone();
+                    for _ in 0..THREAD_LAUNCHES {
+                        apps_db.update(&app).unwrap();
+                    }
+                })
+            })
+            .collect();
+        while !writers.iter().all(thread::JoinHandle::is_finished) {
+            AppsDB::load(Config::default(), profile.clone()).unwrap();
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn save_from_processes() {
+        let dir = TempDir::new("db-processes");
+        let db_path = dir.join("apps.db");
+        let profile =
+            Profile::files(db_path.clone(), db_path.with_extension("hjson"));
+        let app = App::new(
+            "Test".to_owned(),
+            "icon".to_owned(),
+            vec!["/bin/test".to_owned()],
+            false,
+        );
+        // The scores shouldn't decay noticeably while the test runs
+        let config = Config {
+            half_life: 1e6,
+            ..Config::default()
+        };
+        AppsDB::new(config, vec![app])
+            .with_profile(profile.clone())
+            .save()
+            .unwrap();
+
+        let children: Vec<_> = (0..PROCESSES)
+            .map(|_| {
+                std::process::Command::new(std::env::current_exe().unwrap())
+                    .args([
+                        "--ignored",
+                        "--exact",
+                        "db::tests::record_launches",
+                    ])
+                    .env(LAUNCHES_DB_VAR, &db_path)
+                    .stdout(std::process::Stdio::null())
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+        for mut child in children {
+            assert!(child.wait().unwrap().success());
+        }
+
+        let apps_db = AppsDB::load(Config::default(), profile).unwrap();
+        let launches = (PROCESSES * PROCESS_THREADS * THREAD_LAUNCHES) as f32;
+        assert_eq!(apps_db.launches().len(), launches as usize);
+        assert!(
+            (apps_db.get_frecency(&apps_db.apps[0]) - launches).abs() < 0.1
+        );
+        assert!(apps_db
+            .launches()
+            .windows(2)
+            .all(|pair| pair[0].time <= pair[1].time));
+    }
+
+    #[test]
+    fn merge_app_changes() {
+        let mut firefox = app("firefox.desktop", "Firefox", &["/bin/firefox"]);
+        firefox.score = 2.0;
+        let mut files = app("files.desktop", "Files", &["/bin/files"]);
+        files.score = 1.0;
+        let legacy = app("", "Legacy", &["/bin/legacy"]);
+        let mut apps_db = AppsDB::new(
+            Config::default(),
+            vec![firefox.clone(), files.clone(), legacy.clone()],
+        );
+        let new_firefox =
+            app("firefox.desktop", "Firefox Web Browser", &["/bin/firefox"]);
+        let term = app("term.desktop", "Terminal", &["/bin/term"]);
+        apps_db.merge_app_changes(
+            vec![term.clone()],
+            &[files.clone()],
+            vec![new_firefox.clone()],
+        );
+        assert_eq!(apps_db.apps, vec![new_firefox, legacy, term]);
+        assert_eq!(apps_db.apps[0].score, 2.0);
+        assert_eq!(apps_db.apps[0].uuid, firefox.uuid);
+        assert_eq!(apps_db.removed.len(), 1);
+        assert_eq!(apps_db.removed[0].app.uuid, files.uuid);
+    }
+
+    #[test]
+    fn merge_new_entries_renamed() {
+        let mut old = app("firefox.desktop", "Firefox", &["/bin/firefox"]);
+        old.score = 2.0;
+        let mut apps_db = AppsDB::new(Config::default(), vec![old.clone()]);
+        let mut new = app(
+            "firefox.desktop",
+            "Firefox Web Browser",
+            &["/bin/firefox-bin"],
+        );
+        new.icon = "firefox-new".to_owned();
+        apps_db.merge_new_entries(vec![new.clone()]);
+        assert_eq!(apps_db.apps, vec![new]);
+        assert_eq!(apps_db.apps[0].score, 2.0);
+        assert_eq!(apps_db.apps[0].uuid, old.uuid);
+    }
+
+    #[test]
+    fn merge_new_entries_moved() {
+        let mut old = app("firefox.desktop", "Firefox", &["/bin/firefox"]);
+        old.score = 2.0;
+        old.entry_path = "/usr/share/applications/firefox.desktop".into();
+        let mut apps_db = AppsDB::new(Config::default(), vec![old.clone()]);
+
+        // Same desktop file ID in a different directory
+        let mut new = old.clone();
+        new.entry_path = "/var/lib/flatpak/firefox.desktop".into();
+        apps_db.merge_new_entries(vec![new.clone()]);
+        assert_eq!(apps_db.apps[0].entry_path, new.entry_path);
+        assert_eq!(apps_db.apps[0].score, 2.0);
+
+        // Different desktop file ID but the same app
+        let new =
+            app("org.mozilla.firefox.desktop", "Firefox", &["/bin/firefox"]);
+        apps_db.merge_new_entries(vec![new]);
+        assert_eq!(apps_db.apps[0].id, "org.mozilla.firefox.desktop");
+        assert_eq!(apps_db.apps[0].score, 2.0);
+        assert_eq!(apps_db.apps[0].uuid, old.uuid);
+    }
+
+    #[test]
+    fn merge_new_entries_key_before_content() {
+        let mut old = app("a.desktop", "Test", &["/bin/test"]);
+        old.score = 2.0;
+        let mut apps_db = AppsDB::new(Config::default(), vec![old.clone()]);
+        // b.desktop has the same content as the old app, but a.desktop still
+        // exists so it keeps the score
+        let b = app("b.desktop", "Test", &["/bin/test"]);
+        let a = app("a.desktop", "Test", &["/bin/test"]);
+        apps_db.merge_new_entries(vec![b, a]);
+        let a = apps_db
+            .apps
+            .iter()
+            .find(|app| app.id == "a.desktop")
+            .unwrap();
+        let b = apps_db
+            .apps
+            .iter()
+            .find(|app| app.id == "b.desktop")
+            .unwrap();
+        assert_eq!(a.score, 2.0);
+        assert_eq!(b.score, 0.0);
+    }
+
+    #[test]
+    fn merge_new_entries_temporarily_removed() {
+        let mut old = app("firefox.desktop", "Firefox", &["/bin/firefox"]);
+        old.score = 2.0;
+        let other = app("kate.desktop", "Kate", &["/bin/kate"]);
+        let mut apps_db =
+            AppsDB::new(Config::default(), vec![old.clone(), other.clone()]);
+        apps_db.merge_new_entries(vec![other.clone()]);
+        assert_eq!(apps_db.apps, vec![other.clone()]);
+        // Only apps with a score are remembered
+        assert_eq!(apps_db.removed.len(), 1);
+
+        apps_db.merge_new_entries(vec![other.clone(), old.clone()]);
+        let firefox = apps_db
+            .apps
+            .iter()
+            .find(|app| app.id == "firefox.desktop")
+            .unwrap();
+        assert_eq!(firefox.score, 2.0);
+        assert_eq!(firefox.uuid, old.uuid);
+        assert!(apps_db.removed.is_empty());
+    }
+
+    #[test]
+    fn merge_new_entries_forgets_old_removals() {
+        let mut old = app("firefox.desktop", "Firefox", &["/bin/firefox"]);
+        old.score = 2.0;
+        let mut apps_db = AppsDB::new(Config::default(), vec![]);
+        apps_db.removed.push(RemovedApp {
+            app: old.clone(),
+            removed_at: current_time_secs() - REMOVED_RETENTION - 1.,
+        });
+        let new = app("firefox.desktop", "Firefox", &["/bin/firefox"]);
+        apps_db.merge_new_entries(vec![new]);
+        assert!(apps_db.removed.is_empty());
+        assert_eq!(apps_db.apps[0].score, 0.0);
+        assert_ne!(apps_db.apps[0].uuid, old.uuid);
+    }
+
+    fn launch(apps_db: &mut AppsDB, idx: usize, time: f64) {
+        let elapsed = (time - apps_db.reference_time) as f32;
+        let half_life = apps_db.half_life;
+        let app = &mut apps_db.apps[idx];
+        app.update_frecency(1.0, elapsed, half_life);
+        let uuid = app.uuid.clone();
+        apps_db.launches.push(Launch {
+            uuid,
+            action: None,
+            time,
+            query: None,
+        });
+    }
+
+    #[test]
+    fn recompute_scores() {
+        let now = current_time_secs();
+        let mut apps_db = AppsDB::new(
+            Config::default(),
+            vec![
+                app("a.desktop", "A", &["/bin/a"]),
+                app("b.desktop", "B", &["/bin/b"]),
+                app("c.desktop", "C", &["/bin/c"]),
+            ],
+        );
+        apps_db.reference_time = now - 1000.0;
+        apps_db.half_life = 100.0;
+        launch(&mut apps_db, 0, now - 500.0);
+        launch(&mut apps_db, 0, now - 100.0);
+        launch(&mut apps_db, 1, now - 10.0);
+        // An app launched before there was a launch log
+        apps_db.apps[2].score = 2.0;
+        let legacy = apps_db.get_frecency(&apps_db.apps[2]);
+
+        // Recomputing with the same half life keeps the frecencies
+        let frecencies = |apps_db: &AppsDB| -> Vec<f32> {
+            apps_db
+                .apps
+                .iter()
+                .map(|app| apps_db.get_frecency(app))
+                .collect()
+        };
+        let mut recomputed = apps_db.clone();
+        recomputed.recompute_scores(100.0);
+        recomputed.apps.sort_by(|a, b| a.name.cmp(&b.name));
+        for (old, new) in frecencies(&apps_db)
+            .into_iter()
+            .zip(frecencies(&recomputed))
+        {
+            assert!((old - new).abs() < 1e-3, "{} != {}", old, new);
+        }
+
+        // A longer half life makes the older launches count more
+        apps_db.recompute_scores(1000.0);
+        assert_eq!(apps_db.half_life, 1000.0);
+        let frecency = |name: &str| {
+            let app = apps_db.apps.iter().find(|app| app.name == name);
+            apps_db.get_frecency(app.unwrap())
+        };
+        let expected = 2.0f32.powf(-0.5) + 2.0f32.powf(-0.1);
+        assert!((frecency("A") - expected).abs() < 1e-3);
+        assert!((frecency("B") - 2.0f32.powf(-0.01)).abs() < 1e-3);
+        assert!((frecency("C") - legacy).abs() < 1e-3);
+    }
+
+    #[test]
+    fn history() {
+        let mut apps_db = AppsDB::new(
+            Config::default(),
+            vec![
+                app("a.desktop", "A", &["/bin/a"]),
+                app("b.desktop", "B", &["/bin/b"]),
+            ],
+        );
+        let now = current_time_secs();
+        launch(&mut apps_db, 0, now - 50.0);
+        launch(&mut apps_db, 0, now - 10.0);
+        let a = apps_db.apps[0].clone();
+        let b = apps_db.apps[1].clone();
+        assert_eq!(
+            apps_db.history(&a),
+            AppHistory {
+                launches: 2,
+                last_used: Some(now - 10.0),
+            }
+        );
+        assert_eq!(apps_db.history(&b), AppHistory::default());
+        assert_eq!(apps_db.launches().len(), 2);
+    }
+
+    #[test]
+    fn restore_recovered() {
+        let mut old = App::new(
+            "Test1".to_owned(),
+            "icon".to_owned(),
+            vec!["/bin/test".to_owned()],
+            false,
+        );
+        old.score = 3.0;
+        let new = vec![
+            App::new(
+                "Test1".to_owned(),
+                "icon".to_owned(),
+                vec!["/bin/test".to_owned()],
+                false,
+            ),
+            App::new(
+                "Test2".to_owned(),
+                "icon".to_owned(),
+                vec!["/bin/test".to_owned()],
+                false,
+            ),
+        ];
+        let mut apps_db = AppsDB::new(Config::default(), new);
+        apps_db.restore_recovered(format::Recovered {
+            apps: vec![old.clone()],
+            reference_time: Some(10.0),
+            half_life: Some(apps_db.half_life),
+        });
+        assert_eq!(apps_db.reference_time, 10.0);
+        assert_eq!(apps_db.apps[0].score, 3.0);
+        assert_eq!(apps_db.apps[0].uuid, old.uuid);
+        assert_eq!(apps_db.apps[1].score, 0.0);
+    }
+
+    #[test]
+    fn compact_launches() {
+        let launch = |time| Launch {
+            uuid: "uuid".to_owned(),
+            action: None,
+            time,
+            query: None,
+        };
+        let mut apps_db = AppsDB::new(Config::default(), vec![]);
+        apps_db.half_life = 10.0;
+        // Old launches are kept whatever the half life
+        apps_db.launches = vec![launch(0.0), launch(910.0), launch(1000.0)];
+        apps_db.compact_launches();
+        assert_eq!(apps_db.launches.len(), 3);
+
+        apps_db.launches =
+            (0..MAX_LAUNCHES + 5).map(|i| launch(i as f64)).collect();
+        apps_db.compact_launches();
+        assert_eq!(apps_db.launches.len(), MAX_LAUNCHES);
+        assert_eq!(apps_db.launches[0], launch(5.0));
+    }
+
+    #[test]
+    fn restore_recovered_by_id() {
+        let mut old = app("org.editor.desktop", "Editor", &["/bin/editor"]);
+        old.score = 3.0;
+        let new = vec![
+            app("editor.desktop", "Editor", &["/bin/editor"]),
+            app("org.editor.desktop", "Editor", &["/bin/editor"]),
+        ];
+        let mut apps_db = AppsDB::new(Config::default(), new);
+        apps_db.restore_recovered(format::Recovered {
+            apps: vec![old.clone()],
+            reference_time: Some(10.0),
+            half_life: Some(apps_db.half_life),
+        });
+        assert_eq!(apps_db.apps[0].score, 0.0);
+        assert_ne!(apps_db.apps[0].uuid, old.uuid);
+        assert_eq!(apps_db.apps[1].score, 3.0);
+        assert_eq!(apps_db.apps[1].uuid, old.uuid);
+    }
+
+    #[test]
+    fn ranked_list_matches_actions() {
+        let mut app = App::new(
+            "Firefox".to_owned(),
+            "firefox".to_owned(),
+            vec!["/bin/firefox".to_owned()],
+            false,
+        );
+        app.actions.push(AppAction {
+            id: "private".to_owned(),
+            name: "New Private Window".to_owned(),
+            icon: "firefox".to_owned(),
+            exec: vec!["/bin/firefox".to_owned(), "--private".to_owned()],
+            file_arg: None,
+        });
+        let mut apps_db = AppsDB::new(Config::default(), vec![app]);
+        let list = apps_db.get_ranked_list(Some("firefox private"), None);
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].app.name, "Firefox: New Private Window");
+        assert_eq!(list[0].app.action.as_deref(), Some("private"));
+
+        apps_db.config.search_actions = false;
+        let list = apps_db.get_ranked_list(Some("firefox private"), None);
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn ranked_list_matches_other_fields() {
+        let mut browser = App::new(
+            "Firefox".to_owned(),
+            "firefox".to_owned(),
+            vec!["/bin/firefox".to_owned()],
+            false,
+        );
+        browser.generic_name = "Web Browser".to_owned();
+        let mut editor = App::new(
+            "Kate".to_owned(),
+            "kate".to_owned(),
+            vec!["/bin/kate".to_owned()],
+            false,
+        );
+        editor.keywords = vec!["editor".to_owned(), "text".to_owned()];
+        let mut named = App::new(
+            "Browser".to_owned(),
+            "browser".to_owned(),
+            vec!["/bin/browser".to_owned()],
+            false,
+        );
+        named.comment = "Not an editor".to_owned();
+        let apps_db =
+            AppsDB::new(Config::default(), vec![browser, editor, named]);
+
+        let list = apps_db.get_ranked_list(Some("browser"), None);
+        let names: Vec<_> =
+            list.iter().map(|result| result.app.name.as_str()).collect();
+        assert_eq!(names, vec!["Browser", "Firefox"]);
+
+        let list = apps_db.get_ranked_list(Some("editor"), None);
+        let names: Vec<_> =
+            list.iter().map(|result| result.app.name.as_str()).collect();
+        assert_eq!(names, vec!["Kate", "Browser"]);
+    }
+
+    #[test]
+    fn ranked_list_results() {
+        let mut editor = app("kate.desktop", "Kate", &["/bin/kate"]);
+        editor.keywords = vec!["text".to_owned(), "editor".to_owned()];
+        editor.score = 2.0;
+        let config = Config {
+            half_life: 1e6,
+            ..Config::default()
+        };
+        let apps_db = AppsDB::new(config, vec![editor]);
+
+        let list = apps_db.get_ranked_list(Some("kat"), None);
+        assert_eq!(list[0].match_field, Some(MatchField::Name));
+        assert_eq!(list[0].match_indices, vec![0, 1, 2]);
+        assert_eq!(list[0].matched_text(), Some("Kate"));
+        assert!(list[0].fuzzy_score > 0.);
+        assert!((list[0].frecency - 2.0).abs() < 0.01);
+        assert_eq!(
+            list[0].total,
+            list[0].frecency + list[0].fuzzy_score / 100.
+        );
+
+        let list = apps_db.get_ranked_list(Some("edit"), None);
+        assert_eq!(list[0].match_field, Some(MatchField::Keyword(1)));
+        assert_eq!(list[0].matched_text(), Some("editor"));
+        assert_eq!(list[0].match_indices, vec![0, 1, 2, 3]);
+
+        let list = apps_db.get_ranked_list(None, None);
+        assert_eq!(list[0].match_field, None);
+        assert!(list[0].match_indices.is_empty());
+        assert_eq!(list[0].fuzzy_score, 0.);
+    }
+
+    #[test]
+    fn ranked_list_ties() {
+        let names = |apps: Vec<App>, search| -> Vec<String> {
+            let apps_db = AppsDB::new(Config::default(), apps);
+            let list = apps_db.get_ranked_list(search, None);
+            list.into_iter().map(|result| result.app.name).collect()
+        };
+        let apps = vec![
+            app("c.desktop", "cherry", &["/bin/c"]),
+            app("a.desktop", "Apple", &["/bin/a"]),
+            app("b2.desktop", "banana", &["/bin/b"]),
+            app("b1.desktop", "Banana", &["/bin/b"]),
+        ];
+        let sorted = vec!["Apple", "Banana", "banana", "cherry"];
+        assert_eq!(names(apps.clone(), None), sorted);
+        let mut reversed = apps;
+        reversed.reverse();
+        assert_eq!(names(reversed, None), sorted);
+    }
+
+    #[test]
+    fn ranked_list_uses_matcher() {
+        let apps = vec![
+            app("firefox.desktop", "Firefox", &["/bin/firefox"]),
+            app("fox.desktop", "Fox Browser", &["/bin/fox"]),
+        ];
+        let mut apps_db = AppsDB::new(Config::default(), apps);
+        let names = |apps_db: &AppsDB, search| -> Vec<String> {
+            let list = apps_db.get_ranked_list(Some(search), None);
+            list.into_iter().map(|result| result.app.name).collect()
+        };
+        assert_eq!(names(&apps_db, "ffx"), vec!["Firefox"]);
+        apps_db.config.matcher = MatcherKind::Substring;
+        assert!(names(&apps_db, "ffx").is_empty());
+        assert_eq!(names(&apps_db, "fox"), vec!["Fox Browser", "Firefox"]);
+        apps_db.config.matcher = MatcherKind::Prefix;
+        assert_eq!(names(&apps_db, "fox"), vec!["Fox Browser"]);
+        assert_eq!(names(&apps_db, "brow"), vec!["Fox Browser"]);
+    }
+
+    #[test]
+    fn ranked_list_uses_ranker() {
+        #[derive(Debug)]
+        struct Shortest;
+
+        impl Ranker for Shortest {
+            fn rank(&self, app: &App, _: f32, _: &RankContext) -> f32 {
+                -(app.name.len() as f32)
+            }
+        }
+
+        let mut apps_db = AppsDB::new(
+            Config::default(),
+            vec![
+                app("a.desktop", "Medium", &["/bin/a"]),
+                app("b.desktop", "Longest", &["/bin/b"]),
+                app("c.desktop", "Tiny", &["/bin/c"]),
+            ],
+        );
+        apps_db.apps[1].score = 10.0;
+        let names = |apps_db: &AppsDB| -> Vec<String> {
+            let list = apps_db.get_ranked_list(None, None);
+            list.into_iter().map(|result| result.app.name).collect()
+        };
+        assert_eq!(names(&apps_db)[0], "Longest");
+        apps_db.set_ranker(Arc::new(Shortest));
+        assert_eq!(names(&apps_db), vec!["Tiny", "Medium", "Longest"]);
+    }
+
+    #[test]
+    fn search_providers_from_config() {
+        let apps = vec![app("calc.desktop", "Calculator", &["/bin/calc"])];
+        let mut apps_db = AppsDB::new(Config::default(), apps);
+        let titles = |apps_db: &AppsDB, query| -> Vec<String> {
+            let items = apps_db.search(query, None);
+            items.into_iter().map(|item| item.title).collect()
+        };
+        assert_eq!(titles(&apps_db, "calc"), vec!["Calculator"]);
+        assert_eq!(titles(&apps_db, "=6*7"), vec!["42"]);
+
+        apps_db.config.search_providers = vec!["calculator".to_owned()];
+        assert!(titles(&apps_db, "calc").is_empty());
+        assert_eq!(titles(&apps_db, "=6*7"), vec!["42"]);
+        apps_db.config.search_providers = vec!["apps".to_owned()];
+        assert!(titles(&apps_db, "=6*7").is_empty());
+    }
 }
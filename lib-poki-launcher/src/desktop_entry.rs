@@ -14,8 +14,11 @@
  * You should have received a copy of the GNU General Public License
  * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
  */
-use super::App;
+use super::{App, AppAction};
+use crate::exec::{parse_exec, ExecError};
+use crate::locale::Locale;
 use freedesktop_entry_parser as fdep;
+use log::warn;
 use std::path::{Path, PathBuf};
 use std::str::ParseBoolError;
 use thiserror::Error;
@@ -61,6 +64,9 @@ pub enum EntryParseError {
         name: String,
         value: char,
     },
+    /// The 'Exec' parameter is invalid.
+    #[error("Desktop file {file_path} has an invalid 'Exec' parameter: {err}")]
+    InvalidExec { file_path: PathBuf, err: ExecError },
 }
 
 fn prop_is_true(item: Option<&str>) -> Result<bool, ParseBoolError> {
@@ -70,7 +76,30 @@ fn prop_is_true(item: Option<&str>) -> Result<bool, ParseBoolError> {
     }
 }
 
-fn unescape_string(s: &str) -> String {
+/// Get the value of `key` localized for the first matching locale in
+/// `locales`, falling back to the unlocalized value.
+fn localized_attr<'s, T: AsRef<str>>(
+    section: &'s fdep::AttrSelector<'_, T>,
+    key: &str,
+    locales: &[String],
+) -> Option<&'s str> {
+    locales
+        .iter()
+        .find_map(|locale| section.attr_with_param(key, locale))
+        .or_else(|| section.attr(key))
+}
+
+/// Split a value of type `string(s)`, ex. `Keywords`, into its items.
+fn split_list(s: &str) -> Vec<String> {
+    s.split(';')
+        .filter(|item| !item.is_empty())
+        .map(unescape_string)
+        .collect()
+}
+
+/// Unescape a value of type `string`, see the "Possible value types"
+/// section of the desktop entry spec.
+pub(crate) fn unescape_string(s: &str) -> String {
     let mut unescaped = String::with_capacity(s.len());
     let mut iter = s.chars();
     while let Some(c) = iter.next() {
@@ -94,75 +123,80 @@ fn unescape_string(s: &str) -> String {
     unescaped
 }
 
-fn parse_exec(s: &str, name: &str, icon: &str, file_path: &str) -> Vec<String> {
-    let mut output = Vec::new();
-    let mut iter = s.chars();
-    let mut in_quote = false;
-    let mut part = String::new();
-    fn push(v: &mut Vec<String>, item: String) {
-        if !item.is_empty() {
-            v.push(item);
+/// Parse the desktop actions listed in the `Actions` key of an entry.
+///
+/// Actions that are missing a `Desktop Action` section or whose section is
+/// missing the `Name` or `Exec` key are skipped.
+fn parse_actions(
+    file: &fdep::Entry,
+    path: &Path,
+    app_name: &str,
+    app_icon: &str,
+    locales: &[String],
+) -> Vec<AppAction> {
+    let ids = match file.section("Desktop Entry").attr("Actions") {
+        Some(ids) => ids,
+        None => return Vec::new(),
+    };
+    let mut actions = Vec::new();
+    for id in ids.split(';').map(str::trim).filter(|id| !id.is_empty()) {
+        let section_name = format!("Desktop Action {}", id);
+        if !file.has_section(&section_name) {
+            warn!(
+                "Desktop file {} is missing section '{}'",
+                path.display(),
+                section_name
+            );
+            continue;
         }
-    }
-    while let Some(c) = iter.next() {
-        if in_quote {
-            if c == '"' {
-                push(&mut output, part);
-                part = String::new();
-                in_quote = false;
-            } else if c == '\\' {
-                match iter.next() {
-                    Some('"') => part.push('"'),
-                    Some('`') => part.push('`'),
-                    Some('$') => part.push('$'),
-                    Some('\\') => part.push('\\'),
-                    Some(other) => {
-                        part.push('\\');
-                        part.push(other);
-                    }
-                    None => {}
-                }
-            } else {
-                part.push(c);
+        let section = file.section(&section_name);
+        let (name, exec_str) = match (
+            localized_attr(&section, "Name", locales),
+            section.attr("Exec"),
+        ) {
+            (Some(name), Some(exec)) => (unescape_string(name), exec),
+            _ => {
+                warn!(
+                    "Action {} in desktop file {} is missing the 'Name' or 'Exec' parameter",
+                    id,
+                    path.display()
+                );
+                continue;
             }
-        } else if c == '"' {
-            in_quote = true;
-        } else if c == ' ' {
-            push(&mut output, part);
-            part = String::new();
-        } else if c == '%' {
-            match iter.next() {
-                Some('%') => part.push('%'),
-                Some('i') => {
-                    if !icon.is_empty() {
-                        push(&mut output, part);
-                        output.push("--icon".to_owned());
-                        output.push(icon.to_owned());
-                        part = String::new();
-                    }
-                }
-                Some('c') => {
-                    push(&mut output, part);
-                    output.push(name.to_owned());
-                    part = String::new();
-                }
-                Some('k') => {
-                    push(&mut output, part);
-                    output.push(file_path.to_owned());
-                    part = String::new();
-                }
-                Some(_) | None => {}
+        };
+        let icon = section.attr("Icon").unwrap_or(app_icon);
+        let exec = match parse_exec(
+            exec_str,
+            app_name,
+            icon,
+            path.to_string_lossy().as_ref(),
+        ) {
+            Ok(exec) => exec,
+            Err(err) => {
+                warn!(
+                    "Action {} in desktop file {} has an invalid 'Exec' parameter: {}",
+                    id,
+                    path.display(),
+                    err
+                );
+                continue;
             }
-        } else {
-            part.push(c);
-        }
+        };
+        actions.push(AppAction {
+            id: id.to_owned(),
+            name,
+            icon: icon.to_owned(),
+            exec: exec.args,
+            file_arg: exec.file_arg,
+        });
     }
-    push(&mut output, part);
-    output
+    actions
 }
 
 /// Parse a desktop entry
 ///
+/// Localized values are picked for the locale set in the environment.
+///
 /// # Arguments
 ///
 /// * `path` - Path to the desktop entry
@@ -191,9 +225,88 @@ fn parse_exec(s: &str, name: &str, icon: &str, file_path: &str) -> Vec<String> {
 /// ```
 pub fn parse_desktop_file(
     path: impl AsRef<Path>,
+) -> Result<Option<App>, EntryParseError> {
+    parse_desktop_file_with_locale(path, Locale::from_env().as_ref())
+}
+
+/// Parse a desktop entry picking localized values for `locale`.
+///
+/// See `parse_desktop_file`.
+pub fn parse_desktop_file_with_locale(
+    path: impl AsRef<Path>,
+    locale: Option<&Locale>,
 ) -> Result<Option<App>, EntryParseError> {
     let path = path.as_ref();
-    let file = fdep::parse_entry(&path).map_err(|err| {
+    let (file, flags) = read_entry(path)?;
+    if flags.no_display || flags.hidden {
+        return Ok(None);
+    }
+    parse_app(&file, path, locale).map(Some)
+}
+
+/// Parse a desktop entry including the ones with `NoDisplay` set, which
+/// aren't listed but can still be used, ex. to open files, and return its
+/// flags too.
+///
+/// The app is `None` if the entry is `Hidden`, which means it was deleted.
+pub(crate) fn parse_entry_with_flags(
+    path: &Path,
+    locale: Option<&Locale>,
+) -> Result<(Option<App>, Flags), EntryParseError> {
+    let (file, flags) = read_entry(path)?;
+    let app = if flags.hidden {
+        None
+    } else {
+        Some(parse_app(&file, path, locale)?)
+    };
+    Ok((app, flags))
+}
+
+/// Parse an autostart entry like `parse_entry_with_flags` and return
+/// whether it is enabled.
+///
+/// An entry is disabled by `X-GNOME-Autostart-enabled` set to `false` or
+/// `0`. Invalid values are logged and leave the entry enabled.
+pub(crate) fn parse_autostart_entry(
+    path: &Path,
+    locale: Option<&Locale>,
+) -> Result<(Option<App>, bool), EntryParseError> {
+    let (file, flags) = read_entry(path)?;
+    let app = if flags.hidden {
+        None
+    } else {
+        Some(parse_app(&file, path, locale)?)
+    };
+    let enabled = match file
+        .section("Desktop Entry")
+        .attr("X-GNOME-Autostart-enabled")
+    {
+        None | Some("true") | Some("1") => true,
+        Some("false") | Some("0") => false,
+        Some(value) => {
+            warn!(
+                "Invalid X-GNOME-Autostart-enabled value `{}` in {}",
+                value,
+                path.display()
+            );
+            true
+        }
+    };
+    Ok((app, enabled))
+}
+
+/// The keys of a desktop entry that decide where it's used.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Flags {
+    /// `NoDisplay`, the entry isn't listed.
+    pub no_display: bool,
+    /// `Hidden`, the entry was deleted.
+    pub hidden: bool,
+}
+
+/// Read the desktop entry at `path` and its flags.
+fn read_entry(path: &Path) -> Result<(fdep::Entry, Flags), EntryParseError> {
+    let file = fdep::parse_entry(path).map_err(|err| {
         EntryParseError::InvalidDesktopFile {
             file_path: path.to_owned(),
             err,
@@ -205,14 +318,13 @@ pub fn parse_desktop_file(
         });
     }
     let section = file.section("Desktop Entry");
-    let not_display =
-        prop_is_true(section.attr("NoDisplay")).map_err(|_| {
-            EntryParseError::InvalidPropVal {
-                file_path: path.to_owned(),
-                name: "NoDisplay".into(),
-                value: section.attr("NoDisplay").unwrap().to_owned(),
-            }
-        })?;
+    let no_display = prop_is_true(section.attr("NoDisplay")).map_err(|_| {
+        EntryParseError::InvalidPropVal {
+            file_path: path.to_owned(),
+            name: "NoDisplay".into(),
+            value: section.attr("NoDisplay").unwrap().to_owned(),
+        }
+    })?;
     let hidden = prop_is_true(section.attr("Hidden")).map_err(|_| {
         EntryParseError::InvalidPropVal {
             file_path: path.to_owned(),
@@ -220,21 +332,34 @@ pub fn parse_desktop_file(
             value: section.attr("Hidden").unwrap().to_owned(),
         }
     })?;
-    if not_display || hidden {
-        return Ok(None);
-    }
-    let name = unescape_string(section.attr("Name").ok_or(
-        EntryParseError::MissingName {
-            file_path: path.to_owned(),
-        },
-    )?);
+    Ok((file, Flags { no_display, hidden }))
+}
+
+/// Build the app of the desktop entry `file` read from `path`.
+fn parse_app(
+    file: &fdep::Entry,
+    path: &Path,
+    locale: Option<&Locale>,
+) -> Result<App, EntryParseError> {
+    let locales = locale.map(Locale::candidates).unwrap_or_default();
+    let section = file.section("Desktop Entry");
+    let name =
+        unescape_string(localized_attr(&section, "Name", &locales).ok_or(
+            EntryParseError::MissingName {
+                file_path: path.to_owned(),
+            },
+        )?);
     let icon = section.attr("Icon").unwrap_or("");
     let exec_str =
         section.attr("Exec").ok_or(EntryParseError::MissingExec {
             file_path: path.to_owned(),
         })?;
     let exec =
-        parse_exec(exec_str, &name, icon, path.to_string_lossy().as_ref());
+        parse_exec(exec_str, &name, icon, path.to_string_lossy().as_ref())
+            .map_err(|err| EntryParseError::InvalidExec {
+                file_path: path.to_owned(),
+                err,
+            })?;
     let terminal = {
         if let Some(value) = section.attr("Terminal") {
             value.parse().map_err(|_| EntryParseError::InvalidPropVal {
@@ -246,12 +371,50 @@ pub fn parse_desktop_file(
             false
         }
     };
-    Ok(Some(App::new(
-        name,
-        icon.to_owned(),
-        exec,
-        terminal,
-    )))
+    let actions = parse_actions(file, path, &name, icon, &locales);
+    let mut app = App::new(name, icon.to_owned(), exec.args, terminal);
+    app.actions = actions;
+    app.file_arg = exec.file_arg;
+    app.generic_name = localized_attr(&section, "GenericName", &locales)
+        .map(unescape_string)
+        .unwrap_or_default();
+    app.comment = localized_attr(&section, "Comment", &locales)
+        .map(unescape_string)
+        .unwrap_or_default();
+    app.keywords = localized_attr(&section, "Keywords", &locales)
+        .map(split_list)
+        .unwrap_or_default();
+    app.entry_path = path.to_owned();
+    app.id = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    app.only_show_in = section
+        .attr("OnlyShowIn")
+        .map(split_list)
+        .unwrap_or_default();
+    app.not_show_in = section
+        .attr("NotShowIn")
+        .map(split_list)
+        .unwrap_or_default();
+    app.try_exec = section
+        .attr("TryExec")
+        .map(unescape_string)
+        .filter(|try_exec| !try_exec.is_empty());
+    app.working_dir = section
+        .attr("Path")
+        .map(unescape_string)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from);
+    app.dbus_activatable = prop_is_true(section.attr("DBusActivatable"))
+        .map_err(|_| EntryParseError::InvalidPropVal {
+            file_path: path.to_owned(),
+            name: "DBusActivatable".into(),
+            value: section.attr("DBusActivatable").unwrap().to_owned(),
+        })?;
+    app.mime_types =
+        section.attr("MimeType").map(split_list).unwrap_or_default();
+    Ok(app)
 }
 
 #[cfg(test)]
@@ -262,83 +425,8 @@ mod test {
         v.iter().map(|s| (*s).to_owned()).collect()
     }
 
-    mod parse_exec {
-        use super::*;
-
-        #[test]
-        fn basic() {
-            let exec = "/usr/bin/cat --flag";
-            let expected = ovec(&["/usr/bin/cat", "--flag"]);
-            assert_eq!(
-                parse_exec(
-                    &unescape_string(exec),
-                    "cat",
-                    "cat",
-                    "/cat.desktop"
-                ),
-                expected
-            );
-        }
-
-        #[test]
-        fn quoted() {
-            let exec = "\"/usr/bin/cat\" --flag";
-            let expected = ovec(&["/usr/bin/cat", "--flag"]);
-            assert_eq!(
-                parse_exec(
-                    &unescape_string(exec),
-                    "cat",
-                    "cat",
-                    "/cat.desktop"
-                ),
-                expected
-            );
-        }
-
-        #[test]
-        fn args() {
-            let exec = "\"/usr/bin/cat\" --flag %k %i %c %f %%";
-            let expected = ovec(&[
-                "/usr/bin/cat",
-                "--flag",
-                "/cat.desktop",
-                "--icon",
-                "cat",
-                "cat",
-                "%",
-            ]);
-            assert_eq!(
-                parse_exec(
-                    &unescape_string(exec),
-                    "cat",
-                    "cat",
-                    "/cat.desktop"
-                ),
-                expected
-            );
-        }
-
-        #[test]
-        fn complex() {
-            let exec = r#""/usr/bin folder/cat" --flag "a very weird \\\\ \" string \\$ <>`" "#;
-
-            let first_pass = r#""/usr/bin folder/cat" --flag "a very weird \\ \" string \$ <>`" "#;
-            assert_eq!(unescape_string(exec), first_pass);
-            let exec = unescape_string(exec);
-            let expected = ovec(&[
-                "/usr/bin folder/cat",
-                "--flag",
-                r#"a very weird \ " string $ <>`"#,
-            ]);
-            assert_eq!(
-                parse_exec(&exec, "cat", "cat", "/cat icon.png"),
-                expected
-            );
-        }
-    }
-
     mod parse_desktop_file {
-        use crate::App;
+        use crate::{App, FileArg, FileArgKind};
         use std::env::temp_dir;
         use std::fs::{remove_file, File};
         use std::io::prelude::*;
@@ -397,5 +485,172 @@ mod test {
             assert_eq!(app, other_app);
             remove_file(&path).unwrap();
         }
+
+        #[test]
+        fn file_with_actions() {
+            let path = temp_dir().join("./test3.desktop");
+            let mut file = File::create(&path).unwrap();
+            file.write_all(
+                b"[Desktop Entry]
+Name=Test
+Icon=testicon
+Exec=/usr/bin/test %u
+Actions=new-window;private;broken;missing;
+
+[Desktop Action new-window]
+Name=New Window
+Exec=/usr/bin/test --new-window %u
+
+[Desktop Action private]
+Name=New Private Window
+Icon=privateicon
+Exec=/usr/bin/test --private-window %u
+
+[Desktop Action broken]
+Name=No Exec",
+            )
+            .unwrap();
+            let app = parse_desktop_file(&path).unwrap().unwrap();
+            assert_eq!(
+                app.actions,
+                vec![
+                    AppAction {
+                        id: "new-window".to_owned(),
+                        name: "New Window".to_owned(),
+                        icon: "testicon".to_owned(),
+                        exec: ovec(&["/usr/bin/test", "--new-window"]),
+                        file_arg: Some(FileArg {
+                            index: 2,
+                            kind: FileArgKind::Url,
+                        }),
+                    },
+                    AppAction {
+                        id: "private".to_owned(),
+                        name: "New Private Window".to_owned(),
+                        icon: "privateicon".to_owned(),
+                        exec: ovec(&["/usr/bin/test", "--private-window"]),
+                        file_arg: Some(FileArg {
+                            index: 2,
+                            kind: FileArgKind::Url,
+                        }),
+                    },
+                ]
+            );
+            let private = app.action("private").unwrap();
+            assert_eq!(private.name, "Test: New Private Window");
+            assert_eq!(private.action.as_deref(), Some("private"));
+            assert_eq!(private.uuid, app.uuid);
+            assert_eq!(private.file_arg, app.actions[1].file_arg);
+            assert!(app.action("broken").is_none());
+            remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn localized_file() {
+            let path = temp_dir().join("./test4.desktop");
+            let mut file = File::create(&path).unwrap();
+            file.write_all(
+                b"[Desktop Entry]
+Name=Files
+Name[de]=Dateien
+Name[de_CH]=Dateie
+GenericName=File Manager
+GenericName[de]=Dateiverwaltung
+Comment=Access and organize files
+Keywords=folder;manager;explore;
+Keywords[de]=Ordner;Verwaltung;
+Icon=files
+Exec=/usr/bin/files",
+            )
+            .unwrap();
+            let locale = Locale::parse("de_AT.UTF-8").unwrap();
+            let app = parse_desktop_file_with_locale(&path, Some(&locale))
+                .unwrap()
+                .unwrap();
+            assert_eq!(app.name, "Dateien");
+            assert_eq!(app.generic_name, "Dateiverwaltung");
+            assert_eq!(app.comment, "Access and organize files");
+            assert_eq!(app.keywords, ovec(&["Ordner", "Verwaltung"]));
+
+            let locale = Locale::parse("de_CH").unwrap();
+            let app = parse_desktop_file_with_locale(&path, Some(&locale))
+                .unwrap()
+                .unwrap();
+            assert_eq!(app.name, "Dateie");
+
+            let app = parse_desktop_file_with_locale(&path, None)
+                .unwrap()
+                .unwrap();
+            assert_eq!(app.name, "Files");
+            assert_eq!(app.generic_name, "File Manager");
+            assert_eq!(app.keywords, ovec(&["folder", "manager", "explore"]));
+            remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn working_dir_and_dbus() {
+            let path = temp_dir().join("./test5.desktop");
+            let mut file = File::create(&path).unwrap();
+            file.write_all(
+                b"[Desktop Entry]
+Name=Test
+Exec=/usr/bin/test
+Path=/opt/test\\sdir
+DBusActivatable=true",
+            )
+            .unwrap();
+            let app = parse_desktop_file(&path).unwrap().unwrap();
+            assert_eq!(app.working_dir, Some(PathBuf::from("/opt/test dir")));
+            assert!(app.dbus_activatable);
+            remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn mime_types_and_no_display() {
+            let path = temp_dir().join("./test6.desktop");
+            let mut file = File::create(&path).unwrap();
+            file.write_all(
+                b"[Desktop Entry]
+Name=Viewer
+Exec=/usr/bin/viewer %f
+NoDisplay=true
+MimeType=image/png;image/jpeg;",
+            )
+            .unwrap();
+            assert!(parse_desktop_file(&path).unwrap().is_none());
+            let (app, flags) = parse_entry_with_flags(&path, None).unwrap();
+            let app = app.unwrap();
+            assert_eq!(app.mime_types, ovec(&["image/png", "image/jpeg"]));
+            assert!(flags.no_display);
+
+            let mut file = File::create(&path).unwrap();
+            file.write_all(b"[Desktop Entry]\nHidden=true").unwrap();
+            assert!(parse_entry_with_flags(&path, None).unwrap().0.is_none());
+            remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn autostart_enabled() {
+            let path = temp_dir().join("./test7.desktop");
+            let enabled = |value: &str| {
+                let mut file = File::create(&path).unwrap();
+                write!(
+                    file,
+                    "[Desktop Entry]\nName=Applet\nExec=applet\n\
+                     X-GNOME-Autostart-enabled={}",
+                    value
+                )
+                .unwrap();
+                // The key only matters to autostart
+                assert!(parse_desktop_file(&path).unwrap().is_some());
+                parse_autostart_entry(&path, None).unwrap().1
+            };
+            assert!(enabled("true"));
+            assert!(enabled("1"));
+            assert!(!enabled("false"));
+            assert!(!enabled("0"));
+            assert!(enabled("yes"));
+            remove_file(&path).unwrap();
+        }
     }
 }
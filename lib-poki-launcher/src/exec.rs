@@ -0,0 +1,442 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Parse the `Exec` key of desktop entries.
+//!
+//! See the "The Exec key" section of the desktop entry spec. The value is
+//! unescaped twice: first the escapes of string values (`\s`, `\n`, `\t`,
+//! `\r` and `\\`), then the quoting of the arguments.
+use crate::desktop_entry::unescape_string;
+use crate::{FileArg, FileArgKind};
+use thiserror::Error;
+
+/// Characters that have to be quoted in an argument.
+///
+/// The spec also reserves whitespace, which separates arguments, and `"`
+/// and `\`, which quote and escape.
+const RESERVED: &[char] = &[
+    '\'', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(', ')', '`',
+];
+
+/// Field codes that are deprecated and are removed from the command line.
+const DEPRECATED_CODES: &[char] = &['d', 'D', 'n', 'N', 'v', 'm'];
+
+/// Error from parsing an `Exec` key.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ExecError {
+    /// The command line has no arguments.
+    #[error("Exec is empty")]
+    Empty,
+    /// A quoted argument isn't closed.
+    #[error("Exec has an unterminated quote")]
+    UnterminatedQuote,
+    /// The command line ends with a backslash.
+    #[error("Exec ends with a backslash")]
+    TrailingBackslash,
+    /// A reserved character is used outside of quotes.
+    #[error("Exec has the reserved character '{0}' outside of quotes")]
+    ReservedChar(char),
+    /// The command line ends with a `%`.
+    #[error("Exec ends with an incomplete field code")]
+    IncompleteFieldCode,
+    /// An unknown field code is used.
+    #[error("Exec has the unknown field code '%{0}'")]
+    UnknownFieldCode(char),
+    /// A field code that has to be an argument on its own isn't.
+    #[error("Exec field code '%{0}' has to be an argument on its own")]
+    FieldCodeNotAlone(char),
+    /// More than one of `%f`, `%F`, `%u` and `%U` is used.
+    #[error("Exec has more than one file field code")]
+    MultipleFileCodes,
+}
+
+/// A parsed `Exec` key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedExec {
+    /// The program and its arguments, with the field codes expanded except
+    /// for the file field codes.
+    pub args: Vec<String>,
+    /// Where the file field code was, if any.
+    pub file_arg: Option<FileArg>,
+}
+
+/// Parse the value of an `Exec` key.
+///
+/// # Arguments
+///
+/// * `exec` - The value of the key as it's written in the desktop file.
+/// * `name` - The name of the app, for `%c`.
+/// * `icon` - The icon of the app, for `%i`.
+/// * `file_path` - The path to the desktop file, for `%k`.
+///
+/// Arguments are separated by unquoted whitespace. Inside double quotes
+/// `\"`, `` \` ``, `\$` and `\\` are unescaped and field codes aren't
+/// expanded. Outside of quotes a backslash escapes the next character, which
+/// the spec doesn't allow but is used by the entries Wine creates.
+pub fn parse_exec(
+    exec: &str,
+    name: &str,
+    icon: &str,
+    file_path: &str,
+) -> Result<ParsedExec, ExecError> {
+    let exec = unescape_string(exec);
+    let is_separator = |c: &char| matches!(c, ' ' | '\t' | '\n');
+    let mut args = Vec::new();
+    let mut file_arg = None;
+    let mut word = String::new();
+    // Set once something, even an empty quoted string, is in the word
+    let mut in_word = false;
+    let mut chars = exec.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if is_separator(&c) => {
+                if in_word {
+                    args.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '`' | '$' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(ExecError::UnterminatedQuote),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(ExecError::UnterminatedQuote),
+                    }
+                }
+            }
+            '\\' => {
+                word.push(chars.next().ok_or(ExecError::TrailingBackslash)?);
+                in_word = true;
+            }
+            '%' => {
+                let code =
+                    chars.next().ok_or(ExecError::IncompleteFieldCode)?;
+                let alone = !in_word && chars.peek().is_none_or(is_separator);
+                match code {
+                    '%' => {
+                        word.push('%');
+                        in_word = true;
+                    }
+                    'f' | 'F' | 'u' | 'U' => {
+                        if file_arg.is_some() {
+                            return Err(ExecError::MultipleFileCodes);
+                        }
+                        if !alone {
+                            return Err(ExecError::FieldCodeNotAlone(code));
+                        }
+                        file_arg = Some(FileArg {
+                            index: args.len(),
+                            kind: match code {
+                                'f' => FileArgKind::File,
+                                'F' => FileArgKind::Files,
+                                'u' => FileArgKind::Url,
+                                _ => FileArgKind::Urls,
+                            },
+                        });
+                    }
+                    'i' => {
+                        if !alone {
+                            return Err(ExecError::FieldCodeNotAlone(code));
+                        }
+                        if !icon.is_empty() {
+                            args.push("--icon".to_owned());
+                            args.push(icon.to_owned());
+                        }
+                    }
+                    'c' => {
+                        word.push_str(name);
+                        in_word = true;
+                    }
+                    'k' => {
+                        word.push_str(file_path);
+                        in_word = true;
+                    }
+                    code if DEPRECATED_CODES.contains(&code) => {}
+                    code => return Err(ExecError::UnknownFieldCode(code)),
+                }
+            }
+            c if RESERVED.contains(&c) => {
+                return Err(ExecError::ReservedChar(c))
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        args.push(word);
+    }
+    if args.is_empty() {
+        return Err(ExecError::Empty);
+    }
+    Ok(ParsedExec { args, file_arg })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(exec: &str) -> Result<ParsedExec, ExecError> {
+        parse_exec(exec, "Cat", "cat", "/cat.desktop")
+    }
+
+    fn args(exec: &str) -> Vec<String> {
+        parse(exec).unwrap().args
+    }
+
+    fn file_arg(index: usize, kind: FileArgKind) -> Option<FileArg> {
+        Some(FileArg { index, kind })
+    }
+
+    mod spec {
+        use super::*;
+
+        #[test]
+        fn basic() {
+            assert_eq!(args("/usr/bin/cat --flag"), ["/usr/bin/cat", "--flag"]);
+        }
+
+        #[test]
+        fn whitespace() {
+            assert_eq!(
+                args("cat\t--flag  -x\\n-y"),
+                ["cat", "--flag", "-x", "-y"]
+            );
+            assert_eq!(args("cat\\s--flag"), ["cat", "--flag"]);
+        }
+
+        #[test]
+        fn quoted() {
+            assert_eq!(
+                args(r#""/usr/bin folder/cat" --flag "" "a;b""#),
+                ["/usr/bin folder/cat", "--flag", "", "a;b"]
+            );
+        }
+
+        #[test]
+        fn quoted_escapes() {
+            // The value as written in the file, escaped twice
+            let exec = r#""/usr/bin folder/cat" --flag "a very weird \\\\ \" string \\$ <>`""#;
+            assert_eq!(
+                args(exec),
+                [
+                    "/usr/bin folder/cat",
+                    "--flag",
+                    r#"a very weird \ " string $ <>`"#,
+                ]
+            );
+        }
+
+        #[test]
+        fn field_codes() {
+            let exec = "\"/usr/bin/cat\" --flag %k %i %c --name=%c %f %%";
+            let parsed = parse(exec).unwrap();
+            assert_eq!(
+                parsed.args,
+                [
+                    "/usr/bin/cat",
+                    "--flag",
+                    "/cat.desktop",
+                    "--icon",
+                    "cat",
+                    "Cat",
+                    "--name=Cat",
+                    "%",
+                ]
+            );
+            assert_eq!(parsed.file_arg, file_arg(7, FileArgKind::File));
+            assert_eq!(
+                parse_exec("cat %i", "Cat", "", "").unwrap().args,
+                ["cat"]
+            );
+        }
+
+        #[test]
+        fn file_codes() {
+            assert_eq!(
+                parse("cat %F").unwrap().file_arg,
+                file_arg(1, FileArgKind::Files)
+            );
+            assert_eq!(
+                parse("cat %u -x").unwrap().file_arg,
+                file_arg(1, FileArgKind::Url)
+            );
+            assert_eq!(
+                parse("cat %U").unwrap().file_arg,
+                file_arg(1, FileArgKind::Urls)
+            );
+            assert_eq!(parse("cat").unwrap().file_arg, None);
+        }
+
+        #[test]
+        fn field_codes_not_expanded_in_quotes() {
+            assert_eq!(args(r#"sh -c "cat %f""#), ["sh", "-c", "cat %f"]);
+        }
+
+        #[test]
+        fn deprecated_codes() {
+            assert_eq!(args("cat %d %D %n %N %v %m -x"), ["cat", "-x"]);
+        }
+
+        #[test]
+        fn errors() {
+            assert_eq!(parse(""), Err(ExecError::Empty));
+            assert_eq!(parse(" %d "), Err(ExecError::Empty));
+            assert_eq!(parse("\"cat"), Err(ExecError::UnterminatedQuote));
+            assert_eq!(parse("cat \\\\"), Err(ExecError::TrailingBackslash));
+            assert_eq!(parse("cat %"), Err(ExecError::IncompleteFieldCode));
+            assert_eq!(parse("cat %x"), Err(ExecError::UnknownFieldCode('x')));
+            assert_eq!(
+                parse("cat --file=%f"),
+                Err(ExecError::FieldCodeNotAlone('f'))
+            );
+            assert_eq!(
+                parse("cat %U%i"),
+                Err(ExecError::FieldCodeNotAlone('U'))
+            );
+            assert_eq!(parse("cat %f %U"), Err(ExecError::MultipleFileCodes));
+            for c in RESERVED {
+                assert_eq!(
+                    parse(&format!("cat a{}b", c)),
+                    Err(ExecError::ReservedChar(*c))
+                );
+                assert!(parse(&format!("cat \"a{}b\"", c)).is_ok());
+            }
+        }
+    }
+
+    mod real_world {
+        use super::*;
+
+        #[test]
+        fn flatpak() {
+            let parsed = parse(
+                "/usr/bin/flatpak run --branch=stable --arch=x86_64 \
+                 --command=firefox --file-forwarding org.mozilla.firefox \
+                 @@u %u @@",
+            )
+            .unwrap();
+            assert_eq!(
+                parsed.args,
+                [
+                    "/usr/bin/flatpak",
+                    "run",
+                    "--branch=stable",
+                    "--arch=x86_64",
+                    "--command=firefox",
+                    "--file-forwarding",
+                    "org.mozilla.firefox",
+                    "@@u",
+                    "@@",
+                ]
+            );
+            assert_eq!(parsed.file_arg, file_arg(8, FileArgKind::Url));
+        }
+
+        #[test]
+        fn snap() {
+            let parsed = parse(
+                "env BAMF_DESKTOP_FILE_HINT=/var/lib/snapd/desktop/applications/\
+                 firefox_firefox.desktop /snap/bin/firefox %u",
+            )
+            .unwrap();
+            assert_eq!(
+                parsed.args,
+                [
+                    "env",
+                    "BAMF_DESKTOP_FILE_HINT=/var/lib/snapd/desktop/\
+                     applications/firefox_firefox.desktop",
+                    "/snap/bin/firefox",
+                ]
+            );
+            assert_eq!(parsed.file_arg, file_arg(3, FileArgKind::Url));
+        }
+
+        #[test]
+        fn steam() {
+            assert_eq!(
+                args("steam steam://rungameid/570"),
+                ["steam", "steam://rungameid/570"]
+            );
+        }
+
+        #[test]
+        fn wine() {
+            let exec = r#"env WINEPREFIX="/home/user/.wine" wine C:\\\\windows\\\\command\\\\start.exe /Unix /home/user/.wine/dosdevices/c:/ProgramData/Microsoft/Windows/Start\\ Menu/Programs/Notepad++/Notepad++.lnk"#;
+            assert_eq!(
+                args(exec),
+                [
+                    "env",
+                    "WINEPREFIX=/home/user/.wine",
+                    "wine",
+                    r"C:\windows\command\start.exe",
+                    "/Unix",
+                    "/home/user/.wine/dosdevices/c:/ProgramData/Microsoft/\
+                     Windows/Start Menu/Programs/Notepad++/Notepad++.lnk",
+                ]
+            );
+        }
+
+        #[test]
+        fn electron() {
+            let parsed =
+                parse("/opt/Signal/signal-desktop --no-sandbox %U").unwrap();
+            assert_eq!(
+                parsed.args,
+                ["/opt/Signal/signal-desktop", "--no-sandbox"]
+            );
+            assert_eq!(parsed.file_arg, file_arg(2, FileArgKind::Urls));
+        }
+
+        #[test]
+        fn gnome() {
+            let parsed =
+                parse("gapplication launch org.gnome.Nautilus %U").unwrap();
+            assert_eq!(
+                parsed.args,
+                ["gapplication", "launch", "org.gnome.Nautilus"]
+            );
+        }
+
+        #[test]
+        fn kde() {
+            let parsed =
+                parse("konsole -qwindowtitle %c --icon utilities-terminal %i");
+            assert_eq!(
+                parsed.unwrap().args,
+                [
+                    "konsole",
+                    "-qwindowtitle",
+                    "Cat",
+                    "--icon",
+                    "utilities-terminal",
+                    "--icon",
+                    "cat",
+                ]
+            );
+        }
+    }
+}
@@ -0,0 +1,430 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! The apps database file format.
+//!
+//! A database file starts with `MAGIC` followed by the schema version as a
+//! little endian `u32` and the msgpack encoded contents. Files written before
+//! the header was introduced have no header and are treated as version 0.
+use crate::db::{AppsDB, RemovedApp};
+use crate::history::Launch;
+use crate::App;
+use rmp_serde as rmp;
+use serde::de::{Deserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fmt;
+use thiserror::Error;
+
+/// Bytes every database file with a header starts with.
+const MAGIC: &[u8; 6] = b"POKIDB";
+/// Version of the schema written by `encode`.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// The contents of an apps database file.
+#[derive(Debug, Clone)]
+pub struct DbContents {
+    /// The list of apps.
+    pub apps: Vec<App>,
+    /// The reference time used in the ranking calculations.
+    pub reference_time: f64,
+    /// The half life of the app launches.
+    pub half_life: f32,
+    /// Apps that disappeared in a recent rescan.
+    pub removed: Vec<RemovedApp>,
+    /// The launch log.
+    pub launches: Vec<Launch>,
+}
+
+/// Error decoding an apps database file.
+#[derive(Debug, Error)]
+pub enum FormatError {
+    /// The file was written by a newer version of Poki Launcher.
+    #[error("Unsupported apps database schema version {0}")]
+    UnsupportedVersion(u32),
+    /// The contents of the file couldn't be decoded.
+    #[error("{0}")]
+    Decode(#[from] rmp::decode::Error),
+}
+
+/// Version 0, the `AppsDB` struct as it was serialized before versioning.
+#[derive(Deserialize)]
+struct SchemaV0 {
+    apps: Vec<App>,
+    reference_time: f64,
+    half_life: f32,
+}
+
+/// Version 1, the header fields are stored before the apps so they can
+/// still be recovered if the apps are corrupted.
+#[derive(Deserialize)]
+struct SchemaV1 {
+    reference_time: f64,
+    half_life: f32,
+    apps: Vec<App>,
+}
+
+/// Version 2, adds the apps removed in recent rescans.
+#[derive(Deserialize)]
+struct SchemaV2 {
+    reference_time: f64,
+    half_life: f32,
+    apps: Vec<App>,
+    removed: Vec<RemovedApp>,
+}
+
+/// Version 3, adds the launch log.
+#[derive(Serialize, Deserialize)]
+struct SchemaV3<A, R, L> {
+    reference_time: f64,
+    half_life: f32,
+    apps: A,
+    removed: R,
+    launches: L,
+}
+
+/// Split the schema version from the contents of a database file.
+fn split_header(bytes: &[u8]) -> (u32, &[u8]) {
+    let header_len = MAGIC.len() + 4;
+    if bytes.len() >= header_len && bytes.starts_with(MAGIC) {
+        let version = bytes[MAGIC.len()..header_len].try_into().unwrap();
+        (u32::from_le_bytes(version), &bytes[header_len..])
+    } else {
+        (0, bytes)
+    }
+}
+
+/// Encode the contents of a database file with the current schema version.
+pub fn encode(db: &AppsDB) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4096);
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&SCHEMA_VERSION.to_le_bytes());
+    let contents = SchemaV3 {
+        reference_time: db.reference_time,
+        half_life: db.half_life,
+        apps: &db.apps,
+        removed: &db.removed,
+        launches: &db.launches,
+    };
+    buf.extend(rmp::to_vec(&contents).expect("Failed to encode apps db"));
+    buf
+}
+
+/// Decode a database file, migrating it from older schema versions.
+pub fn decode(bytes: &[u8]) -> Result<DbContents, FormatError> {
+    let (version, body) = split_header(bytes);
+    match version {
+        0 => {
+            let db: SchemaV0 = rmp::from_read_ref(body)?;
+            Ok(DbContents {
+                apps: db.apps,
+                reference_time: db.reference_time,
+                half_life: db.half_life,
+                removed: Vec::new(),
+                launches: Vec::new(),
+            })
+        }
+        1 => {
+            let db: SchemaV1 = rmp::from_read_ref(body)?;
+            Ok(DbContents {
+                apps: db.apps,
+                reference_time: db.reference_time,
+                half_life: db.half_life,
+                removed: Vec::new(),
+                launches: Vec::new(),
+            })
+        }
+        2 => {
+            let db: SchemaV2 = rmp::from_read_ref(body)?;
+            Ok(DbContents {
+                apps: db.apps,
+                reference_time: db.reference_time,
+                half_life: db.half_life,
+                removed: db.removed,
+                launches: Vec::new(),
+            })
+        }
+        3 => {
+            let db: SchemaV3<Vec<App>, Vec<RemovedApp>, Vec<Launch>> =
+                rmp::from_read_ref(body)?;
+            Ok(DbContents {
+                apps: db.apps,
+                reference_time: db.reference_time,
+                half_life: db.half_life,
+                removed: db.removed,
+                launches: db.launches,
+            })
+        }
+        version => Err(FormatError::UnsupportedVersion(version)),
+    }
+}
+
+/// What could be salvaged from a corrupted database file.
+#[derive(Debug, Default)]
+pub struct Recovered {
+    /// The apps that could be decoded before the corruption.
+    pub apps: Vec<App>,
+    /// The reference time, if it could be decoded.
+    pub reference_time: Option<f64>,
+    /// The half life, if it could be decoded.
+    pub half_life: Option<f32>,
+}
+
+/// Decode as much as possible of a corrupted database file.
+pub fn recover(bytes: &[u8]) -> Recovered {
+    let (version, body) = split_header(bytes);
+    let mut de = rmp::Deserializer::from_read_ref(body);
+    de.deserialize_seq(RecoverVisitor { version })
+        .unwrap_or_default()
+}
+
+/// Visitor for the top level array of a database file that stops at the
+/// first element it fails to decode.
+struct RecoverVisitor {
+    version: u32,
+}
+
+impl<'de> Visitor<'de> for RecoverVisitor {
+    type Value = Recovered;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an apps database")
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(
+        self,
+        mut seq: S,
+    ) -> Result<Self::Value, S::Error> {
+        let mut recovered = Recovered::default();
+        if self.version == 0 {
+            recovered.apps = match seq.next_element::<PartialApps>() {
+                Ok(Some(apps)) => apps.0,
+                _ => return Ok(recovered),
+            };
+            recovered.reference_time = seq.next_element().ok().flatten();
+            recovered.half_life = seq.next_element().ok().flatten();
+        } else {
+            recovered.reference_time = seq.next_element().ok().flatten();
+            if recovered.reference_time.is_none() {
+                return Ok(recovered);
+            }
+            recovered.half_life = seq.next_element().ok().flatten();
+            if recovered.half_life.is_none() {
+                return Ok(recovered);
+            }
+            if let Ok(Some(apps)) = seq.next_element::<PartialApps>() {
+                recovered.apps = apps.0;
+            }
+        }
+        Ok(recovered)
+    }
+}
+
+/// The apps that could be decoded from a possibly corrupted list.
+struct PartialApps(Vec<App>);
+
+impl<'de> Deserialize<'de> for PartialApps {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        struct PartialAppsVisitor;
+
+        impl<'de> Visitor<'de> for PartialAppsVisitor {
+            type Value = PartialApps;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a list of apps")
+            }
+
+            fn visit_seq<S: SeqAccess<'de>>(
+                self,
+                mut seq: S,
+            ) -> Result<Self::Value, S::Error> {
+                let mut apps = Vec::new();
+                while let Ok(Some(app)) = seq.next_element::<App>() {
+                    apps.push(app);
+                }
+                Ok(PartialApps(apps))
+            }
+        }
+
+        deserializer.deserialize_seq(PartialAppsVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    fn apps() -> Vec<App> {
+        (0..10)
+            .map(|i| {
+                App::new(
+                    format!("Test{}", i),
+                    "icon".to_owned(),
+                    vec![format!("/bin/test{}", i)],
+                    false,
+                )
+            })
+            .collect()
+    }
+
+    fn apps_db(apps: Vec<App>) -> AppsDB {
+        let mut db = AppsDB::new(Config::default(), apps);
+        db.reference_time = 10.0;
+        db.half_life = 20.0;
+        db
+    }
+
+    #[test]
+    fn round_trip() {
+        let apps = apps();
+        let mut apps_db = apps_db(apps.clone());
+        apps_db.removed.push(RemovedApp {
+            app: apps[0].clone(),
+            removed_at: 5.0,
+        });
+        apps_db.launches.push(Launch {
+            uuid: apps[1].uuid.clone(),
+            action: None,
+            time: 15.0,
+            query: Some("test".to_owned()),
+        });
+        let bytes = encode(&apps_db);
+        let db = decode(&bytes).unwrap();
+        assert_eq!(db.apps, apps);
+        assert_eq!(db.reference_time, 10.0);
+        assert_eq!(db.half_life, 20.0);
+        assert_eq!(db.removed.len(), 1);
+        assert_eq!(db.removed[0].app, apps[0]);
+        assert_eq!(db.launches, apps_db.launches);
+    }
+
+    #[test]
+    fn migrate_v1() {
+        #[derive(Serialize)]
+        struct V1<'a> {
+            reference_time: f64,
+            half_life: f32,
+            apps: &'a [App],
+        }
+        let apps = apps();
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend(
+            rmp::to_vec(&V1 {
+                reference_time: 10.0,
+                half_life: 20.0,
+                apps: &apps,
+            })
+            .unwrap(),
+        );
+        let db = decode(&bytes).unwrap();
+        assert_eq!(db.apps, apps);
+        assert_eq!(db.reference_time, 10.0);
+        assert!(db.removed.is_empty());
+        assert!(db.launches.is_empty());
+    }
+
+    #[test]
+    fn migrate_v0() {
+        /// `App` as it was serialized before versioning.
+        #[derive(Serialize)]
+        struct LegacyApp {
+            name: String,
+            exec: Vec<String>,
+            score: f32,
+            uuid: String,
+            icon: String,
+            terminal: bool,
+        }
+        /// `AppsDB` as it was serialized before versioning.
+        #[derive(Serialize)]
+        struct Legacy {
+            apps: Vec<LegacyApp>,
+            reference_time: f64,
+            half_life: f32,
+        }
+        let legacy_app = |name: &str, score, uuid: &str, terminal| LegacyApp {
+            name: name.to_owned(),
+            exec: vec![format!("/bin/{}", name), "--new".to_owned()],
+            score,
+            uuid: uuid.to_owned(),
+            icon: format!("{}-icon", name),
+            terminal,
+        };
+        let bytes = rmp::to_vec(&Legacy {
+            apps: vec![
+                legacy_app("firefox", 2.5, "uuid-firefox", false),
+                legacy_app("htop", 0.75, "uuid-htop", true),
+            ],
+            reference_time: 10.0,
+            half_life: 20.0,
+        })
+        .unwrap();
+        let db = decode(&bytes).unwrap();
+        assert_eq!(db.reference_time, 10.0);
+        assert_eq!(db.half_life, 20.0);
+        assert_eq!(db.apps.len(), 2);
+        let firefox = &db.apps[0];
+        assert_eq!(firefox.name, "firefox");
+        assert_eq!(firefox.exec, vec!["/bin/firefox", "--new"]);
+        assert_eq!(firefox.score, 2.5);
+        assert_eq!(firefox.uuid, "uuid-firefox");
+        assert_eq!(firefox.icon, "firefox-icon");
+        assert!(!firefox.terminal);
+        assert!(firefox.id.is_empty());
+        assert!(firefox.actions.is_empty());
+        let htop = &db.apps[1];
+        assert_eq!(htop.score, 0.75);
+        assert_eq!(htop.uuid, "uuid-htop");
+        assert!(htop.terminal);
+        assert!(db.removed.is_empty());
+        assert!(db.launches.is_empty());
+    }
+
+    #[test]
+    fn newer_version() {
+        let mut bytes = encode(&apps_db(apps()));
+        bytes[MAGIC.len()] = 99;
+        assert!(matches!(
+            decode(&bytes),
+            Err(FormatError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn recover_truncated() {
+        let apps = apps();
+        let bytes = encode(&apps_db(apps.clone()));
+        let truncated = &bytes[..bytes.len() * 2 / 3];
+        assert!(decode(truncated).is_err());
+        let recovered = recover(truncated);
+        assert_eq!(recovered.reference_time, Some(10.0));
+        assert_eq!(recovered.half_life, Some(20.0));
+        assert!(!recovered.apps.is_empty());
+        assert!(recovered.apps.len() < apps.len());
+        assert_eq!(recovered.apps[..], apps[..recovered.apps.len()]);
+    }
+
+    #[test]
+    fn recover_garbage() {
+        let recovered = recover(b"not a database");
+        assert!(recovered.apps.is_empty());
+        assert_eq!(recovered.reference_time, None);
+    }
+}
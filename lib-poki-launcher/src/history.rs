@@ -0,0 +1,111 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use serde::{Deserialize, Serialize};
+
+/// A launch of an app, recorded in the launch log of the `AppsDB`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Launch {
+    /// Uuid of the launched app.
+    pub uuid: String,
+    /// Id of the desktop action that was launched, if any.
+    pub action: Option<String>,
+    /// Time of the launch in seconds since the epoch.
+    pub time: f64,
+    /// The search the app was launched from, if any.
+    #[serde(default)]
+    pub query: Option<String>,
+}
+
+/// Launch statistics of an app.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AppHistory {
+    /// Number of recorded launches of the app and its actions.
+    pub launches: usize,
+    /// Time of the last recorded launch in seconds since the epoch.
+    pub last_used: Option<f64>,
+}
+
+impl AppHistory {
+    /// Collect the history of the app with `uuid` from a launch log.
+    pub fn from_log<'a>(
+        log: impl IntoIterator<Item = &'a Launch>,
+        uuid: &str,
+    ) -> AppHistory {
+        log.into_iter().filter(|launch| launch.uuid == uuid).fold(
+            AppHistory::default(),
+            |history, launch| AppHistory {
+                launches: history.launches + 1,
+                last_used: Some(
+                    history
+                        .last_used
+                        .map_or(launch.time, |last| last.max(launch.time)),
+                ),
+            },
+        )
+    }
+}
+
+/// Compute the score of an app from its launch times.
+///
+/// Each launch adds 1 to the score at the time of the launch which then
+/// halves every `half_life` seconds. Like the scores in the `AppsDB` the
+/// result is relative to `reference_time`, ie. it still has to be decayed by
+/// the time elapsed since `reference_time`.
+pub fn score_from_launches(
+    times: impl IntoIterator<Item = f64>,
+    reference_time: f64,
+    half_life: f32,
+) -> f32 {
+    times
+        .into_iter()
+        .map(|time| 2.0f32.powf((time - reference_time) as f32 / half_life))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn launch(uuid: &str, time: f64) -> Launch {
+        Launch {
+            uuid: uuid.to_owned(),
+            action: None,
+            time,
+            query: None,
+        }
+    }
+
+    #[test]
+    fn history() {
+        let log = vec![launch("a", 10.0), launch("b", 20.0), launch("a", 5.0)];
+        assert_eq!(
+            AppHistory::from_log(&log, "a"),
+            AppHistory {
+                launches: 2,
+                last_used: Some(10.0),
+            }
+        );
+        assert_eq!(AppHistory::from_log(&log, "c"), AppHistory::default());
+    }
+
+    #[test]
+    fn score() {
+        assert_eq!(score_from_launches(vec![100.0], 100.0, 10.0), 1.0);
+        assert_eq!(score_from_launches(vec![90.0, 100.0], 100.0, 10.0), 1.5);
+        assert_eq!(score_from_launches(vec![], 100.0, 10.0), 0.0);
+    }
+}
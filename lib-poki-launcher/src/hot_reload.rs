@@ -1,189 +1,393 @@
 //! This module provide the ability to easily hot reload but the config and apps
 //! list on file system changes.
+//!
+//! Changes are reported as `Event`s to the callback passed to
+//! `HotReloadBuilder::start`. The apps watcher follows the `app_paths`
+//! setting when the config is watched too, and only reparses the desktop
+//! entries that changed, see `AppsDB::apply_app_changes`.
 
+use std::collections::HashMap;
 use std::mem;
-use std::path::PathBuf;
-use std::sync::mpsc;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
 
-use crate::{Config, CFG_PATH};
-use crossbeam_channel::{Receiver, Select, Sender};
+use crate::desktop_entry::parse_desktop_file;
+use crate::scan::{
+    current_desktops, desktop_file_id, filter_app, find_desktop_file,
+    scan_desktop_entries, ScanError,
+};
+use crate::{App, AppsDB, Config, Profile};
 use notify::{
-    watcher, DebouncedEvent::*, RecommendedWatcher, RecursiveMode, Watcher,
+    watcher, DebouncedEvent, DebouncedEvent::*, RecommendedWatcher,
+    RecursiveMode, Watcher,
 };
 
 /// Hot reload callback function
-pub type Callback = Box<dyn Fn() + Send + 'static>;
+pub type Callback = Box<dyn Fn(Event) + Send + 'static>;
+
+/// A change to the config or the apps.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// The config file changed, with the new config.
+    ConfigChanged(Box<Config>),
+    /// Desktop entries were added, removed or modified.
+    ///
+    /// Pass these to `AppsDB::apply_app_changes` to update the apps.
+    AppsChanged {
+        /// Apps whose desktop entry was added.
+        added: Vec<App>,
+        /// Apps whose desktop entry was removed or is now hidden.
+        removed: Vec<App>,
+        /// Apps whose desktop entry was changed, with the new entry.
+        modified: Vec<App>,
+    },
+}
 
 #[derive(Default)]
-pub struct HotReloadBuilder<'config> {
-    config: Option<Callback>,
-    apps: Option<(&'config Config, Callback)>,
+pub struct HotReloadBuilder {
+    config: Option<Profile>,
+    apps: Option<AppsState>,
 }
 
-pub fn build<'config>() -> HotReloadBuilder<'config> {
+pub fn build() -> HotReloadBuilder {
     HotReloadBuilder::default()
 }
 
-impl<'config> HotReloadBuilder<'config> {
-    pub fn config(
-        &mut self,
-        callback: impl Fn() + Send + 'static,
-    ) -> &mut Self {
-        self.config = Some(Box::new(callback));
+impl HotReloadBuilder {
+    /// Watch the config file of `profile`.
+    ///
+    /// In memory profiles have no config file to watch.
+    pub fn config(&mut self, profile: &Profile) -> &mut Self {
+        self.config = Some(profile.clone());
         self
     }
 
-    pub fn apps(
-        &mut self,
-        config: &'config Config,
-        callback: impl Fn() + Send + 'static,
-    ) -> &mut Self {
-        self.apps = Some((config, Box::new(callback)));
+    /// Watch the desktop entries in the `app_paths` of `apps_db`.
+    ///
+    /// Changes are reported relative to the apps currently in `apps_db`.
+    pub fn apps(&mut self, apps_db: &AppsDB) -> &mut Self {
+        self.apps = Some(AppsState::new(
+            apps_db.config.app_paths.clone(),
+            &apps_db.apps,
+        ));
         self
     }
 
-    pub fn start(self) -> notify::Result<HotReloadHandle> {
-        let opt = match (self.config, self.apps) {
-            (Some(config_cb), Some((config, desktop_cb))) => {
-                HotReloadType::Both(config, config_cb, desktop_cb)
+    /// Start watching, events are passed to `callback` from a background
+    /// thread.
+    pub fn start(
+        self,
+        callback: impl Fn(Event) + Send + 'static,
+    ) -> notify::Result<HotReloadHandle> {
+        if self.config.is_none() && self.apps.is_none() {
+            panic!("HotReloadBuilder::start require that a config or desktop file reload has been set");
+        }
+        let (sender, recv) = mpsc::channel();
+        let config_path = self
+            .config
+            .as_ref()
+            .and_then(|profile| profile.config_path());
+        let config_watcher = match config_path {
+            Some(path) => {
+                let mut watcher =
+                    watcher(sender.clone(), Duration::from_secs(1))?;
+                watcher.watch(path, RecursiveMode::NonRecursive)?;
+                Some(watcher)
             }
-            (Some(config_cb), None) => HotReloadType::Config(config_cb),
-            (None, Some((config, desktop_cb))) => {
-                HotReloadType::Apps(config, desktop_cb)
+            None => None,
+        };
+        let apps_watcher = match &self.apps {
+            Some(state) => {
+                let mut watcher = watcher(sender, Duration::from_secs(1))?;
+                watch_app_paths(&mut watcher, &state.app_paths);
+                Some(watcher)
             }
-            (None, None) => HotReloadType::None,
+            None => None,
         };
-        hot_reload(opt)
+        let apps_watcher = Arc::new(Mutex::new(apps_watcher));
+        let state = Arc::new(Mutex::new(HandlerState {
+            apps: self.apps,
+            callback: Box::new(callback),
+        }));
+        let join_handle = start_handler(
+            recv,
+            self.config,
+            Arc::clone(&state),
+            Arc::clone(&apps_watcher),
+        );
+        Ok(HotReloadHandle {
+            config_watcher,
+            apps_watcher,
+            state,
+            join_handle: Some(join_handle),
+        })
     }
 }
 
-enum HotReloadType<'config> {
-    Config(Callback),
-    Apps(&'config Config, Callback),
-    Both(&'config Config, Callback, Callback),
-    None,
-}
-
 pub struct HotReloadHandle {
     config_watcher: Option<RecommendedWatcher>,
-    apps_watcher: Option<RecommendedWatcher>,
-    join_handles: Vec<JoinHandle<()>>,
-    current_app_paths: Vec<PathBuf>,
+    apps_watcher: Arc<Mutex<Option<RecommendedWatcher>>>,
+    state: Arc<Mutex<HandlerState>>,
+    join_handle: Option<JoinHandle<()>>,
 }
 
 impl HotReloadHandle {
-    // TODO this can't be called in the config callback because the
-    // HotReloadHandle hasn't been created yet
-    pub fn config_changes(&mut self, config: &Config) -> notify::Result<()> {
-        if let Some(watcher) = &mut self.apps_watcher {
-            for path in self.current_app_paths.drain(..) {
-                watcher.unwatch(path)?;
-            }
-            for path in config.app_paths.clone() {
-                watcher.watch(&path, RecursiveMode::Recursive)?;
-                self.current_app_paths.push(path);
-            }
+    /// Rescan all desktop entries now, ex. when the user asks for it, and
+    /// pass the changes to the callback.
+    ///
+    /// Apps found this way are watched like all others, so rescan with this
+    /// instead of `AppsDB::merge_new_entries` while the apps are watched.
+    ///
+    /// Returns the errors of the scan, or `None` if the apps aren't watched.
+    pub fn rescan(&self) -> Option<Vec<ScanError>> {
+        let mut state = self.state.lock().unwrap();
+        let HandlerState { apps, callback } = &mut *state;
+        let (event, errors) = apps.as_mut()?.rescan();
+        if let Some(event) = event {
+            callback(event);
         }
-        Ok(())
+        Some(errors)
     }
 }
 
 impl Drop for HotReloadHandle {
     fn drop(&mut self) {
+        // Dropping the watchers closes the channel which stops the handler
         mem::drop(self.config_watcher.take());
-        mem::drop(self.apps_watcher.take());
-        for handle in self.join_handles.drain(..) {
+        mem::drop(self.apps_watcher.lock().unwrap().take());
+        if let Some(handle) = self.join_handle.take() {
             handle.join().unwrap();
         }
     }
 }
 
-fn hot_reload(opt: HotReloadType<'_>) -> notify::Result<HotReloadHandle> {
-    match opt {
-        HotReloadType::Config(callback) => {
-            let (mut config_watcher, join_handle, recv) = new_watcher()?;
-            set_config_watcher(&mut config_watcher)?;
-            let cb_join_handle = setup_handlers(vec![(recv, callback)]);
-            Ok(HotReloadHandle {
-                config_watcher: Some(config_watcher),
-                apps_watcher: None,
-                join_handles: vec![join_handle, cb_join_handle],
-                current_app_paths: vec![],
-            })
+/// State of the handler thread, shared with `HotReloadHandle::rescan`.
+///
+/// Changes are passed to the callback while it's locked, so they're applied
+/// in the order they were found.
+struct HandlerState {
+    apps: Option<AppsState>,
+    callback: Callback,
+}
+
+/// The apps known to the hot reloader, by desktop file ID.
+struct AppsState {
+    app_paths: Vec<PathBuf>,
+    apps: HashMap<String, App>,
+}
+
+impl AppsState {
+    fn new(app_paths: Vec<PathBuf>, apps: &[App]) -> Self {
+        let apps = apps
+            .iter()
+            .filter(|app| !app.id.is_empty() && app.action.is_none())
+            .map(|app| (app.id.clone(), app.clone()))
+            .collect();
+        AppsState { app_paths, apps }
+    }
+
+    /// Reparse the desktop entries at `paths` and return the changes.
+    fn files_changed(&mut self, paths: &[PathBuf]) -> Option<Event> {
+        let mut changes = Changes::default();
+        let mut done = Vec::new();
+        for path in paths {
+            if !path.to_string_lossy().ends_with(".desktop") {
+                continue;
+            }
+            let id = match self.desktop_file_id(path) {
+                Some(id) => id,
+                None => continue,
+            };
+            if done.contains(&id) {
+                continue;
+            }
+            done.push(id.clone());
+            let entry = self.find_entry(&id);
+            // Only an entry shadowed by the current entry of the app changed
+            let shadowed = entry.as_deref().is_some_and(|entry| {
+                entry != path
+                    && self
+                        .apps
+                        .get(&id)
+                        .is_some_and(|app| app.entry_path == entry)
+            });
+            if shadowed {
+                continue;
+            }
+            let app = entry.and_then(|entry| self.parse_entry(&entry, &id));
+            changes.update(&mut self.apps, id, app);
         }
-        HotReloadType::Apps(config, callback) => {
-            let (mut apps_watcher, join_handle, recv) =
-                new_watcher()?;
-            set_apps_watcher(&mut apps_watcher, &config.app_paths)?;
-            let cb_join_handle = setup_handlers(vec![(recv, callback)]);
-            Ok(HotReloadHandle {
-                config_watcher: None,
-                apps_watcher: Some(apps_watcher),
-                join_handles: vec![join_handle, cb_join_handle],
-                current_app_paths: config.app_paths.clone(),
-            })
+        changes.into_event()
+    }
+
+    /// Rescan all desktop entries, ex. because `app_paths` changed, and
+    /// return the changes and the errors of the scan.
+    fn rescan(&mut self) -> (Option<Event>, Vec<ScanError>) {
+        let (apps, errors) = scan_desktop_entries(&self.app_paths);
+        let mut apps: HashMap<_, _> =
+            apps.into_iter().map(|app| (app.id.clone(), app)).collect();
+        let mut changes = Changes::default();
+        let ids: Vec<_> = self.apps.keys().cloned().collect();
+        for id in ids {
+            let app = apps.remove(&id);
+            changes.update(&mut self.apps, id, app);
+        }
+        for (id, app) in apps {
+            changes.update(&mut self.apps, id, Some(app));
+        }
+        (changes.into_event(), errors)
+    }
+
+    /// Get the desktop file ID of the entry at `path` in one of the app
+    /// paths.
+    fn desktop_file_id(&self, path: &Path) -> Option<String> {
+        self.app_paths
+            .iter()
+            .find_map(|base| desktop_file_id(base, path))
+    }
+
+    /// Find the entry with the desktop file ID `id` that takes precedence.
+    fn find_entry(&self, id: &str) -> Option<PathBuf> {
+        self.app_paths
+            .iter()
+            .find_map(|base| find_desktop_file(base, id))
+    }
+
+    /// Parse the entry at `path` with the desktop file ID `id`.
+    ///
+    /// Returns `None` if the entry is invalid or the app shouldn't be shown.
+    fn parse_entry(&self, path: &Path, id: &str) -> Option<App> {
+        let mut app = match parse_desktop_file(path) {
+            Ok(app) => app?,
+            Err(e) => {
+                log::warn!("{}", e);
+                return None;
+            }
+        };
+        app.id = id.to_owned();
+        filter_app(&app, &current_desktops()).ok()?;
+        Some(app)
+    }
+}
+
+/// Changes to the apps collected for an `Event::AppsChanged`.
+#[derive(Default)]
+struct Changes {
+    added: Vec<App>,
+    removed: Vec<App>,
+    modified: Vec<App>,
+}
+
+impl Changes {
+    /// Record that the app with the desktop file ID `id` is now `app`.
+    fn update(
+        &mut self,
+        apps: &mut HashMap<String, App>,
+        id: String,
+        app: Option<App>,
+    ) {
+        match (apps.remove(&id), app) {
+            (None, Some(app)) => {
+                self.added.push(app.clone());
+                apps.insert(id, app);
+            }
+            (Some(_), Some(app)) => {
+                self.modified.push(app.clone());
+                apps.insert(id, app);
+            }
+            (Some(old), None) => self.removed.push(old),
+            (None, None) => {}
         }
-        HotReloadType::Both(config, config_cb, desktop_cb) => {
-            let (mut config_watcher, config_join_handle, config_recv) =
-                new_watcher()?;
-            set_config_watcher(&mut config_watcher)?;
-            let (mut apps_watcher, desktop_join_handle, desktop_recv) =
-                new_watcher()?;
-            set_apps_watcher(&mut apps_watcher, &config.app_paths)?;
-            let cb_join_handle = setup_handlers(vec![
-                (config_recv, config_cb),
-                (desktop_recv, desktop_cb),
-            ]);
-            Ok(HotReloadHandle {
-                config_watcher: Some(config_watcher),
-                apps_watcher: Some(apps_watcher),
-                join_handles: vec![
-                    config_join_handle,
-                    desktop_join_handle,
-                    cb_join_handle,
-                ],
-                current_app_paths: config.app_paths.clone(),
+    }
+
+    fn into_event(self) -> Option<Event> {
+        if self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+        {
+            None
+        } else {
+            Some(Event::AppsChanged {
+                added: self.added,
+                removed: self.removed,
+                modified: self.modified,
             })
         }
-        HotReloadType::None => panic!("HotReloadBuilder::start require that a config or desktop file reload has been set"),
     }
 }
 
-fn setup_handlers(
-    recv_callback: Vec<(Receiver<()>, Callback)>,
+fn start_handler(
+    recv: Receiver<DebouncedEvent>,
+    profile: Option<Profile>,
+    state: Arc<Mutex<HandlerState>>,
+    apps_watcher: Arc<Mutex<Option<RecommendedWatcher>>>,
 ) -> JoinHandle<()> {
     thread::spawn(move || {
-        let mut sel = Select::new();
-        for r in &recv_callback {
-            sel.recv(&r.0);
-        }
-        loop {
-            let idx = sel.ready();
-            match recv_callback[idx].0.recv() {
-                Ok(_) => recv_callback[idx].1(),
-                _ => break,
+        while let Ok(event) = recv.recv() {
+            let mut state = state.lock().unwrap();
+            let HandlerState { apps, callback } = &mut *state;
+            let paths = match event {
+                Write(path) | Remove(path) | Create(path) => vec![path],
+                Rename(from, to) => vec![from, to],
+                Rescan => {
+                    if let Some((Some(event), _)) =
+                        apps.as_mut().map(AppsState::rescan)
+                    {
+                        callback(event);
+                    }
+                    continue;
+                }
+                Error(e, path) => {
+                    log::warn!("Error watching {:?}: {}", path, e);
+                    continue;
+                }
+                _ => continue,
+            };
+            let config_profile = profile.as_ref().filter(|profile| {
+                profile
+                    .config_path()
+                    .is_some_and(|path| paths.iter().any(|p| p == path))
+            });
+            if let Some(profile) = config_profile {
+                let config = match Config::load(profile) {
+                    Ok(config) => config,
+                    Err(e) => {
+                        log::warn!("Failed to reload config: {}", e);
+                        continue;
+                    }
+                };
+                let retarget = apps
+                    .as_ref()
+                    .is_some_and(|apps| apps.app_paths != config.app_paths);
+                let app_paths = config.app_paths.clone();
+                callback(Event::ConfigChanged(Box::new(config)));
+                if retarget {
+                    let apps = apps.as_mut().unwrap();
+                    if let Some(watcher) = &mut *apps_watcher.lock().unwrap() {
+                        for path in &apps.app_paths {
+                            // Fails for dirs that didn't exist
+                            let _ = watcher.unwatch(path);
+                        }
+                        watch_app_paths(watcher, &app_paths);
+                    }
+                    apps.app_paths = app_paths;
+                    if let (Some(event), _) = apps.rescan() {
+                        callback(event);
+                    }
+                }
+            } else if let Some(event) =
+                apps.as_mut().and_then(|apps| apps.files_changed(&paths))
+            {
+                callback(event);
             }
         }
     })
 }
 
-fn new_watcher(
-) -> notify::Result<(RecommendedWatcher, JoinHandle<()>, Receiver<()>)> {
-    let (sender, recv) = crossbeam_channel::unbounded();
-    let (watcher, join_handle) = start_watcher(sender)?;
-    Ok((watcher, join_handle, recv))
-}
-
-fn set_config_watcher(watcher: &mut RecommendedWatcher) -> notify::Result<()> {
-    watcher.watch(&*CFG_PATH, RecursiveMode::NonRecursive)
-}
-
-fn set_apps_watcher(
-    watcher: &mut RecommendedWatcher,
-    app_paths: &[PathBuf],
-) -> notify::Result<()> {
+fn watch_app_paths(watcher: &mut RecommendedWatcher, app_paths: &[PathBuf]) {
     for path in app_paths {
         if path.exists() {
             if let Err(e) = watcher.watch(path, RecursiveMode::Recursive) {
@@ -195,28 +399,107 @@ fn set_apps_watcher(
             }
         }
     }
-    Ok(())
 }
 
-fn start_watcher(
-    sender: Sender<()>,
-) -> notify::Result<(RecommendedWatcher, JoinHandle<()>)> {
-    let (tx, rx) = mpsc::channel();
-    let watcher = match watcher(tx, Duration::from_secs(1)) {
-        Ok(watcher) => watcher,
-        Err(e) => {
-            return Err(e);
-        }
-    };
-    let join_handle = thread::spawn(move || {
-        while let Ok(event) = rx.recv() {
-            match event {
-                Write(_) | Remove(_) | Rename(_, _) | Create(_) => {
-                    sender.send(()).unwrap();
-                }
-                _ => {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::fs;
+
+    fn names(apps: &[App]) -> Vec<&str> {
+        apps.iter().map(|app| app.name.as_str()).collect()
+    }
+
+    #[test]
+    fn files_changed() {
+        let dir = TempDir::new("hot-reload");
+        let (high, low) = (dir.join("high"), dir.join("low"));
+        fs::create_dir_all(high.join("sub")).unwrap();
+        fs::create_dir_all(&low).unwrap();
+        let entry = |path: &Path, name: &str| {
+            let contents = format!(
+                "[Desktop Entry]\nName={}\nExec=/bin/{}",
+                name,
+                name.to_lowercase()
+            );
+            fs::write(path, contents).unwrap();
+        };
+        entry(&low.join("a.desktop"), "A");
+        let mut state = AppsState::new(vec![high.clone(), low.clone()], &[]);
+        assert!(state.rescan().0.is_some());
+
+        // A new entry in a subdirectory
+        let new = high.join("sub/b.desktop");
+        entry(&new, "B");
+        match state.files_changed(std::slice::from_ref(&new)) {
+            Some(Event::AppsChanged { added, .. }) => {
+                assert_eq!(names(&added), ["B"]);
+                assert_eq!(added[0].id, "sub-b.desktop");
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+        // Shadowing an entry in a lower precedence dir
+        let shadow = high.join("a.desktop");
+        entry(&shadow, "Shadow");
+        match state.files_changed(&[shadow.clone(), low.join("a.desktop")]) {
+            Some(Event::AppsChanged {
+                added,
+                removed,
+                modified,
+            }) => {
+                assert!(added.is_empty() && removed.is_empty());
+                assert_eq!(names(&modified), ["Shadow"]);
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+        // Changes to shadowed entries are ignored
+        entry(&low.join("a.desktop"), "Other");
+        assert!(state.files_changed(&[low.join("a.desktop")]).is_none());
+        fs::remove_file(&new).unwrap();
+        match state.files_changed(&[new, dir.join("unrelated.txt")]) {
+            Some(Event::AppsChanged { removed, .. }) => {
+                assert_eq!(names(&removed), ["B"])
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+        assert!(state.files_changed(&[dir.join("c.desktop")]).is_none());
+
+        // Retargeting to only the low precedence dir
+        state.app_paths = vec![low.clone()];
+        match state.rescan().0 {
+            Some(Event::AppsChanged { modified, .. }) => {
+                assert_eq!(names(&modified), ["Other"])
             }
+            event => panic!("Unexpected event {:?}", event),
         }
-    });
-    Ok((watcher, join_handle))
+
+        // Entries with the same ID in differently nested dirs
+        state.app_paths = vec![high.clone(), low.clone()];
+        fs::create_dir_all(low.join("kde4")).unwrap();
+        let nested = low.join("kde4/c.desktop");
+        entry(&nested, "Nested");
+        match state.files_changed(std::slice::from_ref(&nested)) {
+            Some(Event::AppsChanged { added, .. }) => {
+                assert_eq!(names(&added), ["Nested"]);
+                assert_eq!(added[0].id, "kde4-c.desktop");
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+        let flat = high.join("kde4-c.desktop");
+        entry(&flat, "Flat");
+        match state.files_changed(std::slice::from_ref(&flat)) {
+            Some(Event::AppsChanged { modified, .. }) => {
+                assert_eq!(names(&modified), ["Flat"])
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+        fs::remove_file(&flat).unwrap();
+        match state.files_changed(&[flat]) {
+            Some(Event::AppsChanged { modified, .. }) => {
+                assert_eq!(names(&modified), ["Nested"])
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+    }
 }
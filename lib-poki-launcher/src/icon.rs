@@ -0,0 +1,308 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use crate::xdg;
+use lazy_static::lazy_static;
+use linicon::{IconPath, IconType};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Theme every icon theme falls back to.
+const FALLBACK_THEME: &str = "hicolor";
+/// Directory of icons that aren't part of any theme.
+const PIXMAPS_DIR: &str = "/usr/share/pixmaps";
+
+lazy_static! {
+    /// The user's icon theme, looked up once as this may spawn `gsettings`.
+    static ref SYSTEM_THEME: Option<String> = linicon::get_system_theme();
+}
+
+/// Find the file for an icon following the freedesktop icon theme spec.
+///
+/// # Arguments
+///
+/// * `icon` - The `Icon` key of a desktop entry, either an icon name or an
+///   absolute path.
+/// * `size` - Size of the icon in pxs.
+/// * `scale` - Scale of the display the icon will be shown on.
+/// * `theme` - Theme to search first, defaults to the user's icon theme.
+/// * `prefer_svg` - Pick SVG over PNG icons if a theme has both.
+///
+/// The theme and the themes it inherits from are searched before falling
+/// back to `hicolor` then `/usr/share/pixmaps`. The icon closest in size is
+/// picked from the first theme that has the icon.
+pub fn find_icon(
+    icon: &str,
+    size: u16,
+    scale: u16,
+    theme: Option<&str>,
+    prefer_svg: bool,
+) -> Option<PathBuf> {
+    let icon_dirs: Vec<_> = xdg::data_home()
+        .map(|data_home| data_home.join("icons"))
+        .into_iter()
+        .collect();
+    find_icon_in(icon, size, scale, theme, prefer_svg, &icon_dirs)
+}
+
+/// Like `find_icon` but the themes are also searched for in `icon_dirs`,
+/// before the dirs searched by default.
+fn find_icon_in(
+    icon: &str,
+    size: u16,
+    scale: u16,
+    theme: Option<&str>,
+    prefer_svg: bool,
+    icon_dirs: &[PathBuf],
+) -> Option<PathBuf> {
+    if icon.is_empty() {
+        return None;
+    }
+    let path = Path::new(icon);
+    if path.is_absolute() {
+        return if path.exists() {
+            Some(path.to_owned())
+        } else {
+            None
+        };
+    }
+    let theme = theme
+        .map(str::to_owned)
+        .or_else(|| SYSTEM_THEME.clone())
+        .unwrap_or_else(|| FALLBACK_THEME.to_owned());
+    let mut themes = vec![theme.as_str()];
+    if theme != FALLBACK_THEME {
+        themes.push(FALLBACK_THEME);
+    }
+    let mut scales = vec![scale];
+    if scale != 1 {
+        scales.push(1);
+    }
+    themes
+        .into_iter()
+        .flat_map(|theme| scales.iter().map(move |scale| (theme, *scale)))
+        .find_map(|(theme, scale)| {
+            find_in_theme(icon, theme, size, scale, prefer_svg, icon_dirs)
+        })
+        .or_else(|| find_in_pixmaps(icon, prefer_svg))
+}
+
+/// Find the best match for an icon in the first theme that has the icon,
+/// starting with `theme` then the themes it inherits from.
+fn find_in_theme(
+    icon: &str,
+    theme: &str,
+    size: u16,
+    scale: u16,
+    prefer_svg: bool,
+    icon_dirs: &[PathBuf],
+) -> Option<PathBuf> {
+    let mut iter = linicon::lookup_icon(icon).from_theme(theme);
+    let icon_dirs: Vec<_> =
+        icon_dirs.iter().filter_map(|dir| dir.to_str()).collect();
+    if !icon_dirs.is_empty() {
+        iter = iter.with_search_paths(&icon_dirs).ok()?;
+    }
+    let mut found_theme = None;
+    let mut best: Option<((u16, u8), PathBuf)> = None;
+    for icon_path in iter.filter_map(Result::ok) {
+        // Only consider icons from the first theme with a match
+        match &found_theme {
+            Some(found_theme) if *found_theme != icon_path.theme => break,
+            Some(_) => {}
+            None => found_theme = Some(icon_path.theme.clone()),
+        }
+        if icon_path.scale != scale {
+            continue;
+        }
+        let rank = (
+            size_distance(&icon_path, size),
+            format_rank(&icon_path.icon_type, prefer_svg),
+        );
+        if best.as_ref().is_none_or(|(best, _)| rank < *best) {
+            best = Some((rank, icon_path.path));
+        }
+    }
+    best.map(|(_, path)| path)
+}
+
+/// Look for an icon in `/usr/share/pixmaps`.
+fn find_in_pixmaps(icon: &str, prefer_svg: bool) -> Option<PathBuf> {
+    let dir = Path::new(PIXMAPS_DIR);
+    let exts: &[&str] = if prefer_svg {
+        &["svg", "png", "xpm"]
+    } else {
+        &["png", "svg", "xpm"]
+    };
+    exts.iter()
+        .map(|ext| dir.join(format!("{}.{}", icon, ext)))
+        .chain(std::iter::once(dir.join(icon)))
+        .find(|path| path.is_file())
+}
+
+/// How far `size` is from the sizes the icon can be used for.
+fn size_distance(icon: &IconPath, size: u16) -> u16 {
+    if size < icon.min_size {
+        icon.min_size - size
+    } else {
+        size.saturating_sub(icon.max_size)
+    }
+}
+
+/// Order of preference of an icon's file format, lower is better.
+fn format_rank(icon_type: &IconType, prefer_svg: bool) -> u8 {
+    match (icon_type, prefer_svg) {
+        (IconType::SVG, true) | (IconType::PNG, false) => 0,
+        (IconType::PNG, true) | (IconType::SVG, false) => 1,
+        (IconType::XMP, _) => 2,
+    }
+}
+
+/// Icon name, size and scale of an icon lookup.
+type IconKey = (String, u16, u16);
+
+/// Cache of icon lookups keyed by icon, size and scale.
+///
+/// Clones share the same cache.
+#[derive(Debug, Clone, Default)]
+pub struct IconCache {
+    cache: Arc<RwLock<HashMap<IconKey, Option<PathBuf>>>>,
+}
+
+impl IconCache {
+    /// Get the icon from the cache or look it up with `lookup` if the icon
+    /// isn't cached yet.
+    pub fn get_or_insert_with(
+        &self,
+        icon: &str,
+        size: u16,
+        scale: u16,
+        lookup: impl FnOnce() -> Option<PathBuf>,
+    ) -> Option<PathBuf> {
+        let key = (icon.to_owned(), size, scale);
+        if let Some(path) = self.cache.read().unwrap().get(&key) {
+            return path.clone();
+        }
+        let path = lookup();
+        self.cache.write().unwrap().insert(key, path.clone());
+        path
+    }
+
+    /// Remove all cached lookups.
+    pub fn clear(&self) {
+        self.cache.write().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::fs;
+
+    #[test]
+    fn absolute_path() {
+        assert_eq!(
+            find_icon("/bin/sh", 48, 1, None, false),
+            Some(PathBuf::from("/bin/sh"))
+        );
+        assert_eq!(
+            find_icon("/poki/not/an/icon.png", 48, 1, None, false),
+            None
+        );
+        assert_eq!(find_icon("", 48, 1, None, false), None);
+    }
+
+    #[test]
+    fn theme_lookup() {
+        let icon_dir = TempDir::new("icon");
+        let theme_dir = icon_dir.join("poki-test");
+        for dir in &["16x16/apps", "48x48/apps", "scalable/apps"] {
+            fs::create_dir_all(theme_dir.join(dir)).unwrap();
+        }
+        fs::write(
+            theme_dir.join("index.theme"),
+            "[Icon Theme]
+Name=Poki Test
+Directories=16x16/apps,48x48/apps,scalable/apps
+
+[16x16/apps]
+Size=16
+Type=Fixed
+
+[48x48/apps]
+Size=48
+Type=Fixed
+
+[scalable/apps]
+Size=128
+MinSize=8
+MaxSize=512
+Type=Scalable
+",
+        )
+        .unwrap();
+        fs::write(theme_dir.join("16x16/apps/poki-app.png"), "").unwrap();
+        fs::write(theme_dir.join("48x48/apps/poki-app.png"), "").unwrap();
+        fs::write(theme_dir.join("scalable/apps/poki-app.svg"), "").unwrap();
+        fs::write(theme_dir.join("16x16/apps/poki-small.png"), "").unwrap();
+        let icon_dirs = [icon_dir.to_path_buf()];
+
+        let find = |icon, size, prefer_svg| {
+            find_icon_in(
+                icon,
+                size,
+                1,
+                Some("poki-test"),
+                prefer_svg,
+                &icon_dirs,
+            )
+        };
+        assert_eq!(
+            find("poki-app", 48, false),
+            Some(theme_dir.join("48x48/apps/poki-app.png"))
+        );
+        assert_eq!(
+            find("poki-app", 48, true),
+            Some(theme_dir.join("scalable/apps/poki-app.svg"))
+        );
+        assert_eq!(
+            find("poki-app", 16, false),
+            Some(theme_dir.join("16x16/apps/poki-app.png"))
+        );
+        assert_eq!(
+            find("poki-small", 48, true),
+            Some(theme_dir.join("16x16/apps/poki-small.png"))
+        );
+        assert_eq!(find("poki-missing", 48, true), None);
+    }
+
+    #[test]
+    fn cache() {
+        let cache = IconCache::default();
+        let path = cache.get_or_insert_with("app", 48, 1, || {
+            Some(PathBuf::from("/icon.png"))
+        });
+        assert_eq!(path, Some(PathBuf::from("/icon.png")));
+        let path = cache.get_or_insert_with("app", 48, 1, || {
+            panic!("lookup should be cached")
+        });
+        assert_eq!(path, Some(PathBuf::from("/icon.png")));
+        cache.clear();
+        assert_eq!(cache.get_or_insert_with("app", 48, 1, || None), None);
+    }
+}
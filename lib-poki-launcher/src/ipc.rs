@@ -0,0 +1,298 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Protocol spoken between the launcher daemon and its clients.
+//!
+//! Clients connect to the Unix socket of the daemon, see `socket_path`, and
+//! send requests, each of which is answered by one response. Every message
+//! is a frame of a 4 byte big endian length followed by that many bytes of
+//! msgpack, encoded with field names so fields can be added without breaking
+//! older clients.
+//!
+//! Requests carry the `PROTOCOL_VERSION` of the client. The daemon answers
+//! requests with another version with `Response::UnsupportedVersion`.
+use crate::{AppKey, SearchResult, DIRS};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Version of the protocol, changed on incompatible changes.
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Largest frame that is read, 16 MiB.
+const MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Path to the socket of the daemon.
+///
+/// `daemon.sock` in the runtime dir, or in the cache dir if
+/// `$XDG_RUNTIME_DIR` isn't set.
+pub fn socket_path() -> PathBuf {
+    DIRS.runtime_dir()
+        .unwrap_or_else(|| DIRS.cache_dir())
+        .join("daemon.sock")
+}
+
+/// A request to the daemon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    /// Rank the apps, see `AppsDB::get_ranked_list`.
+    Search {
+        query: Option<String>,
+        limit: Option<usize>,
+    },
+    /// Launch an app and record the launch, see `AppsDB::record_launch`.
+    Launch {
+        key: AppKey,
+        /// The search the app was launched from.
+        query: Option<String>,
+        /// Activation token passed to the app, see `RunOptions`.
+        activation_token: Option<String>,
+    },
+    /// Scan for desktop entries again.
+    Rescan,
+}
+
+/// A response of the daemon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response {
+    /// The apps found by `Request::Search`.
+    Apps(Vec<SearchResult>),
+    /// The app of `Request::Launch` was started.
+    Launched,
+    /// The apps were rescanned.
+    Rescanned {
+        /// Number of apps found.
+        apps: usize,
+        /// Errors encountered while scanning.
+        errors: Vec<String>,
+    },
+    /// The request failed.
+    Error(String),
+    /// The request had another protocol version than the daemon's.
+    UnsupportedVersion(u32),
+}
+
+/// A request with the protocol version of the client.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RequestFrame {
+    pub version: u32,
+    pub request: Request,
+}
+
+/// Just the version of a `RequestFrame`, so the version can be checked
+/// before the request is decoded.
+#[derive(Debug, Deserialize)]
+pub(crate) struct VersionFrame {
+    pub version: u32,
+}
+
+/// Write `message` as a frame to `writer`.
+pub(crate) fn write_frame(
+    writer: &mut impl Write,
+    message: &impl Serialize,
+) -> Result<(), IpcError> {
+    let buf = rmp_serde::to_vec_named(message)?;
+    let len = u32::try_from(buf.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME_SIZE)
+        .ok_or(IpcError::FrameTooLarge(buf.len()))?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(&buf)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read a frame from `reader`.
+///
+/// Returns `Ok(None)` if the connection was closed before the frame.
+pub(crate) fn read_frame(
+    reader: &mut impl Read,
+) -> Result<Option<Vec<u8>>, IpcError> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            return Ok(None)
+        }
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME_SIZE {
+        return Err(IpcError::FrameTooLarge(len as usize));
+    }
+    let mut buf = vec![0; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(Some(buf))
+}
+
+/// Decode a message read with `read_frame`.
+pub(crate) fn decode<T: DeserializeOwned>(buf: &[u8]) -> Result<T, IpcError> {
+    Ok(rmp_serde::from_slice(buf)?)
+}
+
+/// A connection to the daemon.
+#[derive(Debug)]
+pub struct Client {
+    stream: UnixStream,
+}
+
+impl Client {
+    /// Connect to the daemon at the default `socket_path`.
+    pub fn connect() -> Result<Client, IpcError> {
+        Client::connect_to(&socket_path())
+    }
+
+    /// Connect to the daemon listening on `path`.
+    pub fn connect_to(path: &Path) -> Result<Client, IpcError> {
+        let stream =
+            UnixStream::connect(path).map_err(|err| IpcError::Connect {
+                path: path.to_owned(),
+                err,
+            })?;
+        Ok(Client { stream })
+    }
+
+    /// Send `request` and wait for the response.
+    ///
+    /// `Response::Error` and `Response::UnsupportedVersion` are returned as
+    /// errors.
+    pub fn request(&mut self, request: Request) -> Result<Response, IpcError> {
+        let frame = RequestFrame {
+            version: PROTOCOL_VERSION,
+            request,
+        };
+        write_frame(&mut self.stream, &frame)?;
+        let buf = read_frame(&mut self.stream)?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        match decode(&buf)? {
+            Response::Error(err) => Err(IpcError::Daemon(err)),
+            Response::UnsupportedVersion(version) => {
+                Err(IpcError::UnsupportedVersion(version))
+            }
+            response => Ok(response),
+        }
+    }
+
+    /// Rank the apps for `query`, see `AppsDB::get_ranked_list`.
+    pub fn search(
+        &mut self,
+        query: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<SearchResult>, IpcError> {
+        let request = Request::Search {
+            query: query.map(str::to_owned),
+            limit,
+        };
+        match self.request(request)? {
+            Response::Apps(apps) => Ok(apps),
+            _ => Err(IpcError::UnexpectedResponse),
+        }
+    }
+
+    /// Launch the app identified by `key` and record the launch.
+    pub fn launch(
+        &mut self,
+        key: AppKey,
+        query: Option<&str>,
+        activation_token: Option<String>,
+    ) -> Result<(), IpcError> {
+        let request = Request::Launch {
+            key,
+            query: query.map(str::to_owned),
+            activation_token,
+        };
+        match self.request(request)? {
+            Response::Launched => Ok(()),
+            _ => Err(IpcError::UnexpectedResponse),
+        }
+    }
+
+    /// Scan for desktop entries again.
+    ///
+    /// Returns the number of apps and the scan errors.
+    pub fn rescan(&mut self) -> Result<(usize, Vec<String>), IpcError> {
+        match self.request(Request::Rescan)? {
+            Response::Rescanned { apps, errors } => Ok((apps, errors)),
+            _ => Err(IpcError::UnexpectedResponse),
+        }
+    }
+}
+
+/// Error talking to the daemon.
+#[derive(Debug, Error)]
+pub enum IpcError {
+    #[error("Failed to connect to daemon socket {}: {err}", .path.display())]
+    Connect { path: PathBuf, err: io::Error },
+    #[error("Daemon connection error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to encode message: {0}")]
+    Encode(#[from] rmp_serde::encode::Error),
+    #[error("Failed to decode message: {0}")]
+    Decode(#[from] rmp_serde::decode::Error),
+    #[error("Message of {0} bytes is too large")]
+    FrameTooLarge(usize),
+    #[error(
+        "Daemon speaks protocol version {0} instead of {}",
+        PROTOCOL_VERSION
+    )]
+    UnsupportedVersion(u32),
+    #[error("Daemon error: {0}")]
+    Daemon(String),
+    #[error("Unexpected response from the daemon")]
+    UnexpectedResponse,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames() {
+        let request = RequestFrame {
+            version: PROTOCOL_VERSION,
+            request: Request::Search {
+                query: Some("fire".to_owned()),
+                limit: None,
+            },
+        };
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &request).unwrap();
+        write_frame(&mut buf, &Response::Launched).unwrap();
+        let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        assert_eq!(
+            len as usize,
+            rmp_serde::to_vec_named(&request).unwrap().len()
+        );
+
+        let mut reader = &buf[..];
+        let frame = read_frame(&mut reader).unwrap().unwrap();
+        let version: VersionFrame = decode(&frame).unwrap();
+        assert_eq!(version.version, PROTOCOL_VERSION);
+        let decoded: RequestFrame = decode(&frame).unwrap();
+        assert_eq!(decoded.request, request.request);
+        let frame = read_frame(&mut reader).unwrap().unwrap();
+        assert!(matches!(decode(&frame).unwrap(), Response::Launched));
+        assert!(read_frame(&mut reader).unwrap().is_none());
+
+        let mut reader = &(MAX_FRAME_SIZE + 1).to_be_bytes()[..];
+        assert!(matches!(
+            read_frame(&mut reader),
+            Err(IpcError::FrameTooLarge(_))
+        ));
+    }
+}
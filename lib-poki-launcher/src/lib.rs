@@ -14,17 +14,58 @@
  * You should have received a copy of the GNU General Public License
  * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
  */
+/// Log files of launched apps
+mod app_log;
 /// Application configuration
 mod config;
 /// Interact with the app database
 mod db;
 /// Parse desktop entries
 mod desktop_entry;
+/// Parse Exec keys
+mod exec;
+/// Apps database file format
+mod format;
+/// App launch history
+mod history;
+/// Find icon files
+mod icon;
+/// Pick localized values
+mod locale;
+/// Associations between MIME types and apps
+mod mime;
+/// Storage locations of launcher profiles
+mod profile;
 /// Run an app
 mod runner;
 /// Scan for desktop entries
 mod scan;
+/// Cache of parsed desktop entries
+mod scan_cache;
+/// Files and URIs to open with an app
+mod target;
+/// Find the terminal to run terminal apps in
+mod terminal;
+/// Fixtures shared by the tests
+#[cfg(test)]
+mod test_util;
+/// Themes of the launcher window
+mod theme;
+/// XDG base directories
+mod xdg;
+/// Start apps on login
+pub mod autostart;
+/// Resident launcher daemon
+pub mod daemon;
 pub mod hot_reload;
+/// Protocol between the daemon and its clients
+pub mod ipc;
+/// Match searches against apps
+pub mod matcher;
+/// Rank apps
+pub mod rank;
+/// Search providers
+pub mod search;
 
 use directories_next::ProjectDirs;
 use lazy_static::lazy_static;
@@ -36,15 +77,25 @@ use std::{
 };
 use uuid::Uuid;
 
-pub use crate::config::Config;
-pub use crate::db::AppsDB;
+pub use crate::config::{AppEnv, Config};
+pub use crate::db::{AppsDB, SearchResult};
+pub use crate::history::{AppHistory, Launch};
+pub use crate::mime::{mimeapps_lists, MimeIndex};
+pub use crate::profile::{LockStrategy, Profile};
+pub use crate::runner::{RunOptions, Running, START_GRACE_PERIOD};
+pub use crate::target::Target;
+pub use crate::theme::{ColorScheme, Rgba, Theme};
 
 /// Custom error types
 pub mod error {
+    pub use crate::config::ConfigError;
     pub use crate::db::AppDBError;
     pub use crate::desktop_entry::EntryParseError;
+    pub use crate::exec::ExecError;
+    pub use crate::mime::MimeError;
     pub use crate::runner::RunError;
     pub use crate::scan::ScanError;
+    pub use crate::theme::{ParseColorError, ThemeError};
 }
 
 lazy_static! {
@@ -56,14 +107,14 @@ lazy_static! {
     static ref DIRS: ProjectDirs =
         ProjectDirs::from("dev", "Ben Aaron Goldberg", "Poki-Launcher")
             .unwrap();
-    /// Path to the DB file
+    /// Path to the DB file of the default profile
     pub static ref DB_PATH: PathBuf = {
         let data_dir = DIRS.data_dir();
         let mut db_path = data_dir.to_path_buf();
         db_path.push("apps.db");
         db_path
     };
-    /// Path to the config file
+    /// Path to the config file of the default profile
     pub static ref CFG_PATH: PathBuf = {
         let config_dir = DIRS.config_dir();
         config_dir.join("poki-launcher.hjson")
@@ -88,6 +139,141 @@ pub struct App {
     pub icon: String,
     /// If true, launch in terminal
     pub(crate) terminal: bool,
+    /// Desktop actions of this app, e.g. "New Private Window".
+    #[serde(default)]
+    pub actions: Vec<AppAction>,
+    /// Id of the desktop action this app launches.
+    ///
+    /// This is only set on the entries for actions returned by
+    /// `AppsDB::get_ranked_list` and `App::action`.
+    #[serde(default)]
+    pub action: Option<String>,
+    /// Generic name of the app, ex. "Web Browser".
+    #[serde(default)]
+    pub generic_name: String,
+    /// Tooltip for the app, ex. "Browse the World Wide Web".
+    #[serde(default)]
+    pub comment: String,
+    /// Keywords that describe the app, used for searching.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Path to the desktop entry this app was parsed from.
+    #[serde(default)]
+    pub entry_path: PathBuf,
+    /// Desktop environments this app should only be shown in.
+    #[serde(default)]
+    pub(crate) only_show_in: Vec<String>,
+    /// Desktop environments this app should not be shown in.
+    #[serde(default)]
+    pub(crate) not_show_in: Vec<String>,
+    /// Program that has to be installed for this app to be shown.
+    #[serde(default)]
+    pub(crate) try_exec: Option<String>,
+    /// Desktop file ID of the entry this app was parsed from,
+    /// ex. `org.gnome.Nautilus.desktop`.
+    #[serde(default)]
+    pub id: String,
+    /// Where files passed to the app go in `exec`, if it accepts files.
+    #[serde(default)]
+    pub file_arg: Option<FileArg>,
+    /// Directory to run the app in, from the `Path` key.
+    #[serde(default)]
+    pub working_dir: Option<PathBuf>,
+    /// Whether the app is started with D-Bus activation.
+    #[serde(default)]
+    pub dbus_activatable: bool,
+    /// MIME types the app can open, ex. `text/plain`.
+    #[serde(default)]
+    pub mime_types: Vec<String>,
+}
+
+/// Identifies an app across rescans of the desktop entries.
+///
+/// Made of the desktop file ID of the app's entry and the desktop action the
+/// app launches, if any.
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+)]
+pub struct AppKey {
+    /// Desktop file ID of the app's entry.
+    pub id: String,
+    /// Id of the desktop action the app launches.
+    pub action: Option<String>,
+}
+
+impl From<&str> for AppKey {
+    /// Parse a key in the form it is displayed in, `<id>` or
+    /// `<id>:<action>`.
+    fn from(key: &str) -> Self {
+        match key.split_once(':') {
+            Some((id, action)) => AppKey {
+                id: id.to_owned(),
+                action: Some(action.to_owned()),
+            },
+            None => AppKey {
+                id: key.to_owned(),
+                action: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for AppKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.action {
+            Some(action) => write!(f, "{}:{}", self.id, action),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
+
+/// An additional way of launching an app listed in its desktop entry.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppAction {
+    /// Identifier of the action from the `Actions` key.
+    pub id: String,
+    /// Display name of the action.
+    pub name: String,
+    /// Icon name for this action.
+    /// Falls back to the icon of the app if the action doesn't have one.
+    pub icon: String,
+    /// The exec string used to run the action.
+    pub exec: Vec<String>,
+    /// Where files passed to the action go in `exec`, if it accepts files.
+    #[serde(default)]
+    pub file_arg: Option<FileArg>,
+}
+
+/// The position of the `%f`, `%F`, `%u` or `%U` field code in the exec
+/// string of an app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileArg {
+    /// Index in `exec` the files are inserted at.
+    pub index: usize,
+    /// Which field code was used.
+    pub kind: FileArgKind,
+}
+
+/// The file field codes of an exec string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileArgKind {
+    /// `%f`, a single file path, one instance is started per file.
+    File,
+    /// `%F`, a list of file paths.
+    Files,
+    /// `%u`, a single URL, one instance is started per URL.
+    Url,
+    /// `%U`, a list of URLs.
+    Urls,
 }
 
 impl App {
@@ -105,14 +291,76 @@ impl App {
             uuid: Uuid::new_v4().to_string(),
             score: 0.0,
             terminal,
+            actions: Vec::new(),
+            action: None,
+            generic_name: String::new(),
+            comment: String::new(),
+            keywords: Vec::new(),
+            entry_path: PathBuf::new(),
+            only_show_in: Vec::new(),
+            not_show_in: Vec::new(),
+            try_exec: None,
+            id: String::new(),
+            file_arg: None,
+            working_dir: None,
+            dbus_activatable: false,
+            mime_types: Vec::new(),
+        }
+    }
+
+    /// Get an app that launches the desktop action `id` of this app.
+    ///
+    /// The returned app is named "App Name: Action Name" and shares the uuid
+    /// of this app, so launching it counts as a launch of this app.
+    pub fn action(&self, id: &str) -> Option<App> {
+        self.actions
+            .iter()
+            .find(|action| action.id == id)
+            .map(|action| self.with_action(action))
+    }
+
+    /// Build the app entry for one of this app's actions.
+    pub(crate) fn with_action(&self, action: &AppAction) -> App {
+        App {
+            name: format!("{}: {}", self.name, action.name),
+            exec: action.exec.clone(),
+            file_arg: action.file_arg,
+            icon: action.icon.clone(),
+            actions: Vec::new(),
+            action: Some(action.id.clone()),
+            generic_name: String::new(),
+            comment: String::new(),
+            keywords: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Find the file for this app's icon.
+    ///
+    /// Looks up the icon in the user's icon theme and the themes it inherits
+    /// from, then falls back to the `hicolor` theme and `/usr/share/pixmaps`.
+    /// Use `AppsDB::icon_path` to respect the icon settings in the config and
+    /// cache the lookups.
+    pub fn icon_path(&self, size: u16, scale: u16) -> Option<PathBuf> {
+        icon::find_icon(&self.icon, size, scale, None, false)
+    }
+
+    /// Get the key that identifies this app across rescans.
+    pub fn key(&self) -> AppKey {
+        AppKey {
+            id: self.id.clone(),
+            action: self.action.clone(),
         }
     }
 
-    /// Set this app's name, icon, and exec to the values of the other app.
+    /// Set everything but this app's score and uuid to the values of the
+    /// other app.
     pub fn merge(&mut self, other: &App) {
-        self.name = other.name.clone();
-        self.icon = other.icon.clone();
-        self.exec = other.exec.clone();
+        *self = App {
+            score: self.score,
+            uuid: std::mem::take(&mut self.uuid),
+            ..other.clone()
+        };
     }
 }
 
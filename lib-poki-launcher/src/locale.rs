@@ -0,0 +1,117 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::env;
+
+/// A POSIX locale, used to pick localized values from desktop entries.
+///
+/// See the "Localized values for keys" section of the desktop entry spec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale {
+    lang: String,
+    country: Option<String>,
+    modifier: Option<String>,
+}
+
+impl Locale {
+    /// Get the locale used for messages from the environment.
+    ///
+    /// The first non-empty value of `LC_ALL`, `LC_MESSAGES` and `LANG` is
+    /// used. Returns `None` if none are set or the locale is `C` or `POSIX`.
+    pub fn from_env() -> Option<Locale> {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Locale::parse(&value))
+    }
+
+    /// Parse a locale of the form `lang_COUNTRY.ENCODING@MODIFIER`.
+    ///
+    /// Everything but `lang` is optional and the encoding is ignored.
+    pub fn parse(s: &str) -> Option<Locale> {
+        let (rest, modifier) = match s.find('@') {
+            Some(idx) => (&s[..idx], Some(s[idx + 1..].to_owned())),
+            None => (s, None),
+        };
+        let rest = rest.split('.').next().unwrap_or(rest);
+        let (lang, country) = match rest.find('_') {
+            Some(idx) => (&rest[..idx], Some(rest[idx + 1..].to_owned())),
+            None => (rest, None),
+        };
+        if lang.is_empty() || lang == "C" || lang == "POSIX" {
+            return None;
+        }
+        Some(Locale {
+            lang: lang.to_owned(),
+            country: country.filter(|c| !c.is_empty()),
+            modifier: modifier.filter(|m| !m.is_empty()),
+        })
+    }
+
+    /// The locale suffixes to look for, most specific first.
+    ///
+    /// For `lang_COUNTRY@MODIFIER` this is `lang_COUNTRY@MODIFIER`,
+    /// `lang_COUNTRY`, `lang@MODIFIER` then `lang`.
+    pub fn candidates(&self) -> Vec<String> {
+        let mut candidates = Vec::with_capacity(4);
+        if let Some(country) = &self.country {
+            if let Some(modifier) = &self.modifier {
+                candidates
+                    .push(format!("{}_{}@{}", self.lang, country, modifier));
+            }
+            candidates.push(format!("{}_{}", self.lang, country));
+        }
+        if let Some(modifier) = &self.modifier {
+            candidates.push(format!("{}@{}", self.lang, modifier));
+        }
+        candidates.push(self.lang.clone());
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_full() {
+        let locale = Locale::parse("sr_YU.UTF-8@Latn").unwrap();
+        assert_eq!(
+            locale.candidates(),
+            vec!["sr_YU@Latn", "sr_YU", "sr@Latn", "sr"]
+        );
+    }
+
+    #[test]
+    fn parse_country() {
+        let locale = Locale::parse("de_DE.UTF-8").unwrap();
+        assert_eq!(locale.candidates(), vec!["de_DE", "de"]);
+    }
+
+    #[test]
+    fn parse_modifier() {
+        let locale = Locale::parse("ca@valencia").unwrap();
+        assert_eq!(locale.candidates(), vec!["ca@valencia", "ca"]);
+    }
+
+    #[test]
+    fn parse_posix() {
+        assert_eq!(Locale::parse("C"), None);
+        assert_eq!(Locale::parse("C.UTF-8"), None);
+        assert_eq!(Locale::parse("POSIX"), None);
+    }
+}
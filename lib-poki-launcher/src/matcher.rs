@@ -0,0 +1,210 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Matching of searches against the apps in `AppsDB::get_ranked_list`.
+//!
+//! The matcher is picked with the `matcher` setting in the config. All
+//! matchers are case insensitive unless the search contains upper case
+//! letters, and report the positions of the matched characters so they can
+//! be highlighted.
+use fuzzy_matcher::clangd::ClangdMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use serde::{Deserialize, Serialize};
+
+/// Score of each matched character of the `Prefix` and `Substring`
+/// matchers, the same as a skim match without bonuses.
+const SCORE_MATCH: i64 = 16;
+/// Bonus of `Prefix` and `Substring` matches at the start of the text.
+const BONUS_START: i64 = SCORE_MATCH;
+/// Bonus of `Prefix` and `Substring` matches at the start of a word.
+const BONUS_WORD: i64 = SCORE_MATCH / 2;
+
+/// The built in matchers.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MatcherKind {
+    /// Fuzzy matching like the skim fuzzy finder, version 2 of its
+    /// algorithm.
+    #[default]
+    Skim,
+    /// Fuzzy matching like the completion of clangd, which favors matches
+    /// at the start of words.
+    Clangd,
+    /// See `Prefix`.
+    Prefix,
+    /// See `Substring`.
+    Substring,
+}
+
+impl MatcherKind {
+    /// Create the matcher.
+    pub fn build(self) -> Box<dyn FuzzyMatcher> {
+        match self {
+            MatcherKind::Skim => Box::new(SkimMatcherV2::default()),
+            MatcherKind::Clangd => Box::new(ClangdMatcher::default()),
+            MatcherKind::Prefix => Box::new(Prefix),
+            MatcherKind::Substring => Box::new(Substring),
+        }
+    }
+}
+
+/// Match searches at the start of the text or of one of its words, ex.
+/// "fox" matches "Fox Browser" and "Firefox Fox" but not "Firefox".
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Prefix;
+
+impl FuzzyMatcher for Prefix {
+    fn fuzzy_indices(
+        &self,
+        choice: &str,
+        pattern: &str,
+    ) -> Option<(i64, Vec<usize>)> {
+        find(choice, pattern, true)
+    }
+}
+
+/// Match searches anywhere in the text, ex. "fox" matches "Firefox".
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Substring;
+
+impl FuzzyMatcher for Substring {
+    fn fuzzy_indices(
+        &self,
+        choice: &str,
+        pattern: &str,
+    ) -> Option<(i64, Vec<usize>)> {
+        find(choice, pattern, false)
+    }
+}
+
+/// Find the first occurrence of `pattern` in `choice`, only at the start of
+/// words if `word_start` is set.
+///
+/// Matches at the start of the text or a word score higher. The indices
+/// are character indices like the ones of the fuzzy matchers.
+fn find(
+    choice: &str,
+    pattern: &str,
+    word_start: bool,
+) -> Option<(i64, Vec<usize>)> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    if pattern.is_empty() {
+        return None;
+    }
+    let ignore_case = !pattern.iter().any(|c| c.is_uppercase());
+    let chars_eq = |c: char, p: char| {
+        c == p || ignore_case && c.to_lowercase().eq(p.to_lowercase())
+    };
+    let choice: Vec<char> = choice.chars().collect();
+    let mut best: Option<(i64, usize)> = None;
+    for start in 0..choice.len().saturating_sub(pattern.len() - 1) {
+        let bonus = if start == 0 {
+            BONUS_START
+        } else if !choice[start - 1].is_alphanumeric() {
+            BONUS_WORD
+        } else if word_start {
+            continue;
+        } else {
+            0
+        };
+        if best.is_some_and(|(best, _)| best >= bonus) {
+            continue;
+        }
+        let matches = choice[start..]
+            .iter()
+            .zip(&pattern)
+            .all(|(c, p)| chars_eq(*c, *p));
+        if matches {
+            best = Some((bonus, start));
+        }
+    }
+    best.map(|(bonus, start)| {
+        let score = SCORE_MATCH * pattern.len() as i64 + bonus;
+        (score, (start..start + pattern.len()).collect())
+    })
+}
+
+/// The field of an app a search matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchField {
+    /// `App::name`.
+    Name,
+    /// `App::generic_name`.
+    GenericName,
+    /// The keyword of `App::keywords` at this index.
+    Keyword(usize),
+    /// `App::comment`.
+    Comment,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substring() {
+        let matcher = MatcherKind::Substring.build();
+        assert_eq!(
+            matcher.fuzzy_indices("Firefox", "fox"),
+            Some((3 * SCORE_MATCH, vec![4, 5, 6]))
+        );
+        assert_eq!(
+            matcher.fuzzy_indices("Fox Firefox", "fox"),
+            Some((3 * SCORE_MATCH + BONUS_START, vec![0, 1, 2]))
+        );
+        assert_eq!(
+            matcher.fuzzy_indices("Firefox - Fox", "fox"),
+            Some((3 * SCORE_MATCH + BONUS_WORD, vec![10, 11, 12]))
+        );
+        assert_eq!(matcher.fuzzy_indices("Firefox", "Fox"), None);
+        assert_eq!(matcher.fuzzy_indices("Firefox", "ffx"), None);
+        assert_eq!(matcher.fuzzy_indices("Fox", "foxes"), None);
+        assert_eq!(matcher.fuzzy_indices("Fox", ""), None);
+        assert_eq!(
+            matcher.fuzzy_indices("Éditeur Été", "été"),
+            Some((3 * SCORE_MATCH + BONUS_WORD, vec![8, 9, 10]))
+        );
+    }
+
+    #[test]
+    fn prefix() {
+        let matcher = MatcherKind::Prefix.build();
+        assert_eq!(matcher.fuzzy_indices("Firefox", "fox"), None);
+        assert_eq!(
+            matcher.fuzzy_indices("Firefox", "fire"),
+            Some((4 * SCORE_MATCH + BONUS_START, vec![0, 1, 2, 3]))
+        );
+        assert_eq!(
+            matcher.fuzzy_indices("Web Browser", "brow"),
+            Some((4 * SCORE_MATCH + BONUS_WORD, vec![4, 5, 6, 7]))
+        );
+    }
+
+    #[test]
+    fn fuzzy() {
+        for kind in &[MatcherKind::Skim, MatcherKind::Clangd] {
+            let matcher = kind.build();
+            let (_, indices) =
+                matcher.fuzzy_indices("Web Browser", "wbr").unwrap();
+            assert_eq!(indices, vec![0, 4, 5]);
+            assert_eq!(matcher.fuzzy_indices("Web Browser", "xyz"), None);
+        }
+    }
+}
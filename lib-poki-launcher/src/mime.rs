@@ -0,0 +1,702 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Associations between MIME types and apps, see the XDG "Association
+//! between MIME types and applications" spec.
+//!
+//! The apps that open a MIME type are the ones listing it in the `MimeType`
+//! key of their desktop entry, plus and minus the associations added and
+//! removed in the `mimeapps.list` files of the user and the system, which
+//! also pick the default app of MIME types.
+use crate::locale::Locale;
+use crate::scan::{current_desktops, desktop_entires, filter_app, ScanError};
+use crate::scan_cache::ScanCache;
+use crate::{xdg, App};
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use thiserror::Error;
+
+/// Group of `mimeapps.list` with the default app of MIME types.
+const DEFAULT_GROUP: &str = "Default Applications";
+/// Group of `mimeapps.list` with apps associated with MIME types.
+const ADDED_GROUP: &str = "Added Associations";
+/// Group of `mimeapps.list` with apps no longer associated with MIME types.
+const REMOVED_GROUP: &str = "Removed Associations";
+
+/// The groups of a `mimeapps.list` file, each mapping MIME types to desktop
+/// file IDs.
+type MimeAppsList = HashMap<String, HashMap<String, Vec<String>>>;
+
+/// Paths of the `mimeapps.list` files, highest precedence first.
+///
+/// The files are read from $XDG_CONFIG_HOME, $XDG_CONFIG_DIRS and the
+/// `applications` dir in $XDG_DATA_HOME and $XDG_DATA_DIRS. In each dir the
+/// `<desktop>-mimeapps.list` of each of `desktops`, ex. `sway-mimeapps.list`,
+/// comes before `mimeapps.list`.
+pub fn mimeapps_lists(desktops: &[String]) -> Vec<PathBuf> {
+    let data_dirs = xdg::data_home()
+        .into_iter()
+        .chain(xdg::data_dirs())
+        .map(|dir| dir.join("applications"));
+    let names: Vec<String> = desktops
+        .iter()
+        .map(|desktop| format!("{}-mimeapps.list", desktop.to_lowercase()))
+        .chain(std::iter::once("mimeapps.list".to_owned()))
+        .collect();
+    xdg::config_home()
+        .into_iter()
+        .chain(xdg::config_dirs())
+        .chain(data_dirs)
+        .flat_map(|dir| {
+            names
+                .iter()
+                .map(move |name| dir.join(name))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Index of the apps that open each MIME type.
+///
+/// MIME types are matched case insensitively, but aliases and subclasses
+/// of MIME types aren't resolved.
+#[derive(Debug, Clone, Default)]
+pub struct MimeIndex {
+    /// The apps by desktop file ID.
+    apps: HashMap<String, App>,
+    /// Desktop file IDs of the apps associated with each MIME type, most
+    /// preferred first.
+    associations: HashMap<String, Vec<String>>,
+    /// Desktop file ID of the default app of each MIME type.
+    defaults: HashMap<String, String>,
+    /// The `mimeapps.list` file changed by `MimeIndex::set_default`.
+    user_list: Option<PathBuf>,
+}
+
+impl MimeIndex {
+    /// Build the index from the desktop entries in `app_paths`, ex. the
+    /// `app_paths` of the config, and the `mimeapps.list` files of the
+    /// current desktops.
+    ///
+    /// Returns the index and the errors encountered while building it,
+    /// which can generally be ignored but you might want to log them.
+    pub fn load(app_paths: &[PathBuf]) -> (MimeIndex, Vec<MimeError>) {
+        MimeIndex::load_cached(app_paths, None)
+    }
+
+    /// Build the index like `MimeIndex::load`, reusing the desktop entries
+    /// parsed in earlier scans from the scan cache at `cache_path`, if any.
+    pub(crate) fn load_cached(
+        app_paths: &[PathBuf],
+        cache_path: Option<&Path>,
+    ) -> (MimeIndex, Vec<MimeError>) {
+        let lists = mimeapps_lists(&current_desktops());
+        let user_list = xdg::config_home().map(|dir| dir.join("mimeapps.list"));
+        let mut cache = cache_path.map(ScanCache::load).unwrap_or_default();
+        let result = MimeIndex::build(app_paths, &lists, user_list, &mut cache);
+        if let Some(path) = cache_path {
+            if let Err(err) = cache.save(path) {
+                warn!("Failed to save scan cache {}: {}", path.display(), err);
+            }
+        }
+        result
+    }
+
+    /// Build the index from the desktop entries in `app_paths` and the
+    /// `mimeapps.list` files `lists`, highest precedence first.
+    ///
+    /// `set_default` writes to `user_list`. See `MimeIndex::load`.
+    pub fn load_from(
+        app_paths: &[PathBuf],
+        lists: &[PathBuf],
+        user_list: Option<PathBuf>,
+    ) -> (MimeIndex, Vec<MimeError>) {
+        MimeIndex::build(app_paths, lists, user_list, &mut ScanCache::default())
+    }
+
+    /// Build the index like `MimeIndex::load_from`, parsing the desktop
+    /// entries with `cache`.
+    fn build(
+        app_paths: &[PathBuf],
+        lists: &[PathBuf],
+        user_list: Option<PathBuf>,
+        cache: &mut ScanCache,
+    ) -> (MimeIndex, Vec<MimeError>) {
+        let mut index = MimeIndex {
+            user_list,
+            ..MimeIndex::default()
+        };
+        let (ids, mut errors) = index.load_apps(app_paths, cache);
+        let mut removed: HashMap<String, HashSet<String>> = HashMap::new();
+        // The defaults of each MIME type, highest precedence first
+        let mut defaults: HashMap<String, Vec<String>> = HashMap::new();
+        for path in lists {
+            let list = match fs::read_to_string(path) {
+                Ok(contents) => parse_mimeapps_list(&contents),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    errors.push(MimeError::Read {
+                        path: path.clone(),
+                        err,
+                    });
+                    continue;
+                }
+            };
+            for (mime, ids) in list.get(DEFAULT_GROUP).into_iter().flatten() {
+                defaults
+                    .entry(mime.clone())
+                    .or_default()
+                    .extend(ids.iter().cloned());
+            }
+            // Removed associations only apply to files with lower
+            // precedence
+            for (mime, ids) in list.get(ADDED_GROUP).into_iter().flatten() {
+                for id in ids {
+                    if !removed.get(mime).is_some_and(|ids| ids.contains(id)) {
+                        index.associate(mime, id);
+                    }
+                }
+            }
+            for (mime, ids) in list.get(REMOVED_GROUP).into_iter().flatten() {
+                removed
+                    .entry(mime.clone())
+                    .or_default()
+                    .extend(ids.iter().cloned());
+            }
+        }
+        for id in ids {
+            let mimes: Vec<String> = index.apps[&id]
+                .mime_types
+                .iter()
+                .map(|mime| mime.to_lowercase())
+                .collect();
+            for mime in mimes {
+                if !removed.get(&mime).is_some_and(|ids| ids.contains(&id)) {
+                    index.associate(&mime, &id);
+                }
+            }
+        }
+        // The first default still associated with the MIME type wins, so a
+        // default is dropped with its association
+        for (mime, ids) in defaults {
+            let associated = match index.associations.get(&mime) {
+                Some(associated) => associated,
+                None => continue,
+            };
+            if let Some(id) = ids.into_iter().find(|id| associated.contains(id))
+            {
+                index.defaults.insert(mime, id);
+            }
+        }
+        (index, errors)
+    }
+
+    /// Parse the desktop entries in `app_paths` into `self.apps` with
+    /// `cache`.
+    ///
+    /// Entries with `NoDisplay` set are included as they can still open
+    /// files. Returns the desktop file IDs of the apps, highest precedence
+    /// first, and the scan errors.
+    fn load_apps(
+        &mut self,
+        app_paths: &[PathBuf],
+        cache: &mut ScanCache,
+    ) -> (Vec<String>, Vec<MimeError>) {
+        let (files, scan_errors) = desktop_entires(app_paths);
+        let mut errors: Vec<MimeError> =
+            scan_errors.into_iter().map(MimeError::from).collect();
+        let parsed = cache.parse_all(&files, Locale::from_env().as_ref());
+        let desktops = current_desktops();
+        let mut ids = Vec::new();
+        for (file, app) in files.into_iter().zip(parsed) {
+            let mut app = match app {
+                Ok(Some(app)) => app,
+                Ok(None) => continue,
+                Err(err) => {
+                    errors.push(ScanError::ParseEntry { err }.into());
+                    continue;
+                }
+            };
+            if let Err(err) = filter_app(&app, &desktops) {
+                errors.push(err.into());
+                continue;
+            }
+            app.id = file.id.clone();
+            self.apps.insert(file.id.clone(), app);
+            ids.push(file.id);
+        }
+        (ids, errors)
+    }
+
+    /// Associate the app `id` with `mime`, if it's installed.
+    fn associate(&mut self, mime: &str, id: &str) {
+        if !self.apps.contains_key(id) {
+            return;
+        }
+        let ids = self.associations.entry(mime.to_owned()).or_default();
+        if !ids.iter().any(|other| other == id) {
+            ids.push(id.to_owned());
+        }
+    }
+
+    /// The app with the desktop file ID `id`.
+    pub fn app(&self, id: &str) -> Option<&App> {
+        self.apps.get(id)
+    }
+
+    /// The apps that open `mime`, ex. for an "Open with" menu.
+    ///
+    /// The default app comes first, followed by the other apps, most
+    /// preferred first.
+    pub fn apps_for_mime(&self, mime: &str) -> Vec<&App> {
+        let mime = mime.to_lowercase();
+        let default = self.defaults.get(&mime);
+        let others = self
+            .associations
+            .get(&mime)
+            .into_iter()
+            .flatten()
+            .filter(|id| Some(*id) != default);
+        default
+            .into_iter()
+            .chain(others)
+            .filter_map(|id| self.apps.get(id))
+            .collect()
+    }
+
+    /// The app that opens `mime` by default.
+    ///
+    /// This is the default app set in the `mimeapps.list` files, or the
+    /// most preferred app associated with `mime` if there is none.
+    pub fn default_app_for(&self, mime: &str) -> Option<&App> {
+        let mime = mime.to_lowercase();
+        self.defaults
+            .get(&mime)
+            .or_else(|| self.associations.get(&mime)?.first())
+            .and_then(|id| self.apps.get(id))
+    }
+
+    /// Make the app `id` the default app of `mime`.
+    ///
+    /// This is saved in the user's `mimeapps.list`, where the app is also
+    /// added to the associations of `mime` and no longer removed from them.
+    /// A `<desktop>-mimeapps.list` of the user still takes precedence.
+    pub fn set_default(
+        &mut self,
+        mime: &str,
+        id: &str,
+    ) -> Result<(), MimeError> {
+        let mime = mime.to_lowercase();
+        if !self.apps.contains_key(id) {
+            return Err(MimeError::UnknownApp(id.to_owned()));
+        }
+        let path = self.user_list.as_ref().ok_or(MimeError::NoUserList)?;
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(MimeError::Read {
+                    path: path.clone(),
+                    err,
+                })
+            }
+        };
+        let contents = set_default_in(&contents, &mime, id);
+        write_list(path, &contents).map_err(|err| MimeError::Write {
+            path: path.clone(),
+            err,
+        })?;
+        self.defaults.insert(mime.clone(), id.to_owned());
+        let ids = self.associations.entry(mime).or_default();
+        ids.retain(|other| other != id);
+        ids.insert(0, id.to_owned());
+        Ok(())
+    }
+}
+
+/// Parse the contents of a `mimeapps.list` file.
+///
+/// MIME types are lower cased. Lines that aren't in a group or aren't
+/// `key=value` pairs are ignored.
+fn parse_mimeapps_list(contents: &str) -> MimeAppsList {
+    let mut list = MimeAppsList::new();
+    let mut group = None;
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            group = Some(name.to_owned());
+            continue;
+        }
+        if let (Some(group), Some((mime, ids))) = (&group, line.split_once('='))
+        {
+            list.entry(group.clone())
+                .or_default()
+                .insert(mime.trim().to_lowercase(), split_ids(ids));
+        }
+    }
+    list
+}
+
+/// Split a `;` separated list of desktop file IDs.
+fn split_ids(ids: &str) -> Vec<String> {
+    ids.split(';')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Make `id` the default app of `mime` in the contents of a `mimeapps.list`
+/// file, keeping the rest of the file as is.
+fn set_default_in(contents: &str, mime: &str, id: &str) -> String {
+    let mut lines: Vec<String> = contents.lines().map(str::to_owned).collect();
+    update_key(&mut lines, DEFAULT_GROUP, mime, |_| vec![id.to_owned()]);
+    update_key(&mut lines, ADDED_GROUP, mime, |mut ids| {
+        ids.retain(|other| other != id);
+        ids.insert(0, id.to_owned());
+        ids
+    });
+    update_key(&mut lines, REMOVED_GROUP, mime, |mut ids| {
+        ids.retain(|other| other != id);
+        ids
+    });
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+/// Set the IDs of `mime` in `group` to the ones returned by `update`, which
+/// is given the current IDs.
+///
+/// The key is removed if there are no IDs left. New keys are added at the
+/// end of the group, which is added at the end of the file if it's missing.
+fn update_key(
+    lines: &mut Vec<String>,
+    group: &str,
+    mime: &str,
+    update: impl FnOnce(Vec<String>) -> Vec<String>,
+) {
+    let header = format!("[{}]", group);
+    let start = match lines.iter().position(|line| line.trim() == header) {
+        Some(start) => start + 1,
+        None => {
+            let ids = update(Vec::new());
+            if !ids.is_empty() {
+                if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                    lines.push(String::new());
+                }
+                lines.push(header);
+                lines.push(format_key(mime, &ids));
+            }
+            return;
+        }
+    };
+    let end = lines[start..]
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .map_or(lines.len(), |i| start + i);
+    let key = (start..end).find(|&i| {
+        let line = lines[i].trim_start();
+        !line.starts_with('#')
+            && line
+                .split_once('=')
+                .is_some_and(|(key, _)| key.trim().eq_ignore_ascii_case(mime))
+    });
+    match key {
+        Some(i) => {
+            let ids = lines[i].split_once('=').map_or("", |(_, ids)| ids);
+            let ids = update(split_ids(ids));
+            if ids.is_empty() {
+                lines.remove(i);
+            } else {
+                lines[i] = format_key(mime, &ids);
+            }
+        }
+        None => {
+            let ids = update(Vec::new());
+            if !ids.is_empty() {
+                // After the last line of the group that isn't blank
+                let at = (start..end)
+                    .rev()
+                    .find(|&i| !lines[i].trim().is_empty())
+                    .map_or(start, |i| i + 1);
+                lines.insert(at, format_key(mime, &ids));
+            }
+        }
+    }
+}
+
+/// Format a line of a `mimeapps.list` group.
+fn format_key(mime: &str, ids: &[String]) -> String {
+    format!("{}={};", mime, ids.join(";"))
+}
+
+/// Replace the `mimeapps.list` file at `path` with `contents`.
+fn write_list(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Write to a temporary file first so other programs never read a
+    // partially written file
+    let tmp_path = path.with_extension(format!("list.{}.tmp", process::id()));
+    fs::write(&tmp_path, contents)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&tmp_path);
+        })
+}
+
+/// Error building the MIME index or setting a default app.
+#[derive(Debug, Error)]
+pub enum MimeError {
+    #[error(transparent)]
+    Scan(#[from] ScanError),
+    #[error("Error reading {}: {err}", .path.display())]
+    Read { path: PathBuf, err: io::Error },
+    #[error("Error writing {}: {err}", .path.display())]
+    Write { path: PathBuf, err: io::Error },
+    #[error("No app `{0}`")]
+    UnknownApp(String),
+    #[error("No config dir to save mimeapps.list in")]
+    NoUserList,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn write_entry(dir: &Path, id: &str, extra: &str) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(
+            dir.join(id),
+            format!("[Desktop Entry]\nName={}\nExec=true\n{}", id, extra),
+        )
+        .unwrap();
+    }
+
+    fn ids(apps: Vec<&App>) -> Vec<&str> {
+        apps.into_iter().map(|app| app.id.as_str()).collect()
+    }
+
+    #[test]
+    fn associations() {
+        let dir = TempDir::new("mime");
+        let user_apps = dir.join("user/applications");
+        let system_apps = dir.join("system/applications");
+        write_entry(
+            &system_apps,
+            "editor.desktop",
+            "MimeType=text/plain;text/markdown;",
+        );
+        write_entry(
+            &system_apps,
+            "viewer.desktop",
+            "NoDisplay=true\nMimeType=text/plain;image/png;",
+        );
+        write_entry(
+            &system_apps,
+            "ide.desktop",
+            "MimeType=text/x-rust;text/markdown;",
+        );
+        write_entry(&system_apps, "gone.desktop", "MimeType=text/plain;");
+        write_entry(&user_apps, "gone.desktop", "Hidden=true");
+        let user_list = dir.join("config/mimeapps.list");
+        let desktop_list = dir.join("config/sway-mimeapps.list");
+        let system_list = dir.join("system/mimeapps.list");
+        fs::create_dir_all(dir.join("config")).unwrap();
+        fs::write(
+            &desktop_list,
+            "[Default Applications]
+image/png=missing.desktop;viewer.desktop
+",
+        )
+        .unwrap();
+        fs::write(
+            &user_list,
+            "# User associations
+[Added Associations]
+text/x-rust=editor.desktop;missing.desktop;
+
+[Removed Associations]
+text/plain=viewer.desktop;
+text/markdown=ide.desktop;
+",
+        )
+        .unwrap();
+        fs::write(
+            &system_list,
+            "[Default Applications]
+text/plain=editor.desktop
+image/png=editor.desktop
+text/markdown=ide.desktop
+[Added Associations]
+text/plain=viewer.desktop;gone.desktop
+",
+        )
+        .unwrap();
+
+        let (mut index, errors) = MimeIndex::load_from(
+            &[user_apps, system_apps],
+            &[desktop_list.clone(), user_list.clone(), system_list],
+            Some(user_list.clone()),
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(index.app("gone.desktop").is_none());
+        assert_eq!(ids(index.apps_for_mime("Text/Plain")), ["editor.desktop"]);
+        assert_eq!(ids(index.apps_for_mime("image/png")), ["viewer.desktop"]);
+        assert_eq!(
+            ids(index.apps_for_mime("text/x-rust")),
+            ["editor.desktop", "ide.desktop"]
+        );
+        assert_eq!(
+            index.default_app_for("text/x-rust").unwrap().id,
+            "editor.desktop"
+        );
+        assert_eq!(
+            index.default_app_for("image/png").unwrap().id,
+            "viewer.desktop"
+        );
+        assert!(index.default_app_for("video/mp4").is_none());
+        // The default is no longer associated
+        assert_eq!(
+            ids(index.apps_for_mime("text/markdown")),
+            ["editor.desktop"]
+        );
+        assert_eq!(
+            index.default_app_for("text/markdown").unwrap().id,
+            "editor.desktop"
+        );
+
+        index.set_default("text/plain", "viewer.desktop").unwrap();
+        assert_eq!(
+            ids(index.apps_for_mime("text/plain")),
+            ["viewer.desktop", "editor.desktop"]
+        );
+        assert!(matches!(
+            index.set_default("text/plain", "missing.desktop"),
+            Err(MimeError::UnknownApp(_))
+        ));
+        let (index, _) = MimeIndex::load_from(
+            &[
+                dir.join("user/applications"),
+                dir.join("system/applications"),
+            ],
+            &[desktop_list, user_list.clone()],
+            None,
+        );
+        assert_eq!(
+            index.default_app_for("text/plain").unwrap().id,
+            "viewer.desktop"
+        );
+        assert!(fs::read_to_string(&user_list)
+            .unwrap()
+            .starts_with("# User associations\n"));
+    }
+
+    #[test]
+    fn set_default() {
+        assert_eq!(
+            set_default_in("", "text/plain", "a.desktop"),
+            "[Default Applications]
+text/plain=a.desktop;
+
+[Added Associations]
+text/plain=a.desktop;
+"
+        );
+        let contents = "[Default Applications]
+text/plain=b.desktop
+image/png=b.desktop;
+
+[Added Associations]
+Text/Plain=b.desktop;a.desktop;
+[Removed Associations]
+text/plain=a.desktop;
+image/png=a.desktop;
+";
+        assert_eq!(
+            set_default_in(contents, "text/plain", "a.desktop"),
+            "[Default Applications]
+text/plain=a.desktop;
+image/png=b.desktop;
+
+[Added Associations]
+text/plain=a.desktop;b.desktop;
+[Removed Associations]
+image/png=a.desktop;
+"
+        );
+        assert_eq!(
+            set_default_in(contents, "video/mp4", "a.desktop"),
+            "[Default Applications]
+text/plain=b.desktop
+image/png=b.desktop;
+video/mp4=a.desktop;
+
+[Added Associations]
+Text/Plain=b.desktop;a.desktop;
+video/mp4=a.desktop;
+[Removed Associations]
+text/plain=a.desktop;
+image/png=a.desktop;
+"
+        );
+    }
+
+    #[test]
+    fn scan_cache() {
+        let dir = TempDir::new("mime-cache");
+        let apps = dir.join("applications");
+        write_entry(
+            &apps,
+            "viewer.desktop",
+            "NoDisplay=true\nMimeType=image/png;",
+        );
+        let cache_path = dir.join("scan_cache.db");
+        let app_paths = [apps];
+
+        let (index, errors) =
+            MimeIndex::load_cached(&app_paths, Some(&cache_path));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(ids(index.apps_for_mime("image/png")), ["viewer.desktop"]);
+        // Change the entry without changing its size and modification time,
+        // so the cached entry is used
+        let path = app_paths[0].join("viewer.desktop");
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, contents.replace("image/png", "image/gif")).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let (index, _) = MimeIndex::load_cached(&app_paths, Some(&cache_path));
+        assert_eq!(ids(index.apps_for_mime("image/png")), ["viewer.desktop"]);
+        let (index, _) = MimeIndex::load_cached(&app_paths, None);
+        assert_eq!(ids(index.apps_for_mime("image/gif")), ["viewer.desktop"]);
+    }
+}
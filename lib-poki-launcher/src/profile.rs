@@ -0,0 +1,512 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use crate::db::AppDBError;
+use crate::{CFG_PATH, DB_PATH, DIRS};
+use lazy_static::lazy_static;
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::libc;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write as _};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{
+    Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+
+lazy_static! {
+    /// Lock of the DB updates of this process, as the temporary file a DB
+    /// is written to is the same for all threads of the process.
+    static ref UPDATE_LOCK: Mutex<()> = Mutex::new(());
+    /// The DB lock files opened by this process, by path.
+    static ref LOCK_FILES: Mutex<HashMap<PathBuf, &'static LockFile>> =
+        Mutex::new(HashMap::new());
+}
+
+/// How access to the DB file by several processes is coordinated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockStrategy {
+    /// Lock a `.lock` file next to the DB file, shared for reading and
+    /// exclusive for writing.
+    #[default]
+    File,
+    /// Don't lock, for DBs only used by one process at a time.
+    None,
+}
+
+/// Where a launcher profile stores its apps DB and config.
+///
+/// `Profile::default()` is the profile in the user's standard directories,
+/// at `DB_PATH` and `CFG_PATH`.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    storage: Storage,
+}
+
+#[derive(Debug, Clone)]
+enum Storage {
+    /// Files on disk.
+    Files {
+        db_path: PathBuf,
+        config_path: PathBuf,
+        theme_dir: PathBuf,
+        lock: LockStrategy,
+    },
+    /// In memory, shared between clones of the profile.
+    Memory(Arc<Mutex<Memory>>),
+}
+
+/// Contents of an in memory profile.
+#[derive(Debug, Default)]
+struct Memory {
+    db: Option<Vec<u8>>,
+    config: Option<String>,
+}
+
+/// A lock on the DB of a profile, released when dropped.
+pub(crate) struct DbLock {
+    _lock: Option<HeldLock>,
+}
+
+/// A DB lock file shared by the threads of the process.
+///
+/// `fcntl` locks belong to the process and are all released when any
+/// descriptor of the file in the process is closed. So the file is opened
+/// once and never closed, and the threads of the process take `threads`
+/// around the `fcntl` lock: shared for reading and exclusive for writing.
+struct LockFile {
+    file: File,
+    threads: RwLock<()>,
+    /// Number of threads holding the shared `fcntl` lock, it's released
+    /// when the last one is done.
+    readers: Mutex<usize>,
+}
+
+/// A lock taken on a `LockFile`.
+struct HeldLock {
+    file: &'static LockFile,
+    guard: ThreadGuard,
+}
+
+enum ThreadGuard {
+    Read {
+        _guard: RwLockReadGuard<'static, ()>,
+    },
+    Write {
+        _guard: RwLockWriteGuard<'static, ()>,
+    },
+}
+
+impl LockFile {
+    /// The lock file at `path`, created if it doesn't exist.
+    fn open(path: &Path) -> io::Result<&'static LockFile> {
+        let mut files = LOCK_FILES.lock().unwrap();
+        if let Some(file) = files.get(path) {
+            return Ok(file);
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        // Kept for the rest of the process, see above
+        let file: &'static LockFile = Box::leak(Box::new(LockFile {
+            file,
+            threads: RwLock::new(()),
+            readers: Mutex::new(0),
+        }));
+        files.insert(path.to_owned(), file);
+        Ok(file)
+    }
+
+    /// Lock the file, shared for reading or exclusive for writing.
+    fn lock(&'static self, write: bool) -> io::Result<HeldLock> {
+        // The guarded data is empty, so a poisoned lock can still be used
+        let guard = if write {
+            let guard =
+                self.threads.write().unwrap_or_else(PoisonError::into_inner);
+            self.fcntl(libc::F_WRLCK)?;
+            ThreadGuard::Write { _guard: guard }
+        } else {
+            let guard =
+                self.threads.read().unwrap_or_else(PoisonError::into_inner);
+            let mut readers = self.readers.lock().unwrap();
+            if *readers == 0 {
+                self.fcntl(libc::F_RDLCK)?;
+            }
+            *readers += 1;
+            ThreadGuard::Read { _guard: guard }
+        };
+        Ok(HeldLock { file: self, guard })
+    }
+
+    /// Set the `fcntl` lock of the whole file to `kind`, waiting for other
+    /// processes to release theirs.
+    fn fcntl(&self, kind: libc::c_int) -> io::Result<()> {
+        let flock = libc::flock {
+            l_type: kind as libc::c_short,
+            l_whence: libc::SEEK_SET as libc::c_short,
+            l_start: 0,
+            l_len: 0,
+            l_pid: 0,
+        };
+        fcntl(self.file.as_raw_fd(), FcntlArg::F_SETLKW(&flock))
+            .map(drop)
+            .map_err(|err| io::Error::from_raw_os_error(err as i32))
+    }
+}
+
+impl Drop for HeldLock {
+    fn drop(&mut self) {
+        // The thread guard is released after the `fcntl` lock
+        let release = match self.guard {
+            ThreadGuard::Write { .. } => true,
+            ThreadGuard::Read { .. } => {
+                let mut readers = self.file.readers.lock().unwrap();
+                *readers -= 1;
+                *readers == 0
+            }
+        };
+        if release {
+            let _ = self.file.fcntl(libc::F_UNLCK);
+        }
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::files(DB_PATH.clone(), CFG_PATH.clone())
+    }
+}
+
+impl Profile {
+    /// A named profile in the user's standard directories, kept apart from
+    /// the default profile.
+    ///
+    /// The DB is stored in `profiles/<name>/apps.db` in the data dir, the
+    /// config at `profiles/<name>.hjson` and the themes in
+    /// `profiles/<name>/themes` in the config dir.
+    pub fn named(name: &str) -> Profile {
+        let profiles = DIRS.config_dir().join("profiles");
+        let mut profile = Profile::files(
+            DIRS.data_dir().join("profiles").join(name).join("apps.db"),
+            profiles.join(format!("{}.hjson", name)),
+        );
+        if let Storage::Files { theme_dir, .. } = &mut profile.storage {
+            *theme_dir = profiles.join(name).join("themes");
+        }
+        profile
+    }
+
+    /// A profile with the DB and config at the given paths.
+    ///
+    /// The themes of the profile are in `themes` next to the config.
+    pub fn files(db_path: PathBuf, config_path: PathBuf) -> Profile {
+        Profile {
+            storage: Storage::Files {
+                theme_dir: config_path.with_file_name("themes"),
+                db_path,
+                config_path,
+                lock: LockStrategy::default(),
+            },
+        }
+    }
+
+    /// A profile that is only kept in memory, ex. for tests.
+    ///
+    /// Clones of the profile share the same storage.
+    pub fn in_memory() -> Profile {
+        Profile {
+            storage: Storage::Memory(Arc::default()),
+        }
+    }
+
+    /// An in memory profile with the hjson config `config`.
+    pub fn in_memory_with_config(config: &str) -> Profile {
+        let profile = Profile::in_memory();
+        if let Storage::Memory(memory) = &profile.storage {
+            memory.lock().unwrap().config = Some(config.to_owned());
+        }
+        profile
+    }
+
+    /// Set how access to the DB file is coordinated, ignored for in memory
+    /// profiles.
+    pub fn with_lock(mut self, strategy: LockStrategy) -> Profile {
+        if let Storage::Files { lock, .. } = &mut self.storage {
+            *lock = strategy;
+        }
+        self
+    }
+
+    /// Path to the DB file, `None` for in memory profiles.
+    pub fn db_path(&self) -> Option<&Path> {
+        match &self.storage {
+            Storage::Files { db_path, .. } => Some(db_path),
+            Storage::Memory(_) => None,
+        }
+    }
+
+    /// Path to the config file, `None` for in memory profiles.
+    pub fn config_path(&self) -> Option<&Path> {
+        match &self.storage {
+            Storage::Files { config_path, .. } => Some(config_path),
+            Storage::Memory(_) => None,
+        }
+    }
+
+    /// Path to the dir with the themes of the profile, `None` for in memory
+    /// profiles.
+    pub fn theme_dir(&self) -> Option<&Path> {
+        match &self.storage {
+            Storage::Files { theme_dir, .. } => Some(theme_dir),
+            Storage::Memory(_) => None,
+        }
+    }
+
+    /// Check if the DB exists.
+    pub fn db_exists(&self) -> bool {
+        match &self.storage {
+            Storage::Files { db_path, .. } => db_path.exists(),
+            Storage::Memory(memory) => memory.lock().unwrap().db.is_some(),
+        }
+    }
+
+    /// Path to the scan cache file, `None` for in memory profiles.
+    pub(crate) fn scan_cache_path(&self) -> Option<PathBuf> {
+        self.db_path()
+            .map(|db_path| db_path.with_file_name("scan_cache.db"))
+    }
+
+    /// The DB path used in errors.
+    pub(crate) fn db_file(&self) -> PathBuf {
+        self.db_path()
+            .map_or_else(|| PathBuf::from("<memory>"), Path::to_owned)
+    }
+
+    /// Lock the DB, shared for reading or exclusive for writing.
+    ///
+    /// A separate lock file is used as the DB file is replaced on save. The
+    /// locks are `fcntl` locks on a lock file shared by the threads of the
+    /// process, see `LockFile`.
+    pub(crate) fn lock_db(&self, write: bool) -> Result<DbLock, AppDBError> {
+        let db_path = match &self.storage {
+            Storage::Files {
+                db_path,
+                lock: LockStrategy::File,
+                ..
+            } => db_path,
+            _ => return Ok(DbLock { _lock: None }),
+        };
+        let lock_path = db_path.with_extension("db.lock");
+        LockFile::open(&lock_path)
+            .and_then(|file| file.lock(write))
+            .map(|lock| DbLock { _lock: Some(lock) })
+            .map_err(|err| AppDBError::FileOpen {
+                file_path: lock_path,
+                err,
+            })
+    }
+
+    /// Read the DB under a shared lock.
+    pub(crate) fn read_db(&self) -> Result<Vec<u8>, AppDBError> {
+        let db_path = match &self.storage {
+            Storage::Files { db_path, .. } => db_path,
+            Storage::Memory(memory) => {
+                return memory.lock().unwrap().db.clone().ok_or_else(|| {
+                    AppDBError::FileOpen {
+                        file_path: self.db_file(),
+                        err: io::ErrorKind::NotFound.into(),
+                    }
+                })
+            }
+        };
+        let _lock = self.lock_db(false)?;
+        fs::read(db_path).map_err(|err| AppDBError::FileOpen {
+            file_path: db_path.clone(),
+            err,
+        })
+    }
+
+    /// Replace the DB with the result of `update` under an exclusive lock.
+    ///
+    /// `update` is given the current contents of the DB, if it exists, so
+    /// changes made by other processes can be merged. The DB is written to a
+    /// temporary file which then replaces the DB file, so the DB file is
+    /// never left partially written.
+    pub(crate) fn update_db(
+        &self,
+        update: impl FnOnce(Option<&[u8]>) -> Vec<u8>,
+    ) -> Result<(), AppDBError> {
+        let db_path = match &self.storage {
+            Storage::Files { db_path, .. } => db_path,
+            Storage::Memory(memory) => {
+                let mut memory = memory.lock().unwrap();
+                memory.db = Some(update(memory.db.as_deref()));
+                return Ok(());
+            }
+        };
+        if let Some(data_dir) = db_path.parent() {
+            if !data_dir.exists() {
+                fs::create_dir_all(data_dir).map_err(|err| {
+                    AppDBError::DirCreate {
+                        dir_path: data_dir.to_owned(),
+                        err,
+                    }
+                })?;
+            }
+        }
+        let _update_lock = UPDATE_LOCK.lock().unwrap();
+        let _lock = self.lock_db(true)?;
+        let current = match fs::read(db_path) {
+            Ok(current) => Some(current),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                return Err(AppDBError::FileOpen {
+                    file_path: db_path.clone(),
+                    err,
+                })
+            }
+        };
+        let buf = update(current.as_deref());
+        let tmp_path =
+            db_path.with_extension(format!("db.{}.tmp", process::id()));
+        let mut file =
+            File::create(&tmp_path).map_err(|err| AppDBError::FileCreate {
+                file_path: tmp_path.clone(),
+                err,
+            })?;
+        if let Err(err) = file.write_all(&buf).and_then(|_| file.sync_all()) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(AppDBError::FileWrite {
+                file_path: tmp_path,
+                err,
+            });
+        }
+        fs::rename(&tmp_path, db_path).map_err(|err| {
+            let _ = fs::remove_file(&tmp_path);
+            AppDBError::FileWrite {
+                file_path: db_path.clone(),
+                err,
+            }
+        })
+    }
+
+    /// Move a corrupted DB out of the way, to `apps.db.corrupt-<time>`.
+    ///
+    /// Returns where the DB was moved to, `None` for in memory profiles
+    /// where it is dropped.
+    pub(crate) fn quarantine_db(
+        &self,
+        time: u64,
+    ) -> Result<Option<PathBuf>, AppDBError> {
+        let db_path = match &self.storage {
+            Storage::Files { db_path, .. } => db_path,
+            Storage::Memory(memory) => {
+                memory.lock().unwrap().db = None;
+                return Ok(None);
+            }
+        };
+        let quarantine_path =
+            db_path.with_extension(format!("db.corrupt-{}", time));
+        fs::rename(db_path, &quarantine_path).map_err(|err| {
+            AppDBError::Quarantine {
+                file_path: db_path.clone(),
+                err,
+            }
+        })?;
+        Ok(Some(quarantine_path))
+    }
+
+    /// Read the config, `Ok(None)` if there is no config.
+    pub(crate) fn read_config(&self) -> io::Result<Option<String>> {
+        match &self.storage {
+            Storage::Files { config_path, .. } => {
+                match fs::read_to_string(config_path) {
+                    Ok(config) => Ok(Some(config)),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                        Ok(None)
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            Storage::Memory(memory) => {
+                Ok(memory.lock().unwrap().config.clone())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn memory() {
+        let profile = Profile::in_memory();
+        assert!(!profile.db_exists());
+        assert!(profile.read_db().is_err());
+        profile.clone().update_db(|_| b"db".to_vec()).unwrap();
+        assert!(profile.db_exists());
+        profile
+            .update_db(|db| {
+                assert_eq!(db, Some(&b"db"[..]));
+                [db.unwrap(), b"2"].concat()
+            })
+            .unwrap();
+        assert_eq!(profile.read_db().unwrap(), b"db2");
+        assert_eq!(profile.quarantine_db(1).unwrap(), None);
+        assert!(!profile.db_exists());
+        assert_eq!(profile.read_config().unwrap(), None);
+    }
+
+    #[test]
+    fn config() {
+        let profile =
+            Profile::in_memory_with_config("half_life: 2\nterm_cmd: foot");
+        let config = crate::Config::load(&profile).unwrap();
+        assert_eq!(config.half_life, 2. * 24. * 60. * 60.);
+        assert_eq!(config.term_cmd.as_deref(), Some("foot"));
+        let config = crate::Config::load(&Profile::in_memory()).unwrap();
+        assert_eq!(config.term_cmd, None);
+    }
+
+    #[test]
+    fn files() {
+        let dir = TempDir::new("profile");
+        let profile =
+            Profile::files(dir.join("data/apps.db"), dir.join("config.hjson"))
+                .with_lock(LockStrategy::None);
+        assert_eq!(profile.read_config().unwrap(), None);
+        profile
+            .update_db(|db| {
+                assert_eq!(db, None);
+                b"db".to_vec()
+            })
+            .unwrap();
+        assert_eq!(profile.read_db().unwrap(), b"db");
+        assert!(!dir.join("data/apps.db.lock").exists());
+        assert_eq!(
+            profile.quarantine_db(1).unwrap(),
+            Some(dir.join("data/apps.db.corrupt-1"))
+        );
+        assert!(!profile.db_exists());
+    }
+}
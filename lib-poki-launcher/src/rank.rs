@@ -0,0 +1,348 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Ranking of the apps returned by `AppsDB::get_ranked_list`.
+//!
+//! A `Ranker` scores each app from its frecency, the fuzzy match score of the
+//! search and the launch log. The ranker used by an `AppsDB` is picked with
+//! the `ranker` setting in the config or set with `AppsDB::set_ranker`.
+use crate::history::Launch;
+use crate::App;
+use chrono::{Datelike, Local, NaiveDateTime, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Scores apps for the ranked list, higher scores are listed first.
+pub trait Ranker: fmt::Debug + Send + Sync {
+    /// Score `app`.
+    ///
+    /// `match_score` is the fuzzy match score of the search, or 0 when not
+    /// searching.
+    fn rank(&self, app: &App, match_score: f32, ctx: &RankContext) -> f32;
+}
+
+/// Information about the search and the app launches available to rankers.
+pub struct RankContext<'a> {
+    /// The search, if any.
+    pub query: Option<&'a str>,
+    /// Current time in seconds since the epoch.
+    pub now: f64,
+    /// Seconds elapsed since the reference time of the app scores.
+    pub elapsed: f32,
+    /// Half life of the app launches in seconds.
+    pub half_life: f32,
+    /// The launch log grouped by app uuid.
+    launches: HashMap<&'a str, Vec<&'a Launch>>,
+}
+
+impl<'a> RankContext<'a> {
+    /// Create a context for ranking apps.
+    pub fn new(
+        query: Option<&'a str>,
+        now: f64,
+        elapsed: f32,
+        half_life: f32,
+        launches: &'a [Launch],
+    ) -> Self {
+        let mut by_uuid: HashMap<&str, Vec<&Launch>> = HashMap::new();
+        for launch in launches {
+            by_uuid.entry(&launch.uuid).or_default().push(launch);
+        }
+        RankContext {
+            query,
+            now,
+            elapsed,
+            half_life,
+            launches: by_uuid,
+        }
+    }
+
+    /// The recorded launches of `app` and its actions.
+    pub fn launches(&self, app: &App) -> &[&'a Launch] {
+        self.launches
+            .get(app.uuid.as_str())
+            .map_or(&[], |launches| launches.as_slice())
+    }
+
+    /// Current frecency of `app`.
+    pub fn frecency(&self, app: &App) -> f32 {
+        app.get_frecency(self.elapsed, self.half_life)
+    }
+
+    /// How much a launch at `time` still counts, halving every half life.
+    pub fn decay(&self, time: f64) -> f32 {
+        2.0f32.powf(-((self.now - time) as f32) / self.half_life)
+    }
+}
+
+/// The built in rankers.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum RankerKind {
+    /// See `Frecency`.
+    #[default]
+    Frecency,
+    /// See `TimeOfDay`.
+    TimeOfDay,
+    /// See `Prefix`.
+    Prefix,
+}
+
+impl RankerKind {
+    /// Create the ranker with its default settings.
+    pub fn build(self) -> Arc<dyn Ranker> {
+        match self {
+            RankerKind::Frecency => Arc::new(Frecency),
+            RankerKind::TimeOfDay => Arc::new(TimeOfDay::default()),
+            RankerKind::Prefix => Arc::new(Prefix::default()),
+        }
+    }
+}
+
+/// Rank apps by how often and recently they were launched.
+///
+/// The score is the frecency of the app plus the match score / 100.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Frecency;
+
+impl Ranker for Frecency {
+    fn rank(&self, app: &App, match_score: f32, ctx: &RankContext) -> f32 {
+        ctx.frecency(app) + match_score / 100.
+    }
+}
+
+/// Rank apps launched around the current time of day higher, ex. the
+/// terminal in the morning and the music player in the evening.
+///
+/// Adds the launches of the app near the current time of day, decayed like
+/// the frecency, to the `Frecency` score. Launches on the same day of the
+/// week count fully, launches on other days count half if both days are
+/// weekdays or both are weekend days and a quarter otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeOfDay {
+    /// How many hours away from the current time of day launches count.
+    pub window: f32,
+    /// Weight of the time of day score relative to the frecency.
+    pub weight: f32,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        TimeOfDay {
+            window: 2.0,
+            weight: 1.0,
+        }
+    }
+}
+
+impl TimeOfDay {
+    /// How similar the time of day and day of the week of `a` and `b` are,
+    /// from 0 to 1.
+    fn similarity(&self, a: NaiveDateTime, b: NaiveDateTime) -> f32 {
+        let hours = |time: NaiveDateTime| {
+            time.hour() as f32
+                + time.minute() as f32 / 60.
+                + time.second() as f32 / 3600.
+        };
+        let diff = (hours(a) - hours(b)).abs();
+        let diff = diff.min(24. - diff);
+        let time_similarity = (1. - diff / self.window).max(0.);
+        let weekend =
+            |time: NaiveDateTime| time.weekday().number_from_monday() > 5;
+        let day_similarity = if a.weekday() == b.weekday() {
+            1.
+        } else if weekend(a) == weekend(b) {
+            0.5
+        } else {
+            0.25
+        };
+        time_similarity * day_similarity
+    }
+}
+
+/// Convert a time in seconds since the epoch to the local date and time.
+fn local_time(time: f64) -> Option<NaiveDateTime> {
+    Local
+        .timestamp_opt(time as i64, 0)
+        .earliest()
+        .map(|time| time.naive_local())
+}
+
+impl Ranker for TimeOfDay {
+    fn rank(&self, app: &App, match_score: f32, ctx: &RankContext) -> f32 {
+        let base = Frecency.rank(app, match_score, ctx);
+        let now = match local_time(ctx.now) {
+            Some(now) => now,
+            None => return base,
+        };
+        let score: f32 = ctx
+            .launches(app)
+            .iter()
+            .filter_map(|launch| {
+                let time = local_time(launch.time)?;
+                Some(ctx.decay(launch.time) * self.similarity(now, time))
+            })
+            .sum();
+        base + self.weight * score
+    }
+}
+
+/// Learn which app a search leads to.
+///
+/// Adds the launches of the app that were made from a search starting with
+/// the current search, decayed like the frecency, to the `Frecency` score.
+/// Launches from the exact same search count double. Launches of desktop
+/// actions only count for that action.
+#[derive(Debug, Clone, Copy)]
+pub struct Prefix {
+    /// Weight of the learned searches relative to the frecency.
+    pub weight: f32,
+}
+
+impl Default for Prefix {
+    fn default() -> Self {
+        Prefix { weight: 2.0 }
+    }
+}
+
+impl Ranker for Prefix {
+    fn rank(&self, app: &App, match_score: f32, ctx: &RankContext) -> f32 {
+        let base = Frecency.rank(app, match_score, ctx);
+        let query = match ctx.query {
+            Some(query) if !query.is_empty() => query.to_lowercase(),
+            _ => return base,
+        };
+        let score: f32 = ctx
+            .launches(app)
+            .iter()
+            .filter(|launch| launch.action == app.action)
+            .filter_map(|launch| {
+                let launch_query = launch.query.as_ref()?.to_lowercase();
+                if launch_query == query {
+                    Some(ctx.decay(launch.time))
+                } else if launch_query.starts_with(&query) {
+                    Some(ctx.decay(launch.time) / 2.)
+                } else {
+                    None
+                }
+            })
+            .sum();
+        base + self.weight * score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::app;
+    use chrono::NaiveDate;
+
+    fn launch(app: &App, time: f64, query: Option<&str>) -> Launch {
+        Launch {
+            uuid: app.uuid.clone(),
+            action: app.action.clone(),
+            time,
+            query: query.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn frecency() {
+        let mut app = app("a.desktop", "a", &["/bin/a"]);
+        app.score = 2.0;
+        let ctx = RankContext::new(None, 100.0, 10.0, 10.0, &[]);
+        assert_eq!(Frecency.rank(&app, 0.0, &ctx), 1.0);
+        assert_eq!(Frecency.rank(&app, 50.0, &ctx), 1.5);
+    }
+
+    #[test]
+    fn time_of_day_similarity() {
+        let ranker = TimeOfDay::default();
+        // Monday to Sunday
+        let day = |day, hour, min| {
+            NaiveDate::from_ymd_opt(2024, 1, day)
+                .unwrap()
+                .and_hms_opt(hour, min, 0)
+                .unwrap()
+        };
+        assert_eq!(ranker.similarity(day(1, 9, 0), day(1, 9, 0)), 1.0);
+        assert_eq!(ranker.similarity(day(1, 9, 0), day(1, 10, 0)), 0.5);
+        assert_eq!(ranker.similarity(day(1, 9, 0), day(1, 12, 0)), 0.0);
+        assert_eq!(ranker.similarity(day(1, 23, 30), day(1, 0, 30)), 0.5);
+        assert_eq!(ranker.similarity(day(1, 9, 0), day(2, 9, 0)), 0.5);
+        assert_eq!(ranker.similarity(day(1, 9, 0), day(7, 9, 0)), 0.25);
+    }
+
+    #[test]
+    fn time_of_day() {
+        let terminal = app("terminal.desktop", "terminal", &["/bin/terminal"]);
+        let music = app("music.desktop", "music", &["/bin/music"]);
+        let now = 1_000_000_000.0;
+        let hour = 60. * 60.;
+        let day = 24. * hour;
+        let log = vec![
+            launch(&terminal, now - day, None),
+            launch(&terminal, now - 2. * day, None),
+            launch(&music, now - day - 10. * hour, None),
+            launch(&music, now - 2. * day - 10. * hour, None),
+        ];
+        let ctx = RankContext::new(None, now, 0.0, 7. * day as f32, &log);
+        let ranker = TimeOfDay::default();
+        assert!(
+            ranker.rank(&terminal, 0.0, &ctx) > ranker.rank(&music, 0.0, &ctx)
+        );
+        let ctx = RankContext::new(
+            None,
+            now - 10. * hour,
+            0.0,
+            7. * day as f32,
+            &log,
+        );
+        assert!(
+            ranker.rank(&music, 0.0, &ctx) > ranker.rank(&terminal, 0.0, &ctx)
+        );
+    }
+
+    #[test]
+    fn prefix() {
+        let firefox = app("firefox.desktop", "firefox", &["/bin/firefox"]);
+        let files = app("files.desktop", "files", &["/bin/files"]);
+        let private = App {
+            action: Some("private".to_owned()),
+            ..firefox.clone()
+        };
+        let now = 1000.0;
+        let log = vec![
+            launch(&firefox, now - 1., Some("fi")),
+            launch(&files, now - 10., Some("fil")),
+            launch(&private, now - 5., Some("pri")),
+        ];
+        let ranker = Prefix::default();
+        let rank = |query, app| {
+            let ctx = RankContext::new(Some(query), now, 0.0, 100.0, &log);
+            ranker.rank(app, 0.0, &ctx)
+        };
+        assert!(rank("fi", &firefox) > rank("fi", &files));
+        assert!(rank("fil", &files) > rank("fil", &firefox));
+        assert!(rank("Fil", &files) > rank("Fil", &firefox));
+        assert!(rank("pri", &private) > rank("pri", &firefox));
+        assert_eq!(rank("pri", &firefox), 0.0);
+    }
+}
@@ -14,16 +14,46 @@
  * You should have received a copy of the GNU General Public License
  * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
  */
+use crate::app_log;
 use crate::config::Config;
-use log::debug;
+use crate::terminal::find_terminal;
+use lazy_static::lazy_static;
+use log::{debug, warn};
 use nix::unistd::{getpid, setpgid};
-use std::env::VarError;
 use std::io::{self, ErrorKind};
 use std::os::unix::process::CommandExt as _;
-use std::process::{Command, Stdio};
+use std::path::PathBuf;
+use std::process::{self, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
-use super::App;
+use super::{App, FileArgKind, Target};
+
+/// Apps that exit with an error within this time after being started are
+/// reported as having failed to start, see `Running::check`.
+pub const START_GRACE_PERIOD: Duration = Duration::from_millis(500);
+/// Number of lines of output reported when an app fails to start.
+const OUTPUT_TAIL_LINES: usize = 10;
+/// How often the reaper thread checks if the started apps exited.
+const REAP_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A started process and where to send its exit status once it exits.
+type Reaped = (process::Child, Sender<io::Result<ExitStatus>>);
+
+lazy_static! {
+    /// Hands started processes to the reaper thread, see `reap`.
+    static ref REAPER: Mutex<Sender<Reaped>> = {
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("poki-reaper".to_owned())
+            .spawn(move || reap(receiver))
+            .expect("Failed to start the reaper thread");
+        Mutex::new(sender)
+    };
+}
 
 /// Error from running the app
 #[derive(Debug, Error)]
@@ -35,54 +65,759 @@ pub enum RunError {
         /// The error to propagate.
         err: io::Error,
     },
-    #[error("Error getting value of $TERM: {0}")]
-    TermVar(VarError),
-    #[error("Could not determine what terminal program to use to launch this app, please set `term_cmd` in the config file")]
+    #[error("Could not find a terminal to run this app in, please install one or set `term_cmd` in the config file")]
     CantFindTerm,
+    #[error("App {app} has no action {action}")]
+    UnknownAction {
+        /// Name of the app.
+        app: String,
+        /// The requested action id.
+        action: String,
+    },
+    #[error("App {app} accepts at most {max} files but {count} were given")]
+    TooManyTargets {
+        /// Name of the app.
+        app: String,
+        /// The number of files the app accepts.
+        max: usize,
+        /// The number of files that were given.
+        count: usize,
+    },
+    #[error("Working directory {path} of app {app} doesn't exist")]
+    WorkingDir {
+        /// Name of the app.
+        app: String,
+        /// The working directory from the `Path` key.
+        path: PathBuf,
+    },
+    #[error("{app} failed to start: {status}")]
+    EarlyExit {
+        /// Name of the app.
+        app: String,
+        /// How the app exited.
+        status: ExitStatus,
+        /// The last lines the app wrote to stdout and stderr.
+        output: String,
+    },
+    #[error("App {app} only accepts local files, can't open {target}")]
+    UnsupportedTarget {
+        /// Name of the app.
+        app: String,
+        /// The target that isn't a local file.
+        target: String,
+    },
 }
 
 impl App {
+    /// Run the desktop action `action` of the app.
+    pub fn run_action(
+        &self,
+        action: &str,
+        config: &Config,
+    ) -> Result<(), RunError> {
+        self.action(action)
+            .ok_or_else(|| RunError::UnknownAction {
+                app: self.name.clone(),
+                action: action.to_owned(),
+            })?
+            .run(config)
+    }
+
     /// Run the app.
     pub fn run(&self, config: &Config) -> Result<(), RunError> {
-        debug!("Exec: `{:?}`", self.exec);
-        let mut command = if self.terminal {
-            if let Some(term) = &config.term_cmd {
-                let args: Vec<_> = term.split(' ').collect();
-                let mut cmd = Command::new(args[0]);
-                cmd.args(&args[1..]);
-                cmd.args(&self.exec);
-                cmd
+        self.run_with(&[], config)
+    }
+
+    /// Run the app with files or URIs.
+    ///
+    /// The targets are passed where the `%f`, `%F`, `%u` or `%U` field code
+    /// is in the exec string of the app:
+    /// - `%f` and `%u`: one instance of the app is started per target
+    /// - `%F` and `%U`: all targets are passed to one instance of the app
+    ///
+    /// `file://` URIs are converted to paths for `%f` and `%F`, other URIs
+    /// can't be opened by apps that only accept files. Apps without a file
+    /// field code can't be run with targets.
+    pub fn run_with(
+        &self,
+        targets: &[Target],
+        config: &Config,
+    ) -> Result<(), RunError> {
+        self.run_with_options(targets, &RunOptions::default(), config)
+            .map(|_| ())
+    }
+
+    /// Run the app with files or URIs and extra options.
+    ///
+    /// See `App::run_with`. Apps with `DBusActivatable` set are activated
+    /// over D-Bus with `gdbus`, see `App::activate`, unless `app_env` in the
+    /// config sets environment variables for them, as the app might already
+    /// be running or is started by the D-Bus daemon.
+    ///
+    /// The output of the app is written to its log file, see
+    /// `app_log_path`. The started processes are reaped in the background,
+    /// use `Running::check` on the result to find out if they failed to
+    /// start.
+    pub fn run_with_options(
+        &self,
+        targets: &[Target],
+        options: &RunOptions,
+        config: &Config,
+    ) -> Result<Running, RunError> {
+        let mut running = Running {
+            app: self.name.clone(),
+            children: Vec::new(),
+        };
+        if self.dbus_activatable && !self.id.is_empty() {
+            if config.app_env(self).is_empty() {
+                if let Some(child) = self.activate(targets, options)? {
+                    running.children.push(child);
+                    return Ok(running);
+                }
             } else {
-                let term = std::env::var("TERM").map_err(RunError::TermVar)?;
-                let mut cmd = Command::new(term);
-                cmd.arg("-e");
-                cmd.args(&self.exec);
-                cmd
+                debug!("Running {} with Exec to set its environment", self);
+            }
+        }
+        for exec in self.expand_targets(targets)? {
+            running.children.push(self.spawn(
+                &exec,
+                self.terminal,
+                options,
+                config,
+            )?);
+        }
+        Ok(running)
+    }
+
+    /// Build the exec of each instance of the app to start for `targets`.
+    fn expand_targets(
+        &self,
+        targets: &[Target],
+    ) -> Result<Vec<Vec<String>>, RunError> {
+        let file_arg = match self.file_arg {
+            Some(file_arg) => file_arg,
+            None if targets.is_empty() => return Ok(vec![self.exec.clone()]),
+            None => {
+                return Err(RunError::TooManyTargets {
+                    app: self.name.clone(),
+                    max: 0,
+                    count: targets.len(),
+                })
+            }
+        };
+        // Paths are made absolute as the app may run in another directory
+        let to_arg = |target: &Target| match (file_arg.kind, target) {
+            (FileArgKind::Url, Target::Uri(uri))
+            | (FileArgKind::Urls, Target::Uri(uri)) => Ok(uri.clone()),
+            _ => target
+                .to_path()
+                .map(|path| path.to_string_lossy().into_owned())
+                .ok_or_else(|| RunError::UnsupportedTarget {
+                    app: self.name.clone(),
+                    target: target.to_string(),
+                }),
+        };
+        let args = targets.iter().map(to_arg).collect::<Result<Vec<_>, _>>()?;
+        let with_args = |args: &[String]| {
+            let mut exec = self.exec.clone();
+            let index = file_arg.index.min(exec.len());
+            exec.splice(index..index, args.iter().cloned());
+            exec
+        };
+        Ok(match file_arg.kind {
+            FileArgKind::File | FileArgKind::Url if !args.is_empty() => args
+                .iter()
+                .map(|arg| with_args(std::slice::from_ref(arg)))
+                .collect(),
+            _ => vec![with_args(&args)],
+        })
+    }
+
+    /// Activate the app over D-Bus with `gdbus`.
+    ///
+    /// Returns `None` if the app should be run with its exec string instead,
+    /// because `gdbus` isn't installed or fails within `START_GRACE_PERIOD`,
+    /// ex. when the D-Bus service of the app isn't installed. A slower
+    /// activation is assumed to succeed.
+    ///
+    /// The app is started by the D-Bus daemon, so `Path=` doesn't apply and
+    /// only the output of `gdbus` goes to the log file of the app.
+    fn activate(
+        &self,
+        targets: &[Target],
+        options: &RunOptions,
+    ) -> Result<Option<Child>, RunError> {
+        let exec = self.dbus_command(targets, options);
+        let mut command = Command::new(&exec[0]);
+        command.args(&exec[1..]);
+        let child = match self.start(&mut command, &exec, options) {
+            Ok(child) => child,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                debug!("gdbus not found, running {} with Exec", self);
+                return Ok(None);
+            }
+            Err(err) => {
+                return Err(RunError::Exec {
+                    exec: format!("`{:?}`", command),
+                    err,
+                })
             }
+        };
+        match child.exited.recv_timeout(START_GRACE_PERIOD) {
+            Ok(Ok(status)) if !status.success() => {
+                warn!(
+                    "D-Bus activation of {} failed with {}, running it with Exec",
+                    self, status
+                );
+                Ok(None)
+            }
+            Ok(Err(err)) => {
+                warn!("Failed to wait for gdbus activating {}: {}", self, err);
+                Ok(Some(child))
+            }
+            // Activated, or still waiting for the app to start
+            _ => Ok(Some(child)),
+        }
+    }
+
+    /// Build the `gdbus` command that activates the app over D-Bus, see the
+    /// "D-Bus Activation" section of the desktop entry spec.
+    fn dbus_command(
+        &self,
+        targets: &[Target],
+        options: &RunOptions,
+    ) -> Vec<String> {
+        let bus_name = self.id.trim_end_matches(".desktop");
+        let object_path =
+            format!("/{}", bus_name.replace('.', "/").replace('-', "_"));
+        let platform_data = match &options.activation_token {
+            Some(token) => format!(
+                "{{'activation-token': <{token}>, 'desktop-startup-id': <{token}>}}",
+                token = gvariant_string(token)
+            ),
+            None => "@a{sv} {}".to_owned(),
+        };
+        let mut exec: Vec<String> = vec![
+            "gdbus".into(),
+            "call".into(),
+            "--session".into(),
+            "--dest".into(),
+            bus_name.into(),
+            "--object-path".into(),
+            object_path,
+            "--method".into(),
+        ];
+        if let Some(action) = &self.action {
+            exec.push("org.freedesktop.Application.ActivateAction".into());
+            exec.push(gvariant_string(action));
+            exec.push("@av []".into());
+        } else if targets.is_empty() {
+            exec.push("org.freedesktop.Application.Activate".into());
+        } else {
+            let uris: Vec<_> = targets
+                .iter()
+                .map(|target| gvariant_string(&target.to_uri()))
+                .collect();
+            exec.push("org.freedesktop.Application.Open".into());
+            exec.push(format!("[{}]", uris.join(", ")));
+        }
+        exec.push(platform_data);
+        exec
+    }
+
+    /// Build the command to start one instance of the app running `exec`.
+    fn command(
+        &self,
+        exec: &[String],
+        terminal: bool,
+        options: &RunOptions,
+        config: &Config,
+    ) -> Result<Command, RunError> {
+        let exec = if terminal {
+            find_terminal(config)
+                .ok_or(RunError::CantFindTerm)?
+                .wrap(exec)
         } else {
-            let mut cmd = Command::new(&self.exec[0]);
-            cmd.args(&self.exec[1..]);
-            cmd
+            exec.to_vec()
         };
+        let mut command = Command::new(&exec[0]);
+        command.args(&exec[1..]);
+        if let Some(dir) = &self.working_dir {
+            if !dir.is_dir() {
+                return Err(RunError::WorkingDir {
+                    app: self.name.clone(),
+                    path: dir.clone(),
+                });
+            }
+            command.current_dir(dir);
+        }
+        command.envs(config.app_env(self));
+        // Activation tokens can only be used once, don't pass on the token
+        // Poki Launcher was started with
+        match &options.activation_token {
+            Some(token) => command
+                .env("XDG_ACTIVATION_TOKEN", token)
+                .env("DESKTOP_STARTUP_ID", token),
+            None => command
+                .env_remove("XDG_ACTIVATION_TOKEN")
+                .env_remove("DESKTOP_STARTUP_ID"),
+        };
+        Ok(command)
+    }
+
+    /// Start one instance of the app running `exec`, reaping it in the
+    /// background.
+    fn spawn(
+        &self,
+        exec: &[String],
+        terminal: bool,
+        options: &RunOptions,
+        config: &Config,
+    ) -> Result<Child, RunError> {
+        debug!("Exec: `{:?}`", exec);
+        let mut command = self.command(exec, terminal, options, config)?;
+        self.start(&mut command, exec, options).map_err(|e| {
+            if terminal
+                && config.term_cmd.is_none()
+                && e.kind() == ErrorKind::NotFound
+            {
+                RunError::CantFindTerm
+            } else {
+                RunError::Exec {
+                    exec: format!("`{:?}`", command),
+                    err: e,
+                }
+            }
+        })
+    }
+
+    /// Start `command`, which runs `exec`, with its output going to the log
+    /// file of the app and reap it in the background.
+    fn start(
+        &self,
+        command: &mut Command,
+        exec: &[String],
+        options: &RunOptions,
+    ) -> io::Result<Child> {
         debug!("Running command: `{:?}`", command);
-        command
-            .stdout(Stdio::null())
-            .stderr(Stdio::null());
+        let log_dir = options.log_dir.clone().unwrap_or_else(app_log::log_dir);
+        let log = app_log::open(&log_dir, self, exec).and_then(|file| {
+            let offset = file.metadata()?.len();
+            Ok((file.try_clone()?, file, offset))
+        });
+        let log = match log {
+            Ok((stdout, stderr, offset)) => {
+                command.stdout(stdout).stderr(stderr);
+                Some((app_log::log_path(&log_dir, self), offset))
+            }
+            Err(err) => {
+                warn!("Failed to open log file for {}: {}", self, err);
+                command.stdout(Stdio::null()).stderr(Stdio::null());
+                None
+            }
+        };
+        command.stdin(Stdio::null());
         unsafe {
+            // Errors here are returned by `spawn`
             command.pre_exec(|| {
                 let pid = getpid();
-                // TODO Hanle error here
-                setpgid(pid, pid).expect("Failed to set pgid");
-                Ok(())
+                setpgid(pid, pid).map_err(io::Error::from)
+            });
+        }
+        let child = command.spawn()?;
+        let (sender, exited) = mpsc::channel();
+        REAPER
+            .lock()
+            .unwrap()
+            .send((child, sender))
+            .expect("The reaper thread stopped");
+        Ok(Child { exited, log })
+    }
+
+    /// Path to the file the output of the app is logged to.
+    ///
+    /// The log file is rotated when it grows over 1 MiB.
+    pub fn app_log_path(&self) -> PathBuf {
+        app_log::log_path(&app_log::log_dir(), self)
+    }
+}
+
+/// Options for running an app, see `App::run_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Activation token passed to the app so it can take focus, as
+    /// `XDG_ACTIVATION_TOKEN` and `DESKTOP_STARTUP_ID` or in the platform
+    /// data of D-Bus activation.
+    pub activation_token: Option<String>,
+    /// Directory to write the log file of the app to instead of `logs` in
+    /// the cache dir.
+    pub log_dir: Option<PathBuf>,
+}
+
+/// The instances of an app started by `App::run_with_options`.
+///
+/// The instances are reaped in the background, dropping this doesn't leave
+/// zombie processes behind.
+#[derive(Debug)]
+pub struct Running {
+    /// Name of the app.
+    app: String,
+    children: Vec<Child>,
+}
+
+/// A started instance of an app.
+#[derive(Debug)]
+struct Child {
+    /// Receives the exit status once the instance exits.
+    exited: Receiver<io::Result<ExitStatus>>,
+    /// The log file of the instance and where its output starts in it.
+    log: Option<(PathBuf, u64)>,
+}
+
+impl Running {
+    /// Check if the app failed to start.
+    ///
+    /// Waits up to `grace`, ex. `START_GRACE_PERIOD`, for the instances of
+    /// the app to exit. Returns `RunError::EarlyExit` with the exit status
+    /// and the last lines of output if one of them exits with an error.
+    /// Apps that exit successfully, like ones that pass the files on to an
+    /// already running instance, or are still running are fine.
+    pub fn check(&self, grace: Duration) -> Result<(), RunError> {
+        let deadline = Instant::now() + grace;
+        for child in &self.children {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let status = match child.exited.recv_timeout(timeout) {
+                Ok(Ok(status)) => status,
+                Ok(Err(err)) => {
+                    warn!("Failed to wait for {}: {}", self.app, err);
+                    continue;
+                }
+                // Still running, or already checked
+                Err(_) => continue,
+            };
+            if status.success() {
+                continue;
+            }
+            let output = match &child.log {
+                Some((path, offset)) => {
+                    app_log::read_tail(path, *offset, OUTPUT_TAIL_LINES)
+                        .unwrap_or_else(|err| {
+                            warn!(
+                                "Failed to read log of {}: {}",
+                                self.app, err
+                            );
+                            String::new()
+                        })
+                }
+                None => String::new(),
+            };
+            return Err(RunError::EarlyExit {
+                app: self.app.clone(),
+                status,
+                output,
             });
         }
-        match command.spawn() {
-            Ok(_) => Ok(()),
-            Err(e) if config.term_cmd.is_none() && e.kind() == ErrorKind::NotFound => Err(RunError::CantFindTerm),
-            Err(e) => Err(RunError::Exec {
-                exec: format!("`{:?}`", command),
-                err: e,
+        Ok(())
+    }
+}
+
+/// Wait for the processes received from `receiver` to exit and send on
+/// their exit status.
+///
+/// Runs on a single thread for all started apps. Each process is polled with
+/// `try_wait` rather than waiting for any child with `waitpid(-1)`, which
+/// would steal the exit status of processes started by other code.
+fn reap(receiver: Receiver<Reaped>) {
+    let mut children: Vec<Reaped> = Vec::new();
+    loop {
+        if children.is_empty() {
+            match receiver.recv() {
+                Ok(child) => children.push(child),
+                Err(_) => return,
+            }
+        } else {
+            match receiver.recv_timeout(REAP_INTERVAL) {
+                Ok(child) => children.push(child),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        children.extend(receiver.try_iter());
+        children.retain_mut(|(child, exited)| {
+            let status = match child.try_wait() {
+                Ok(None) => return true,
+                Ok(Some(status)) => Ok(status),
+                Err(err) => Err(err),
+            };
+            // The receiver might be gone already
+            let _ = exited.send(status);
+            false
+        });
+    }
+}
+
+/// Format `s` as a GVariant string literal.
+fn gvariant_string(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppEnv;
+    use crate::test_util::{self, TempDir};
+    use crate::FileArg;
+    use std::fs;
+
+    fn app(exec: &[&str], file_arg: Option<(usize, FileArgKind)>) -> App {
+        let mut app = test_util::app("", "Test", exec);
+        app.file_arg = file_arg.map(|(index, kind)| FileArg { index, kind });
+        app
+    }
+
+    fn targets() -> Vec<Target> {
+        vec![
+            Target::Path(PathBuf::from("/tmp/a b.txt")),
+            Target::Uri("file:///tmp/c%20d.txt".to_owned()),
+        ]
+    }
+
+    #[test]
+    fn one_instance_per_file() {
+        let app = app(&["edit", "--new"], Some((1, FileArgKind::File)));
+        assert_eq!(
+            app.expand_targets(&targets()).unwrap(),
+            vec![
+                vec!["edit", "/tmp/a b.txt", "--new"],
+                vec!["edit", "/tmp/c d.txt", "--new"],
+            ]
+        );
+        assert_eq!(
+            app.expand_targets(&[]).unwrap(),
+            vec![vec!["edit", "--new"]]
+        );
+    }
+
+    #[test]
+    fn all_files_in_one_instance() {
+        let app = app(&["edit"], Some((1, FileArgKind::Files)));
+        assert_eq!(
+            app.expand_targets(&targets()).unwrap(),
+            vec![vec!["edit", "/tmp/a b.txt", "/tmp/c d.txt"]]
+        );
+    }
+
+    #[test]
+    fn urls() {
+        let app = app(&["browser"], Some((1, FileArgKind::Urls)));
+        let mut targets = targets();
+        targets.push(Target::Uri("https://example.com".to_owned()));
+        assert_eq!(
+            app.expand_targets(&targets).unwrap(),
+            vec![vec![
+                "browser",
+                "/tmp/a b.txt",
+                "file:///tmp/c%20d.txt",
+                "https://example.com",
+            ]]
+        );
+    }
+
+    #[test]
+    fn relative_paths() {
+        let app = app(&["edit"], Some((1, FileArgKind::Urls)));
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(
+            app.expand_targets(&[Target::Path(PathBuf::from("a.txt"))])
+                .unwrap(),
+            vec![vec![
+                "edit".to_owned(),
+                cwd.join("a.txt").display().to_string()
+            ]]
+        );
+    }
+
+    #[test]
+    fn unsupported_targets() {
+        let web = [Target::Uri("https://example.com".to_owned())];
+        let files_app = app(&["edit"], Some((1, FileArgKind::File)));
+        assert!(matches!(
+            files_app.expand_targets(&web),
+            Err(RunError::UnsupportedTarget { .. })
+        ));
+        let no_files_app = app(&["edit"], None);
+        assert!(matches!(
+            no_files_app.expand_targets(&targets()),
+            Err(RunError::TooManyTargets {
+                max: 0,
+                count: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn command() {
+        let dir = std::env::temp_dir();
+        let mut app = app(&["test"], None);
+        app.id = "test.desktop".to_owned();
+        app.working_dir = Some(dir.clone());
+        let config = Config {
+            app_env: vec![
+                AppEnv {
+                    app: "test.desktop".to_owned(),
+                    env: vec!["FOO=a=b".to_owned(), "invalid".to_owned()],
+                },
+                AppEnv {
+                    app: "other.desktop".to_owned(),
+                    env: vec!["BAR=1".to_owned()],
+                },
+            ],
+            ..Config::default()
+        };
+        let options = RunOptions {
+            activation_token: Some("token".to_owned()),
+            ..RunOptions::default()
+        };
+        let command = app.command(&app.exec, false, &options, &config).unwrap();
+        assert_eq!(command.get_current_dir(), Some(dir.as_path()));
+        let envs: Vec<_> = command.get_envs().collect();
+        assert!(envs.contains(&("FOO".as_ref(), Some("a=b".as_ref()))));
+        assert!(envs.contains(&(
+            "XDG_ACTIVATION_TOKEN".as_ref(),
+            Some("token".as_ref())
+        )));
+        assert!(!envs.iter().any(|(key, _)| *key == "BAR"));
+
+        let command = app
+            .command(&app.exec, false, &RunOptions::default(), &config)
+            .unwrap();
+        let envs: Vec<_> = command.get_envs().collect();
+        assert!(envs.contains(&("DESKTOP_STARTUP_ID".as_ref(), None)));
+
+        app.working_dir = Some(dir.join("poki/missing/dir"));
+        assert!(matches!(
+            app.command(&app.exec, false, &options, &config),
+            Err(RunError::WorkingDir { .. })
+        ));
+    }
+
+    #[test]
+    fn early_exit() {
+        let dir = TempDir::new("early-exit");
+        let options = RunOptions {
+            log_dir: Some(dir.to_path_buf()),
+            ..RunOptions::default()
+        };
+        let config = Config::default();
+        let script = "echo starting; echo 'no display' >&2; exit 3";
+        let failing = app(&["sh", "-c", script], None);
+        let running = failing.run_with_options(&[], &options, &config).unwrap();
+        match running.check(Duration::from_secs(10)) {
+            Err(RunError::EarlyExit { status, output, .. }) => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(output, "starting\nno display\n");
+            }
+            result => panic!("Expected early exit, got {:?}", result),
+        }
+        // Only the output of the last run is reported
+        let running = failing.run_with_options(&[], &options, &config).unwrap();
+        match running.check(Duration::from_secs(10)) {
+            Err(RunError::EarlyExit { output, .. }) => {
+                assert_eq!(output, "starting\nno display\n")
+            }
+            result => panic!("Expected early exit, got {:?}", result),
+        }
+
+        let exits = app(&["true"], None);
+        let running = exits.run_with_options(&[], &options, &config).unwrap();
+        assert!(running.check(Duration::from_secs(10)).is_ok());
+        let sleeps = app(&["sleep", "1"], None);
+        let running = sleeps.run_with_options(&[], &options, &config).unwrap();
+        assert!(running.check(Duration::from_millis(50)).is_ok());
+    }
+
+    #[test]
+    fn reap_children() {
+        let dir = TempDir::new("reap");
+        let options = RunOptions {
+            log_dir: Some(dir.to_path_buf()),
+            ..RunOptions::default()
+        };
+        let config = Config::default();
+        let running: Vec<_> = (0..20)
+            .map(|code| {
+                let script = format!("sleep 0.1; exit {}", code % 2);
+                app(&["sh", "-c", &script], None)
+                    .run_with_options(&[], &options, &config)
+                    .unwrap()
             })
+            .collect();
+        for (code, running) in running.iter().enumerate() {
+            let result = running.check(Duration::from_secs(10));
+            assert_eq!(result.is_ok(), code % 2 == 0);
         }
     }
+
+    #[test]
+    fn dbus_fallback() {
+        let dir = TempDir::new("dbus-fallback");
+        let options = RunOptions {
+            log_dir: Some(dir.to_path_buf()),
+            ..RunOptions::default()
+        };
+        let mut app = app(&["sh", "-c", "echo exec"], None);
+        app.id = "org.poki.Missing.desktop".to_owned();
+        app.dbus_activatable = true;
+        let config = Config::default();
+        // Either gdbus isn't installed or the activation fails
+        let running = app.run_with_options(&[], &options, &config).unwrap();
+        assert!(running.check(Duration::from_secs(10)).is_ok());
+        let log = app_log::log_path(&dir, &app);
+        assert!(fs::read_to_string(log).unwrap().ends_with("exec\n"));
+    }
+
+    #[test]
+    fn dbus_command() {
+        let mut app = app(&["gedit"], Some((1, FileArgKind::Urls)));
+        app.id = "org.gnome.gedit-app.desktop".to_owned();
+        app.dbus_activatable = true;
+        let options = RunOptions {
+            activation_token: Some("it's".to_owned()),
+            ..RunOptions::default()
+        };
+        let command = app.dbus_command(&[], &options);
+        assert_eq!(
+            command[3..],
+            [
+                "--dest",
+                "org.gnome.gedit-app",
+                "--object-path",
+                "/org/gnome/gedit_app",
+                "--method",
+                "org.freedesktop.Application.Activate",
+                r"{'activation-token': <'it\'s'>, 'desktop-startup-id': <'it\'s'>}",
+            ]
+        );
+        let command = app.dbus_command(&targets(), &RunOptions::default());
+        assert_eq!(
+            command[8..],
+            [
+                "org.freedesktop.Application.Open",
+                "['file:///tmp/a%20b.txt', 'file:///tmp/c%20d.txt']",
+                "@a{sv} {}",
+            ]
+        );
+        app.action = Some("new-window".to_owned());
+        let command = app.dbus_command(&[], &RunOptions::default());
+        assert_eq!(
+            command[8..],
+            [
+                "org.freedesktop.Application.ActivateAction",
+                "'new-window'",
+                "@av []",
+                "@a{sv} {}",
+            ]
+        );
+    }
 }
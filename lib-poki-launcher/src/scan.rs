@@ -14,9 +14,15 @@
  * You should have received a copy of the GNU General Public License
  * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
  */
-use crate::desktop_entry::{parse_desktop_file, EntryParseError};
+use crate::desktop_entry::EntryParseError;
+use crate::locale::Locale;
+use crate::scan_cache::ScanCache;
 use crate::App;
-use std::{env::VarError, path::PathBuf};
+use std::collections::HashSet;
+use std::env::{self, VarError};
+use std::fs;
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 use walkdir::WalkDir;
 
@@ -37,19 +43,200 @@ pub enum ScanError {
         path: String,
         err: shellexpand::LookupError<VarError>,
     },
+    /// The entry is only shown in desktops that aren't running.
+    #[error("Desktop entry {file_path} is only shown in {}", .desktops.join(", "))]
+    OnlyShowIn {
+        file_path: PathBuf,
+        desktops: Vec<String>,
+    },
+    /// The entry isn't shown in a desktop that is running.
+    #[error("Desktop entry {file_path} is not shown in {desktop}")]
+    NotShowIn { file_path: PathBuf, desktop: String },
+    /// The program in the entry's `TryExec` key isn't installed.
+    #[error(
+        "Desktop entry {file_path} requires {try_exec} which was not found"
+    )]
+    TryExec {
+        file_path: PathBuf,
+        try_exec: String,
+    },
+}
+
+/// Get the list of desktops from `$XDG_CURRENT_DESKTOP`.
+pub fn current_desktops() -> Vec<String> {
+    env::var("XDG_CURRENT_DESKTOP")
+        .map(|desktops| {
+            desktops
+                .split(':')
+                .filter(|desktop| !desktop.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Find an executable by name in `$PATH`, or check that it exists if `name`
+/// is an absolute path.
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    fn is_executable(path: &Path) -> bool {
+        path.metadata()
+            .map(|meta| {
+                meta.is_file() && meta.permissions().mode() & 0o111 != 0
+            })
+            .unwrap_or(false)
+    }
+    let path = Path::new(name);
+    if path.is_absolute() {
+        return if is_executable(path) {
+            Some(path.to_owned())
+        } else {
+            None
+        };
+    }
+    let search_path = env::var_os("PATH")?;
+    env::split_paths(&search_path)
+        .map(|dir| dir.join(name))
+        .find(|path| is_executable(path))
+}
+
+/// Check if an app should be shown given the `OnlyShowIn`, `NotShowIn` and
+/// `TryExec` keys of its desktop entry.
+///
+/// Returns the reason the app should be hidden as an error.
+pub fn filter_app(app: &App, desktops: &[String]) -> Result<(), ScanError> {
+    if !app.only_show_in.is_empty()
+        && !desktops
+            .iter()
+            .any(|desktop| app.only_show_in.contains(desktop))
+    {
+        return Err(ScanError::OnlyShowIn {
+            file_path: app.entry_path.clone(),
+            desktops: app.only_show_in.clone(),
+        });
+    }
+    if let Some(desktop) = desktops
+        .iter()
+        .find(|desktop| app.not_show_in.contains(desktop))
+    {
+        return Err(ScanError::NotShowIn {
+            file_path: app.entry_path.clone(),
+            desktop: desktop.clone(),
+        });
+    }
+    if let Some(try_exec) = &app.try_exec {
+        if find_executable(try_exec).is_none() {
+            return Err(ScanError::TryExec {
+                file_path: app.entry_path.clone(),
+                try_exec: try_exec.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Remove the apps that shouldn't be shown in `desktops`.
+///
+/// Returns the apps to show and the reason each other app was excluded.
+pub fn filter_apps(
+    apps: Vec<App>,
+    desktops: &[String],
+) -> (Vec<App>, Vec<ScanError>) {
+    let mut errors = Vec::new();
+    let apps = apps
+        .into_iter()
+        .filter(|app| match filter_app(app, desktops) {
+            Ok(()) => true,
+            Err(err) => {
+                errors.push(err);
+                false
+            }
+        })
+        .collect();
+    (apps, errors)
+}
+
+/// A desktop entry file found while scanning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesktopFile {
+    /// Desktop file ID of the entry.
+    pub id: String,
+    /// Path to the entry.
+    pub path: PathBuf,
+}
+
+/// Get the desktop file ID of the entry at `path` in the directory `base`.
+///
+/// The ID is the path relative to `base` with `/` replaced by `-`, so
+/// `base/kde4/konqueror.desktop` has the ID `kde4-konqueror.desktop`.
+pub fn desktop_file_id(base: &Path, path: &Path) -> Option<String> {
+    let parts = path
+        .strip_prefix(base)
+        .ok()?
+        .components()
+        .map(|part| part.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join("-"))
+    }
+}
+
+/// Find the entry with the desktop file ID `id` in the directory `base`, the
+/// reverse of `desktop_file_id`.
+///
+/// Several entries can have the same ID, ex. `kde4-konqueror.desktop` and
+/// `kde4/konqueror.desktop`, the one `desktop_entires` picks is returned.
+pub fn find_desktop_file(base: &Path, id: &str) -> Option<PathBuf> {
+    let is_dir = |path: &Path| {
+        fs::symlink_metadata(path).is_ok_and(|meta| meta.is_dir())
+    };
+    // Entries in subdirectories come first since the directory names sort
+    // before the longer file names they are a prefix of
+    let in_subdir = id.match_indices('-').find_map(|(idx, _)| {
+        let dir = base.join(&id[..idx]);
+        if is_dir(&dir) {
+            find_desktop_file(&dir, &id[idx + 1..])
+        } else {
+            None
+        }
+    });
+    in_subdir.or_else(|| {
+        let path = base.join(id);
+        (path.exists() && !is_dir(&path)).then_some(path)
+    })
 }
 
 /// Get a list of desktop entries from a list of directories to search.
-pub fn desktop_entires(paths: &[PathBuf]) -> (Vec<PathBuf>, Vec<ScanError>) {
+///
+/// `paths` are in order of precedence, an entry is left out if an entry with
+/// the same desktop file ID was found in an earlier directory.
+pub fn desktop_entires(
+    paths: &[PathBuf],
+) -> (Vec<DesktopFile>, Vec<ScanError>) {
     let mut files = Vec::new();
     let mut errors = Vec::new();
+    let mut seen_ids = HashSet::new();
     for path in paths {
-        for entry in WalkDir::new(&path) {
+        for entry in WalkDir::new(path).sort_by_file_name() {
             match entry {
                 Ok(entry) => {
-                    if entry.file_name().to_str().unwrap().contains(".desktop")
-                    {
-                        files.push(entry.path().to_owned())
+                    let is_entry = entry
+                        .file_name()
+                        .to_str()
+                        .is_some_and(|name| name.ends_with(".desktop"));
+                    if !is_entry || entry.file_type().is_dir() {
+                        continue;
+                    }
+                    let id = match desktop_file_id(path, entry.path()) {
+                        Some(id) => id,
+                        None => continue,
+                    };
+                    if seen_ids.insert(id.clone()) {
+                        files.push(DesktopFile {
+                            id,
+                            path: entry.path().to_owned(),
+                        });
                     }
                 }
                 Err(err) => {
@@ -66,19 +253,214 @@ pub fn desktop_entires(paths: &[PathBuf]) -> (Vec<PathBuf>, Vec<ScanError>) {
 }
 
 /// Get a list of apps for a list of paths to search.
+///
+/// Entries with `Hidden=true` hide entries with the same desktop file ID in
+/// lower precedence directories.
+///
+/// Apps that shouldn't be shown in the current desktop, or whose `TryExec`
+/// program isn't installed, are left out and reported as errors.
 pub fn scan_desktop_entries(paths: &[PathBuf]) -> (Vec<App>, Vec<ScanError>) {
+    scan_desktop_entries_cached(paths, &mut ScanCache::default())
+}
+
+/// Get a list of apps for a list of paths to search, reusing the entries in
+/// `cache` that didn't change.
+///
+/// See `scan_desktop_entries`. `cache` is updated with the scanned entries.
+pub fn scan_desktop_entries_cached(
+    paths: &[PathBuf],
+    cache: &mut ScanCache,
+) -> (Vec<App>, Vec<ScanError>) {
     let (entries, mut errors) = desktop_entires(paths);
+    let parsed = cache.parse(&entries, Locale::from_env().as_ref());
     let (apps, errs): (Vec<_>, Vec<_>) = entries
         .into_iter()
-        .map(|path| {
-            parse_desktop_file(&path)
-                .map_err(|err| ScanError::ParseEntry { err })
+        .zip(parsed)
+        .map(|(file, app)| {
+            let app = app.map_err(|err| ScanError::ParseEntry { err })?;
+            Ok(app.map(|mut app| {
+                app.id = file.id;
+                app
+            }))
         })
         .partition(Result::is_ok);
-    let mut apps: Vec<_> =
-        apps.into_iter().map(Result::unwrap).flatten().collect();
-    apps.sort_unstable();
-    apps.dedup();
+    let apps: Vec<_> = apps.into_iter().filter_map(Result::unwrap).collect();
+    let (mut apps, filtered) = filter_apps(apps, &current_desktops());
+    errors.extend(filtered);
+    // Apps with the same name and exec but different IDs are distinct apps
+    apps.sort_by_cached_key(App::key);
+    apps.dedup_by_key(|app| app.key());
     errors.extend(errs.into_iter().map(Result::unwrap_err).collect::<Vec<_>>());
     (apps, errors)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, TempDir};
+
+    fn app() -> App {
+        let mut app = test_util::app("test.desktop", "Test", &["/bin/test"]);
+        app.entry_path = "/test.desktop".into();
+        app
+    }
+
+    fn desktops(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| (*s).to_owned()).collect()
+    }
+
+    #[test]
+    fn only_show_in() {
+        let mut app = app();
+        app.only_show_in = desktops(&["KDE"]);
+        assert!(filter_app(&app, &desktops(&["KDE"])).is_ok());
+        assert!(filter_app(&app, &desktops(&["sway", "KDE"])).is_ok());
+        assert!(matches!(
+            filter_app(&app, &desktops(&["GNOME"])),
+            Err(ScanError::OnlyShowIn { .. })
+        ));
+        assert!(matches!(
+            filter_app(&app, &[]),
+            Err(ScanError::OnlyShowIn { .. })
+        ));
+    }
+
+    #[test]
+    fn not_show_in() {
+        let mut app = app();
+        app.not_show_in = desktops(&["GNOME"]);
+        assert!(filter_app(&app, &desktops(&["KDE"])).is_ok());
+        assert!(filter_app(&app, &[]).is_ok());
+        match filter_app(&app, &desktops(&["ubuntu", "GNOME"])) {
+            Err(ScanError::NotShowIn { desktop, .. }) => {
+                assert_eq!(desktop, "GNOME")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_exec() {
+        let mut app = app();
+        app.try_exec = Some("sh".to_owned());
+        assert!(filter_app(&app, &[]).is_ok());
+        app.try_exec = Some("/bin/sh".to_owned());
+        assert!(filter_app(&app, &[]).is_ok());
+        app.try_exec = Some("poki-launcher-not-installed".to_owned());
+        assert!(matches!(
+            filter_app(&app, &[]),
+            Err(ScanError::TryExec { .. })
+        ));
+    }
+
+    #[test]
+    fn file_id() {
+        let base = Path::new("/usr/share/applications");
+        assert_eq!(
+            desktop_file_id(base, &base.join("firefox.desktop")).unwrap(),
+            "firefox.desktop"
+        );
+        assert_eq!(
+            desktop_file_id(base, &base.join("kde4/konqueror.desktop"))
+                .unwrap(),
+            "kde4-konqueror.desktop"
+        );
+        assert_eq!(desktop_file_id(base, base), None);
+        assert_eq!(desktop_file_id(base, Path::new("/other.desktop")), None);
+    }
+
+    #[test]
+    fn find_file() {
+        let base = TempDir::new("scan-find-file");
+        fs::create_dir_all(base.join("kde4/kde-apps")).unwrap();
+        fs::create_dir_all(base.join("dir.desktop")).unwrap();
+        for path in &["kde4-konqueror.desktop", "kde4/konqueror.desktop"] {
+            fs::write(base.join(path), "").unwrap();
+        }
+        fs::write(base.join("kde4/kde-apps/kate.desktop"), "").unwrap();
+        let (files, _) = desktop_entires(&[base.to_path_buf()]);
+        for file in &files {
+            assert_eq!(find_desktop_file(&base, &file.id).unwrap(), file.path);
+        }
+        assert_eq!(
+            find_desktop_file(&base, "kde4-konqueror.desktop").unwrap(),
+            base.join("kde4/konqueror.desktop")
+        );
+        assert_eq!(
+            find_desktop_file(&base, "kde4-kde-apps-kate.desktop").unwrap(),
+            base.join("kde4/kde-apps/kate.desktop")
+        );
+        assert_eq!(find_desktop_file(&base, "dir.desktop"), None);
+        assert_eq!(find_desktop_file(&base, "kde4-missing.desktop"), None);
+    }
+
+    #[test]
+    fn shadowing() {
+        let root = TempDir::new("scan-shadowing");
+        let user = root.join("user");
+        let system = root.join("system");
+        fs::create_dir_all(&user).unwrap();
+        fs::create_dir_all(system.join("kde4")).unwrap();
+        let entry = |name: &str, extra: &str| {
+            format!(
+                "[Desktop Entry]\nName={}\nExec=/bin/{}\n{}",
+                name, name, extra
+            )
+        };
+        fs::write(user.join("firefox.desktop"), entry("MyFirefox", ""))
+            .unwrap();
+        fs::write(user.join("kate.desktop"), entry("Kate", "Hidden=true"))
+            .unwrap();
+        fs::write(system.join("firefox.desktop"), entry("Firefox", ""))
+            .unwrap();
+        fs::write(system.join("kate.desktop"), entry("Kate", "")).unwrap();
+        fs::write(system.join("kde4/konqueror.desktop"), entry("Konq", ""))
+            .unwrap();
+        fs::write(system.join("old.desktop.bak"), entry("Old", "")).unwrap();
+        fs::create_dir_all(system.join("dir.desktop")).unwrap();
+
+        let (files, errors) = desktop_entires(&[user.clone(), system.clone()]);
+        assert!(errors.is_empty());
+        let ids: Vec<_> = files.iter().map(|file| file.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec!["firefox.desktop", "kate.desktop", "kde4-konqueror.desktop"]
+        );
+        assert_eq!(files[0].path, user.join("firefox.desktop"));
+
+        let (apps, _) = scan_desktop_entries(&[user, system]);
+        let mut names: Vec<_> = apps
+            .iter()
+            .map(|app| (app.name.as_str(), app.id.as_str()))
+            .collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            vec![
+                ("Konq", "kde4-konqueror.desktop"),
+                ("MyFirefox", "firefox.desktop")
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_lookalikes() {
+        let dir = TempDir::new("scan-lookalikes");
+        let entry = "[Desktop Entry]\nName=Editor\nExec=/bin/editor";
+        fs::write(dir.join("editor.desktop"), entry).unwrap();
+        fs::write(dir.join("org.editor.desktop"), entry).unwrap();
+        let (apps, _) = scan_desktop_entries(&[dir.to_path_buf()]);
+        let ids: Vec<_> = apps.iter().map(|app| app.id.as_str()).collect();
+        assert_eq!(ids, vec!["editor.desktop", "org.editor.desktop"]);
+    }
+
+    #[test]
+    fn filter_list() {
+        let mut hidden = app();
+        hidden.not_show_in = desktops(&["sway"]);
+        let (apps, errors) =
+            filter_apps(vec![app(), hidden], &desktops(&["sway"]));
+        assert_eq!(apps.len(), 1);
+        assert_eq!(errors.len(), 1);
+    }
+}
@@ -0,0 +1,291 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Cache of parsed desktop entries.
+//!
+//! Entries are keyed by their path and reused as long as the modification
+//! time and size of the file don't change. The cache is stored next to the
+//! apps DB file of the profile.
+use crate::desktop_entry::{
+    parse_desktop_file_with_locale, parse_entry_with_flags, EntryParseError,
+};
+use crate::locale::Locale;
+use crate::scan::{scan_desktop_entries_cached, DesktopFile, ScanError};
+use crate::App;
+use log::{debug, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+/// Version of the cache file format, caches with another version are
+/// discarded.
+const SCAN_CACHE_VERSION: u32 = 3;
+
+/// Parsed desktop entries from previous scans.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ScanCache {
+    /// Locales the entries were parsed for, the cache is cleared when they
+    /// change.
+    locales: Vec<String>,
+    entries: HashMap<PathBuf, CachedEntry>,
+}
+
+/// A parsed desktop entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedEntry {
+    /// Modification time of the file when it was parsed.
+    modified: SystemTime,
+    /// Size of the file when it was parsed.
+    size: u64,
+    /// The parsed app, `None` if the entry is hidden.
+    app: Option<App>,
+    /// Whether the entry has `NoDisplay` set, so the app isn't listed.
+    no_display: bool,
+}
+
+/// Scan the desktop entries in `paths` using the cache file at
+/// `cache_path`, if any.
+///
+/// See `scan::scan_desktop_entries`.
+pub fn scan_desktop_entries(
+    paths: &[PathBuf],
+    cache_path: Option<&Path>,
+) -> (Vec<App>, Vec<ScanError>) {
+    let path = match cache_path {
+        Some(path) => path,
+        None => return crate::scan::scan_desktop_entries(paths),
+    };
+    let mut cache = ScanCache::load(path);
+    let result = scan_desktop_entries_cached(paths, &mut cache);
+    if let Err(err) = cache.save(path) {
+        warn!("Failed to save scan cache {}: {}", path.display(), err);
+    }
+    result
+}
+
+impl ScanCache {
+    /// Load the cache from `path`.
+    ///
+    /// Returns an empty cache if the file doesn't exist or can't be read.
+    pub fn load(path: &Path) -> ScanCache {
+        let buf = match fs::read(path) {
+            Ok(buf) => buf,
+            Err(err) => {
+                debug!("Not using scan cache {}: {}", path.display(), err);
+                return ScanCache::default();
+            }
+        };
+        match rmp_serde::from_slice::<(u32, ScanCache)>(&buf) {
+            Ok((SCAN_CACHE_VERSION, cache)) => cache,
+            Ok((version, _)) => {
+                debug!("Discarding scan cache version {}", version);
+                ScanCache::default()
+            }
+            Err(err) => {
+                warn!("Discarding invalid scan cache: {}", err);
+                ScanCache::default()
+            }
+        }
+    }
+
+    /// Save the cache to `path`.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let buf = rmp_serde::to_vec(&(SCAN_CACHE_VERSION, self))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Write to a temporary file first so other processes never read a
+        // partially written cache
+        let tmp_path = path.with_extension(format!("{}.tmp", process::id()));
+        fs::write(&tmp_path, buf)
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&tmp_path);
+            })
+    }
+
+    /// Parse the desktop entries `files` for `locale`, in parallel.
+    ///
+    /// Entries that didn't change since they were cached aren't parsed
+    /// again. Afterwards the cache only contains the entries in `files`.
+    /// Returns `None` for the apps that aren't listed, see
+    /// `parse_desktop_file`.
+    pub fn parse(
+        &mut self,
+        files: &[DesktopFile],
+        locale: Option<&Locale>,
+    ) -> Vec<Result<Option<App>, EntryParseError>> {
+        self.parse_entries(files, locale)
+            .into_iter()
+            .zip(files)
+            .map(|(result, file)| match result {
+                Ok((app, no_display)) => Ok(app.filter(|_| !no_display)),
+                // Entries with `NoDisplay` set aren't listed, even if the
+                // rest of the entry is invalid
+                Err(_) => parse_desktop_file_with_locale(&file.path, locale),
+            })
+            .collect()
+    }
+
+    /// Parse the desktop entries `files` like `ScanCache::parse`, including
+    /// the apps of entries with `NoDisplay` set, ex. to open files.
+    ///
+    /// Returns `None` for hidden entries.
+    pub fn parse_all(
+        &mut self,
+        files: &[DesktopFile],
+        locale: Option<&Locale>,
+    ) -> Vec<Result<Option<App>, EntryParseError>> {
+        self.parse_entries(files, locale)
+            .into_iter()
+            .map(|result| result.map(|(app, _)| app))
+            .collect()
+    }
+
+    /// Parse the desktop entries `files` into their apps and whether they
+    /// have `NoDisplay` set, reusing the cached entries.
+    fn parse_entries(
+        &mut self,
+        files: &[DesktopFile],
+        locale: Option<&Locale>,
+    ) -> Vec<Result<(Option<App>, bool), EntryParseError>> {
+        let locales = locale.map(Locale::candidates).unwrap_or_default();
+        if self.locales != locales {
+            self.entries.clear();
+            self.locales = locales;
+        }
+        let parse = |path: &Path| {
+            parse_entry_with_flags(path, locale)
+                .map(|(app, flags)| (app, flags.no_display))
+        };
+        let cached = std::mem::take(&mut self.entries);
+        let (entries, results): (Vec<_>, Vec<_>) = files
+            .par_iter()
+            .map(|file| {
+                let stamp = fs::metadata(&file.path)
+                    .ok()
+                    .and_then(|meta| Some((meta.modified().ok()?, meta.len())));
+                let (modified, size) = match stamp {
+                    Some(stamp) => stamp,
+                    None => return (None, parse(&file.path)),
+                };
+                if let Some(entry) = cached.get(&file.path) {
+                    if entry.modified == modified && entry.size == size {
+                        let result = (entry.app.clone(), entry.no_display);
+                        return (
+                            Some((file.path.clone(), entry.clone())),
+                            Ok(result),
+                        );
+                    }
+                }
+                let result = parse(&file.path);
+                // Errors aren't cached so they're reported on every scan
+                let entry = result.as_ref().ok().map(|(app, no_display)| {
+                    let entry = CachedEntry {
+                        modified,
+                        size,
+                        app: app.clone(),
+                        no_display: *no_display,
+                    };
+                    (file.path.clone(), entry)
+                });
+                (entry, result)
+            })
+            .unzip();
+        self.entries = entries.into_iter().flatten().collect();
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn reuse_unchanged() {
+        let dir = TempDir::new("scan-cache");
+        let path = dir.join("test.desktop");
+        fs::write(&path, "[Desktop Entry]\nName=Test\nExec=/bin/test").unwrap();
+        let files = vec![DesktopFile {
+            id: "test.desktop".to_owned(),
+            path: path.clone(),
+        }];
+        let name = |results: Vec<Result<Option<App>, _>>| {
+            results[0].as_ref().unwrap().as_ref().unwrap().name.clone()
+        };
+
+        let mut cache = ScanCache::default();
+        assert_eq!(name(cache.parse(&files, None)), "Test");
+        // Changing the cached app shows it wasn't parsed again
+        cache
+            .entries
+            .get_mut(&path)
+            .unwrap()
+            .app
+            .as_mut()
+            .unwrap()
+            .name = "Cached".to_owned();
+        let cache_path = dir.join("scan_cache.db");
+        cache.save(&cache_path).unwrap();
+        let mut cache = ScanCache::load(&cache_path);
+        assert_eq!(name(cache.parse(&files, None)), "Cached");
+
+        let locale = Locale::parse("de").unwrap();
+        assert_eq!(name(cache.parse(&files, Some(&locale))), "Test");
+        fs::write(&path, "[Desktop Entry]\nName=Changed\nExec=/bin/test")
+            .unwrap();
+        assert_eq!(name(cache.parse(&files, Some(&locale))), "Changed");
+
+        assert!(cache.parse(&[], None).is_empty());
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn no_display() {
+        let dir = TempDir::new("scan-cache-no-display");
+        let entry = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+            DesktopFile {
+                id: name.to_owned(),
+                path,
+            }
+        };
+        let files = vec![
+            entry(
+                "settings.desktop",
+                "[Desktop Entry]\nName=Settings\nExec=settings\nNoDisplay=true",
+            ),
+            entry("broken.desktop", "[Desktop Entry]\nNoDisplay=true"),
+        ];
+
+        let mut cache = ScanCache::default();
+        let listed = cache.parse(&files, None);
+        assert!(matches!(listed[0], Ok(None)));
+        assert!(matches!(listed[1], Ok(None)));
+        let all = cache.parse_all(&files, None);
+        assert_eq!(all[0].as_ref().unwrap().as_ref().unwrap().name, "Settings");
+        assert!(all[1].is_err());
+        assert!(cache.entries[&files[0].path].no_display);
+    }
+}
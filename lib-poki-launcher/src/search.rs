@@ -0,0 +1,629 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Search providers for the results of `AppsDB::search`.
+//!
+//! A `SearchProvider` turns a query into a list of `SearchItem`s. The
+//! providers listed in the `search_providers` setting of the config are
+//! searched and their results merged by score.
+//!
+//! Scores of all providers are between 0 and 1 so the results of different
+//! providers can be compared. Providers with a single obvious result, like
+//! the calculator, score it 1; app scores only approach 1, see
+//! `normalize_score`.
+//!
+//! A provider can have a trigger, a prefix the query has to start with for
+//! the provider to be searched, ex. "=" for the calculator. When the query
+//! starts with the trigger of an enabled provider, only the providers with
+//! that trigger are searched, with the trigger removed from the query.
+//! Otherwise only the providers without a trigger are searched.
+use crate::config::Config;
+use crate::runner::RunError;
+use crate::{App, AppsDB};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Provides search results.
+pub trait SearchProvider: fmt::Debug + Send + Sync {
+    /// Name used to enable the provider in the config.
+    fn name(&self) -> &str;
+
+    /// Prefix the query has to start with for the provider to be searched.
+    ///
+    /// Providers without a trigger are searched when the query doesn't start
+    /// with any trigger.
+    fn trigger(&self) -> Option<&str> {
+        None
+    }
+
+    /// Search for `query`, the trigger of the provider has been removed.
+    fn search(&self, query: &str, apps_db: &AppsDB) -> Vec<SearchItem>;
+}
+
+/// A search result.
+#[derive(Debug, Clone)]
+pub struct SearchItem {
+    /// Main text of the result.
+    pub title: String,
+    /// Secondary text of the result, may be empty.
+    pub subtitle: String,
+    /// Icon name or path to an icon file, may be empty.
+    pub icon: String,
+    /// What to do when the result is picked.
+    pub action: SearchAction,
+    /// Score of the result between 0 and 1, higher scores are listed first.
+    pub score: f32,
+    /// Name of the provider of the result.
+    pub provider: String,
+}
+
+/// What to do when a search result is picked.
+#[derive(Debug, Clone)]
+pub enum SearchAction {
+    /// Launch an app.
+    ///
+    /// The launch should be recorded with `AppsDB::record_launch`.
+    Launch(Box<App>),
+    /// Open a file with the default app for its type.
+    Open(PathBuf),
+    /// Run a shell command.
+    Shell(String),
+    /// Copy text to the clipboard, this has to be done by the UI.
+    Copy(String),
+}
+
+impl SearchAction {
+    /// Perform the action.
+    ///
+    /// `Copy` actions are left to the UI and do nothing.
+    pub fn run(&self, config: &Config) -> Result<(), RunError> {
+        match self {
+            SearchAction::Launch(app) => app.run(config),
+            SearchAction::Open(path) => {
+                let path = path.to_string_lossy().into_owned();
+                command(vec!["xdg-open".to_owned(), path]).run(config)
+            }
+            SearchAction::Shell(cmd) => {
+                let args = vec!["sh".to_owned(), "-c".to_owned(), cmd.clone()];
+                command(args).run(config)
+            }
+            SearchAction::Copy(_) => Ok(()),
+        }
+    }
+}
+
+/// An app that runs `exec`, used to run the actions of search results.
+fn command(exec: Vec<String>) -> App {
+    App::new(exec.join(" "), String::new(), exec, false)
+}
+
+/// Create the built in providers.
+pub fn builtin_providers() -> Vec<Arc<dyn SearchProvider>> {
+    vec![
+        Arc::new(AppsProvider),
+        Arc::new(CalculatorProvider),
+        Arc::new(FilesProvider),
+        Arc::new(ShellProvider),
+    ]
+}
+
+/// Names of the built in providers, the default value of the
+/// `search_providers` setting.
+pub fn builtin_provider_names() -> Vec<String> {
+    vec![
+        AppsProvider::NAME.to_owned(),
+        CalculatorProvider::NAME.to_owned(),
+        FilesProvider::NAME.to_owned(),
+        ShellProvider::NAME.to_owned(),
+    ]
+}
+
+/// Map a score of 0 or more, ex. an app's `SearchResult::total`, to a
+/// score between 0 and 1 for `SearchItem::score`, keeping their order.
+pub fn normalize_score(score: f32) -> f32 {
+    let score = score.max(0.0);
+    score / (score + 1.0)
+}
+
+/// Search the given providers and merge their results by score.
+///
+/// Scores out of the range 0 to 1 are clamped to it. Results with the same
+/// score keep the order of `providers`.
+pub fn search<'a>(
+    providers: impl IntoIterator<Item = &'a dyn SearchProvider>,
+    query: &str,
+    apps_db: &AppsDB,
+) -> Vec<SearchItem> {
+    let providers: Vec<_> = providers.into_iter().collect();
+    let triggered: Vec<_> = providers
+        .iter()
+        .filter(|provider| {
+            provider
+                .trigger()
+                .is_some_and(|trigger| query.starts_with(trigger))
+        })
+        .collect();
+    let mut items: Vec<SearchItem> = if triggered.is_empty() {
+        providers
+            .iter()
+            .filter(|provider| provider.trigger().is_none())
+            .flat_map(|provider| provider.search(query, apps_db))
+            .collect()
+    } else {
+        triggered
+            .into_iter()
+            .flat_map(|provider| {
+                let trigger = provider.trigger().unwrap_or_default();
+                provider.search(&query[trigger.len()..], apps_db)
+            })
+            .collect()
+    };
+    for item in &mut items {
+        item.score = item.score.clamp(0.0, 1.0);
+    }
+    items.sort_by(|left, right| right.score.total_cmp(&left.score));
+    items
+}
+
+/// Search the apps in the database, see `AppsDB::get_ranked_list`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AppsProvider;
+
+impl AppsProvider {
+    /// Name of the provider.
+    pub const NAME: &'static str = "apps";
+}
+
+impl SearchProvider for AppsProvider {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn search(&self, query: &str, apps_db: &AppsDB) -> Vec<SearchItem> {
+        let search = if query.is_empty() { None } else { Some(query) };
+        apps_db
+            .get_ranked_list(search, None)
+            .into_iter()
+            .map(|result| {
+                let app = result.app;
+                SearchItem {
+                    title: app.name.clone(),
+                    subtitle: if app.generic_name.is_empty() {
+                        app.comment.clone()
+                    } else {
+                        app.generic_name.clone()
+                    },
+                    icon: app.icon.clone(),
+                    score: normalize_score(result.total),
+                    provider: Self::NAME.to_owned(),
+                    action: SearchAction::Launch(Box::new(app)),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Evaluate arithmetic expressions, ex. "=2*(3+4)".
+///
+/// Supports `+`, `-`, `*`, `/`, `%`, `^` and parentheses. Picking the result
+/// copies it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CalculatorProvider;
+
+impl CalculatorProvider {
+    /// Name of the provider.
+    pub const NAME: &'static str = "calculator";
+}
+
+impl SearchProvider for CalculatorProvider {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn trigger(&self) -> Option<&str> {
+        Some("=")
+    }
+
+    fn search(&self, query: &str, _apps_db: &AppsDB) -> Vec<SearchItem> {
+        let result = match eval(query) {
+            Some(result) => format_number(result),
+            None => return Vec::new(),
+        };
+        vec![SearchItem {
+            title: result.clone(),
+            subtitle: query.trim().to_owned(),
+            icon: "accessories-calculator".to_owned(),
+            action: SearchAction::Copy(result),
+            score: 1.0,
+            provider: Self::NAME.to_owned(),
+        }]
+    }
+}
+
+/// Deepest nesting of parentheses and unary operators `eval` accepts, so
+/// the parser can't overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+/// Evaluate an arithmetic expression.
+///
+/// Returns `None` if the expression is invalid, nested deeper than
+/// `MAX_DEPTH` or the result isn't finite.
+fn eval(expr: &str) -> Option<f64> {
+    let mut parser = Parser {
+        chars: expr.chars().filter(|c| !c.is_whitespace()).collect(),
+        pos: 0,
+        depth: 0,
+    };
+    let value = parser.expr()?;
+    if parser.pos == parser.chars.len() && value.is_finite() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Recursive descent parser for arithmetic expressions.
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// Number of nested calls of `Parser::unary`.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// expr = term (('+' | '-') term)*
+    fn expr(&mut self) -> Option<f64> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                value += self.term()?;
+            } else if self.eat('-') {
+                value -= self.term()?;
+            } else {
+                return Some(value);
+            }
+        }
+    }
+
+    /// term = unary (('*' | '/' | '%') unary)*
+    fn term(&mut self) -> Option<f64> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat('*') {
+                value *= self.unary()?;
+            } else if self.eat('/') {
+                value /= self.unary()?;
+            } else if self.eat('%') {
+                value %= self.unary()?;
+            } else {
+                return Some(value);
+            }
+        }
+    }
+
+    /// unary = ('-' | '+') unary | power
+    ///
+    /// Every recursion of the parser goes through here, so this is where
+    /// the nesting depth is limited.
+    fn unary(&mut self) -> Option<f64> {
+        if self.depth == MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let value = if self.eat('-') {
+            self.unary().map(|value| -value)
+        } else if self.eat('+') {
+            self.unary()
+        } else {
+            self.power()
+        };
+        self.depth -= 1;
+        value
+    }
+
+    /// power = primary ('^' unary)?
+    fn power(&mut self) -> Option<f64> {
+        let base = self.primary()?;
+        if self.eat('^') {
+            Some(base.powf(self.unary()?))
+        } else {
+            Some(base)
+        }
+    }
+
+    /// primary = number | '(' expr ')'
+    fn primary(&mut self) -> Option<f64> {
+        if self.eat('(') {
+            let value = self.expr()?;
+            return if self.eat(')') { Some(value) } else { None };
+        }
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+}
+
+/// Format a number without trailing zeros.
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        let formatted = format!("{:.10}", value);
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_owned()
+    }
+}
+
+/// Browse files, ex. "/usr/share". Picking a file opens it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FilesProvider;
+
+impl FilesProvider {
+    /// Name of the provider.
+    pub const NAME: &'static str = "files";
+}
+
+impl SearchProvider for FilesProvider {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn trigger(&self) -> Option<&str> {
+        Some("/")
+    }
+
+    /// List the files in the directory of the path in the query whose name
+    /// starts with the last component of the path. Hidden files are only
+    /// listed if the last component starts with a '.'.
+    fn search(&self, query: &str, _apps_db: &AppsDB) -> Vec<SearchItem> {
+        let path = Path::new("/").join(query);
+        let (dir, prefix) = if query.is_empty() || query.ends_with('/') {
+            (path.as_path(), String::new())
+        } else {
+            match (path.parent(), path.file_name()) {
+                (Some(dir), Some(name)) => {
+                    (dir, name.to_string_lossy().to_lowercase())
+                }
+                _ => return Vec::new(),
+            }
+        };
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let mut files: Vec<(String, PathBuf, bool)> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let lower = name.to_lowercase();
+                if !lower.starts_with(&prefix)
+                    || (name.starts_with('.') && !prefix.starts_with('.'))
+                {
+                    return None;
+                }
+                let is_dir = entry.path().is_dir();
+                Some((name, entry.path(), is_dir))
+            })
+            .collect();
+        files.sort();
+        files
+            .into_iter()
+            .map(|(name, path, is_dir)| SearchItem {
+                title: if is_dir { format!("{}/", name) } else { name },
+                subtitle: path.to_string_lossy().into_owned(),
+                icon: if is_dir { "folder" } else { "text-x-generic" }
+                    .to_owned(),
+                action: SearchAction::Open(path),
+                score: 1.0,
+                provider: Self::NAME.to_owned(),
+            })
+            .collect()
+    }
+}
+
+/// Run a shell command, ex. ">make -C ~/src/project".
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShellProvider;
+
+impl ShellProvider {
+    /// Name of the provider.
+    pub const NAME: &'static str = "shell";
+}
+
+impl SearchProvider for ShellProvider {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn trigger(&self) -> Option<&str> {
+        Some(">")
+    }
+
+    fn search(&self, query: &str, _apps_db: &AppsDB) -> Vec<SearchItem> {
+        let cmd = query.trim();
+        if cmd.is_empty() {
+            return Vec::new();
+        }
+        vec![SearchItem {
+            title: cmd.to_owned(),
+            subtitle: "Run command".to_owned(),
+            icon: "utilities-terminal".to_owned(),
+            action: SearchAction::Shell(cmd.to_owned()),
+            score: 1.0,
+            provider: Self::NAME.to_owned(),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{app, TempDir};
+
+    #[test]
+    fn calculator() {
+        assert_eq!(eval("1 + 2 * 3"), Some(7.0));
+        assert_eq!(eval("(1 + 2) * 3"), Some(9.0));
+        assert_eq!(eval("2 ^ 3 ^ 2"), Some(512.0));
+        assert_eq!(eval("-2 ^ 2"), Some(-4.0));
+        assert_eq!(eval("7 % 4 - -1"), Some(4.0));
+        assert_eq!(eval("1.5 / 2"), Some(0.75));
+        assert_eq!(eval("1 / 0"), None);
+        assert_eq!(eval("(1 + 2"), None);
+        assert_eq!(eval("1 +"), None);
+        assert_eq!(eval("firefox"), None);
+        assert_eq!(format_number(7.0), "7");
+        assert_eq!(format_number(0.1 + 0.2), "0.3");
+        assert_eq!(format_number(-2.5), "-2.5");
+    }
+
+    #[test]
+    fn calculator_depth() {
+        let nested =
+            |depth| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&nested(MAX_DEPTH - 1)), Some(1.0));
+        assert_eq!(eval(&nested(MAX_DEPTH)), None);
+        assert_eq!(eval(&nested(100_000)), None);
+        assert_eq!(eval(&format!("{}1", "-".repeat(100_000))), None);
+        assert_eq!(eval(&format!("2{}", "^2".repeat(100_000))), None);
+        assert_eq!(
+            eval(&format!("1{}", "+1".repeat(100_000))),
+            Some(100_001.0)
+        );
+    }
+
+    #[test]
+    fn files() {
+        let dir = TempDir::new("files");
+        fs::create_dir_all(dir.join("Documents")).unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        fs::write(dir.join("Novel.md"), "").unwrap();
+        fs::write(dir.join(".hidden"), "").unwrap();
+        let query = dir.to_str().unwrap().trim_start_matches('/');
+        let apps_db = AppsDB::new(Config::default(), vec![]);
+        let titles = |query: &str| -> Vec<String> {
+            FilesProvider
+                .search(query, &apps_db)
+                .into_iter()
+                .map(|item| item.title)
+                .collect()
+        };
+
+        assert_eq!(
+            titles(&format!("{}/", query)),
+            vec!["Documents/", "Novel.md", "notes.txt"]
+        );
+        assert_eq!(
+            titles(&format!("{}/no", query)),
+            vec!["Novel.md", "notes.txt"]
+        );
+        assert_eq!(titles(&format!("{}/.h", query)), vec![".hidden"]);
+        assert!(titles(&format!("{}/missing/", query)).is_empty());
+    }
+
+    #[derive(Debug)]
+    struct Fixed(&'static str, Option<&'static str>, f32);
+
+    impl SearchProvider for Fixed {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn trigger(&self) -> Option<&str> {
+            self.1
+        }
+
+        fn search(&self, query: &str, _apps_db: &AppsDB) -> Vec<SearchItem> {
+            vec![SearchItem {
+                title: format!("{}:{}", self.0, query),
+                subtitle: String::new(),
+                icon: String::new(),
+                action: SearchAction::Copy(query.to_owned()),
+                score: self.2,
+                provider: self.0.to_owned(),
+            }]
+        }
+    }
+
+    #[test]
+    fn merge_and_triggers() {
+        let apps_db = AppsDB::new(Config::default(), vec![]);
+        let providers: Vec<Box<dyn SearchProvider>> = vec![
+            Box::new(Fixed("low", None, 0.5)),
+            Box::new(Fixed("high", None, 0.9)),
+            Box::new(Fixed("tie", None, 0.5)),
+            Box::new(Fixed("eq", Some("="), 1.0)),
+        ];
+        let titles = |query: &str| -> Vec<String> {
+            search(providers.iter().map(Box::as_ref), query, &apps_db)
+                .into_iter()
+                .map(|item| item.title)
+                .collect()
+        };
+        assert_eq!(titles("a"), vec!["high:a", "low:a", "tie:a"]);
+        assert_eq!(titles("=1+1"), vec!["eq:1+1"]);
+    }
+
+    #[test]
+    fn merge_with_apps() {
+        let mut often = app("often.desktop", "Often", &["/bin/often"]);
+        often.score = 3.0;
+        let never = app("never.desktop", "Never", &["/bin/never"]);
+        let config = Config {
+            half_life: 1e6,
+            ..Config::default()
+        };
+        let apps_db = AppsDB::new(config, vec![often, never]);
+        let providers: Vec<Box<dyn SearchProvider>> = vec![
+            Box::new(AppsProvider),
+            Box::new(Fixed("middle", None, 0.5)),
+            Box::new(Fixed("huge", None, 100.0)),
+            Box::new(Fixed("negative", None, -1.0)),
+        ];
+        let items = search(providers.iter().map(Box::as_ref), "", &apps_db);
+        let titles: Vec<_> =
+            items.iter().map(|item| item.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            vec!["huge:", "Often", "middle:", "Never", "negative:"]
+        );
+        assert!(items.iter().all(|item| (0.0..=1.0).contains(&item.score)));
+        assert!((items[1].score - normalize_score(3.0)).abs() < 0.01);
+    }
+}
@@ -0,0 +1,204 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::ffi::OsString;
+use std::fmt;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+/// A file or URI to open with an app, see `App::run_with`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// A local file.
+    Path(PathBuf),
+    /// A URI, ex. `https://example.com` or `file:///home/user/file.txt`.
+    Uri(String),
+}
+
+impl Target {
+    /// Parse a target, strings that start with a URI scheme are URIs and
+    /// anything else is a path.
+    pub fn parse(s: &str) -> Target {
+        if has_scheme(s) {
+            Target::Uri(s.to_owned())
+        } else {
+            Target::Path(PathBuf::from(s))
+        }
+    }
+
+    /// The target as an absolute local path.
+    ///
+    /// Relative paths are made absolute using the current directory.
+    /// `file://` URIs are converted to paths, returns `None` for other URIs.
+    pub fn to_path(&self) -> Option<PathBuf> {
+        match self {
+            Target::Path(path) => Some(absolute(path)),
+            Target::Uri(uri) => {
+                let rest = uri.strip_prefix("file://")?;
+                // Only local files, `file:///path` or `file://localhost/path`
+                let path = rest.strip_prefix("localhost").unwrap_or(rest);
+                if !path.starts_with('/') {
+                    return None;
+                }
+                let path = percent_decode(path.as_bytes())?;
+                Some(PathBuf::from(OsString::from_vec(path)))
+            }
+        }
+    }
+
+    /// The target as a URI.
+    ///
+    /// Paths are converted to `file://` URIs, relative paths are made
+    /// absolute using the current directory.
+    pub fn to_uri(&self) -> String {
+        match self {
+            Target::Path(path) => {
+                format!("file://{}", percent_encode(&absolute(path)))
+            }
+            Target::Uri(uri) => uri.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Path(path) => write!(f, "{}", path.display()),
+            Target::Uri(uri) => write!(f, "{}", uri),
+        }
+    }
+}
+
+impl From<PathBuf> for Target {
+    fn from(path: PathBuf) -> Self {
+        Target::Path(path)
+    }
+}
+
+/// Make `path` absolute using the current directory.
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_owned();
+    }
+    std::env::current_dir()
+        .map(|dir| dir.join(path))
+        .unwrap_or_else(|_| path.to_owned())
+}
+
+/// Check if `s` starts with a URI scheme, ex. `https:`.
+///
+/// Schemes are at least 2 characters so Windows style drive letters don't
+/// count.
+fn has_scheme(s: &str) -> bool {
+    match s.find(':') {
+        Some(idx) if idx >= 2 => {
+            let scheme = &s[..idx];
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| {
+                    c.is_ascii_alphanumeric()
+                        || c == '+'
+                        || c == '-'
+                        || c == '.'
+                })
+        }
+        _ => false,
+    }
+}
+
+/// Percent encode a path for use in a `file://` URI.
+fn percent_encode(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~'
+            | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Decode the percent encoded bytes in `s`.
+///
+/// Returns `None` if a `%` isn't followed by two hex digits.
+fn percent_decode(s: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(s.len());
+    let mut iter = s.iter();
+    while let Some(&byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [*iter.next()?, *iter.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Target::parse("https://example.com"),
+            Target::Uri("https://example.com".to_owned())
+        );
+        assert_eq!(
+            Target::parse("/tmp/a:b.txt"),
+            Target::Path(PathBuf::from("/tmp/a:b.txt"))
+        );
+        assert_eq!(
+            Target::parse("C:/file"),
+            Target::Path(PathBuf::from("C:/file"))
+        );
+    }
+
+    #[test]
+    fn path_to_uri() {
+        let target = Target::Path(PathBuf::from("/home/me/My File#1.txt"));
+        assert_eq!(target.to_uri(), "file:///home/me/My%20File%231.txt");
+        assert_eq!(
+            Target::Uri(target.to_uri()).to_path(),
+            Some(PathBuf::from("/home/me/My File#1.txt"))
+        );
+    }
+
+    #[test]
+    fn uri_to_path() {
+        let path = |uri: &str| Target::Uri(uri.to_owned()).to_path();
+        assert_eq!(path("file:///tmp/a%20b"), Some(PathBuf::from("/tmp/a b")));
+        assert_eq!(
+            path("file://localhost/tmp/a"),
+            Some(PathBuf::from("/tmp/a"))
+        );
+        assert_eq!(path("file://server/tmp/a"), None);
+        assert_eq!(path("file:///tmp/%zz"), None);
+        assert_eq!(path("https://example.com"), None);
+        assert_eq!(
+            Target::Path(PathBuf::from("a.txt")).to_path(),
+            Some(std::env::current_dir().unwrap().join("a.txt"))
+        );
+    }
+}
@@ -0,0 +1,413 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Find the terminal emulator to run terminal apps in.
+//!
+//! Terminals are looked up like xdg-terminal-exec does:
+//! 1. The `term_cmd` setting in the config.
+//! 2. The desktop file IDs listed in the `xdg-terminals.list` and
+//!    `<desktop>-xdg-terminals.list` files in the config and data dirs.
+//! 3. Installed desktop entries in the `TerminalEmulator` category.
+//!
+//! The terminal found is cached until the preference lists or the app dirs
+//! change.
+use crate::config::Config;
+use crate::exec::parse_exec;
+use crate::scan::{current_desktops, desktop_entires, find_executable};
+use crate::xdg;
+use freedesktop_entry_parser as fdep;
+use lazy_static::lazy_static;
+use log::debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Placeholder for the command to run in a terminal command.
+const PLACEHOLDER: &str = "{}";
+
+/// A terminal emulator to run terminal apps in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Terminal {
+    /// The terminal command, `PLACEHOLDER` marks where the command to run
+    /// goes.
+    args: Vec<String>,
+}
+
+impl Terminal {
+    /// Create a terminal from a command like the `term_cmd` setting,
+    /// ex. `alacritty -e {}`.
+    ///
+    /// The command to run is appended if there is no `{}`.
+    pub fn from_command(command: &str) -> Terminal {
+        let mut args: Vec<_> =
+            command.split_whitespace().map(str::to_owned).collect();
+        if !args.iter().any(|arg| arg == PLACEHOLDER) {
+            args.push(PLACEHOLDER.to_owned());
+        }
+        Terminal { args }
+    }
+
+    /// Create a terminal from the exec of its desktop entry and the
+    /// arguments it takes before the command to run.
+    fn from_exec(mut exec: Vec<String>, exec_arg: &[&str]) -> Terminal {
+        exec.extend(exec_arg.iter().map(|arg| (*arg).to_owned()));
+        exec.push(PLACEHOLDER.to_owned());
+        Terminal { args: exec }
+    }
+
+    /// Build the command to run `exec` in the terminal.
+    pub fn wrap(&self, exec: &[String]) -> Vec<String> {
+        let mut args = Vec::with_capacity(self.args.len() + exec.len());
+        for arg in &self.args {
+            if arg == PLACEHOLDER {
+                args.extend(exec.iter().cloned());
+            } else {
+                args.push(arg.clone());
+            }
+        }
+        args
+    }
+}
+
+/// The arguments `program` takes before a command to run in it.
+///
+/// Used for terminals whose desktop entry doesn't have the
+/// `X-TerminalArgExec` key.
+fn exec_arg(program: &str) -> &'static [&'static str] {
+    let name = Path::new(program)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(program);
+    match name {
+        "gnome-terminal" | "kgx" | "ptyxis" | "kitty" | "foot"
+        | "footclient" => &["--"],
+        "xfce4-terminal" | "mate-terminal" | "terminator" | "tilix" => &["-x"],
+        "wezterm" => &["start", "--"],
+        _ => &["-e"],
+    }
+}
+
+lazy_static! {
+    /// The terminal found by `find_terminal`.
+    static ref CACHE: Mutex<TerminalCache> = Mutex::default();
+}
+
+/// Find the terminal to run terminal apps in.
+pub fn find_terminal(config: &Config) -> Option<Terminal> {
+    if let Some(command) = &config.term_cmd {
+        return Some(Terminal::from_command(command));
+    }
+    CACHE.lock().unwrap().get(&list_files(), &config.app_paths)
+}
+
+/// Cache of the terminal found by `find_in`.
+#[derive(Debug, Default)]
+struct TerminalCache {
+    /// The terminal and what it was found from.
+    cached: Option<(CacheKey, Option<Terminal>)>,
+}
+
+/// The preference lists and app dirs a terminal was found from, with their
+/// modification times.
+///
+/// The time of an app dir changes when entries are added to or removed
+/// from it, ex. when a terminal is installed.
+#[derive(Debug, PartialEq, Eq)]
+struct CacheKey {
+    paths: Vec<PathBuf>,
+    mtimes: Vec<Option<SystemTime>>,
+}
+
+impl CacheKey {
+    fn new(list_files: &[PathBuf], app_paths: &[PathBuf]) -> CacheKey {
+        let paths: Vec<_> =
+            list_files.iter().chain(app_paths).cloned().collect();
+        let mtimes = paths
+            .iter()
+            .map(|path| fs::metadata(path).and_then(|meta| meta.modified()))
+            .map(Result::ok)
+            .collect();
+        CacheKey { paths, mtimes }
+    }
+}
+
+impl TerminalCache {
+    /// Get the cached terminal, or find it with `find_in` if the
+    /// preference lists or app dirs changed.
+    fn get(
+        &mut self,
+        list_files: &[PathBuf],
+        app_paths: &[PathBuf],
+    ) -> Option<Terminal> {
+        let key = CacheKey::new(list_files, app_paths);
+        match &self.cached {
+            Some((cached_key, terminal)) if *cached_key == key => {
+                terminal.clone()
+            }
+            _ => {
+                let terminal = find_in(list_files, app_paths);
+                self.cached = Some((key, terminal.clone()));
+                terminal
+            }
+        }
+    }
+}
+
+/// Find a terminal from the preference lists in `list_files` and the
+/// desktop entries in `app_paths`.
+fn find_in(list_files: &[PathBuf], app_paths: &[PathBuf]) -> Option<Terminal> {
+    let mut preferred = Vec::new();
+    let mut excluded = Vec::new();
+    for path in list_files {
+        if let Ok(contents) = fs::read_to_string(path) {
+            parse_list(&contents, &mut preferred, &mut excluded);
+        }
+    }
+    let (entries, _) = desktop_entires(app_paths);
+    let preferred_terminal = preferred.iter().find_map(|id| {
+        let entry = entries.iter().find(|entry| &entry.id == id)?;
+        parse_entry(&entry.path, false)
+    });
+    if preferred_terminal.is_some() {
+        return preferred_terminal;
+    }
+    entries
+        .iter()
+        .filter(|entry| !excluded.contains(&entry.id))
+        .find_map(|entry| parse_entry(&entry.path, true))
+}
+
+/// Get the paths of the terminal preference lists, in order of precedence.
+fn list_files() -> Vec<PathBuf> {
+    let names: Vec<_> = current_desktops()
+        .iter()
+        .map(|desktop| format!("{}-xdg-terminals.list", desktop.to_lowercase()))
+        .chain(std::iter::once("xdg-terminals.list".to_owned()))
+        .collect();
+    let data_dirs = xdg::data_home()
+        .into_iter()
+        .chain(xdg::data_dirs())
+        .map(|dir| dir.join("xdg-terminal-exec"));
+    xdg::config_home()
+        .into_iter()
+        .chain(xdg::config_dirs())
+        .chain(data_dirs)
+        .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+        .collect()
+}
+
+/// Add the desktop file IDs in a preference list to `preferred`, or
+/// `excluded` for IDs prefixed with `-`.
+///
+/// Each line is a desktop file ID, optionally followed by `:` and an action
+/// which is ignored. Lines starting with `#` are comments.
+fn parse_list(
+    contents: &str,
+    preferred: &mut Vec<String>,
+    excluded: &mut Vec<String>,
+) {
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (list, entry) = match line.strip_prefix('-') {
+            Some(entry) => (&mut *excluded, entry),
+            None => (&mut *preferred, line.trim_start_matches('+')),
+        };
+        let id = entry.split(':').next().unwrap_or(entry).trim();
+        if id.ends_with(".desktop") && !list.iter().any(|other| other == id) {
+            list.push(id.to_owned());
+        }
+    }
+}
+
+/// Parse the desktop entry of a terminal.
+///
+/// Returns `None` if the entry is hidden or its program isn't installed.
+/// Terminals that weren't picked in a preference list, with
+/// `from_category` set, also have to be in the `TerminalEmulator` category
+/// and not have `NoDisplay=true`.
+fn parse_entry(path: &Path, from_category: bool) -> Option<Terminal> {
+    let file = fdep::parse_entry(path).ok()?;
+    let section = file.section("Desktop Entry");
+    if section.attr("Hidden") == Some("true") {
+        return None;
+    }
+    if from_category
+        && (section.attr("NoDisplay") == Some("true")
+            || !section.attr("Categories").is_some_and(|categories| {
+                categories.split(';').any(|c| c == "TerminalEmulator")
+            }))
+    {
+        return None;
+    }
+    let name = section.attr("Name").unwrap_or("");
+    let icon = section.attr("Icon").unwrap_or("");
+    let exec = parse_exec(
+        section.attr("Exec")?,
+        name,
+        icon,
+        path.to_string_lossy().as_ref(),
+    )
+    .ok()?;
+    let program = section.attr("TryExec").unwrap_or(&exec.args[0]);
+    find_executable(program)?;
+    debug!("Using terminal {}", path.display());
+    Some(match section.attr("X-TerminalArgExec") {
+        Some(arg) => {
+            let arg: Vec<_> = arg.split_whitespace().collect();
+            Terminal::from_exec(exec.args, &arg)
+        }
+        None => {
+            let arg = exec_arg(&exec.args[0]);
+            Terminal::from_exec(exec.args, arg)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn ovec(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| (*arg).to_owned()).collect()
+    }
+
+    #[test]
+    fn command() {
+        let exec = ovec(&["htop", "-d", "10"]);
+        assert_eq!(
+            Terminal::from_command("alacritty -e {} --hold").wrap(&exec),
+            ovec(&["alacritty", "-e", "htop", "-d", "10", "--hold"])
+        );
+        assert_eq!(
+            Terminal::from_command("urxvt  -e").wrap(&exec),
+            ovec(&["urxvt", "-e", "htop", "-d", "10"])
+        );
+    }
+
+    #[test]
+    fn conventions() {
+        let wrap = |program: &str| {
+            Terminal::from_exec(ovec(&[program]), exec_arg(program))
+                .wrap(&ovec(&["htop"]))
+        };
+        assert_eq!(wrap("xterm"), ovec(&["xterm", "-e", "htop"]));
+        assert_eq!(
+            wrap("/usr/bin/gnome-terminal"),
+            ovec(&["/usr/bin/gnome-terminal", "--", "htop"])
+        );
+        assert_eq!(
+            wrap("xfce4-terminal"),
+            ovec(&["xfce4-terminal", "-x", "htop"])
+        );
+        assert_eq!(wrap("wezterm"), ovec(&["wezterm", "start", "--", "htop"]));
+    }
+
+    #[test]
+    fn list() {
+        let mut preferred = Vec::new();
+        let mut excluded = Vec::new();
+        parse_list(
+            "# Preferred terminals\n\
+             foot.desktop:server\n\
+             \n\
+             +kitty.desktop\n\
+             -xterm.desktop\n\
+             foot.desktop\n\
+             not-an-id\n",
+            &mut preferred,
+            &mut excluded,
+        );
+        assert_eq!(preferred, ovec(&["foot.desktop", "kitty.desktop"]));
+        assert_eq!(excluded, ovec(&["xterm.desktop"]));
+    }
+
+    #[test]
+    fn find() {
+        let dir = TempDir::new("terminal");
+        let apps = dir.join("applications");
+        fs::create_dir_all(&apps).unwrap();
+        let entry = |id: &str, contents: &str| {
+            fs::write(apps.join(id), contents).unwrap();
+        };
+        entry(
+            "b-term.desktop",
+            "[Desktop Entry]\nName=B\nExec=sh -l\nCategories=System;TerminalEmulator;\nX-TerminalArgExec=-c",
+        );
+        entry(
+            "c-term.desktop",
+            "[Desktop Entry]\nName=C\nExec=sh\nX-TerminalArgExec=",
+        );
+        entry(
+            "a-missing.desktop",
+            "[Desktop Entry]\nName=M\nExec=poki-missing-terminal\nCategories=TerminalEmulator;",
+        );
+        entry(
+            "a-no-display.desktop",
+            "[Desktop Entry]\nName=N\nExec=sh\nCategories=TerminalEmulator;\nNoDisplay=true",
+        );
+        let exec = ovec(&["htop"]);
+        let wrap = |lists: &[PathBuf]| {
+            find_in(lists, std::slice::from_ref(&apps))
+                .map(|term| term.wrap(&exec))
+        };
+        assert_eq!(wrap(&[]), Some(ovec(&["sh", "-l", "-c", "htop"])));
+
+        let list = dir.join("xdg-terminals.list");
+        fs::write(&list, "a-missing.desktop\nc-term.desktop\n").unwrap();
+        assert_eq!(
+            wrap(std::slice::from_ref(&list)),
+            Some(ovec(&["sh", "htop"]))
+        );
+
+        fs::write(&list, "-b-term.desktop\n").unwrap();
+        assert_eq!(wrap(&[list]), None);
+    }
+
+    #[test]
+    fn cache() {
+        let dir = TempDir::new("terminal-cache");
+        let apps = dir.join("applications");
+        fs::create_dir_all(&apps).unwrap();
+        let term = apps.join("term.desktop");
+        let write_term = |exec: &str| {
+            let contents = format!(
+                "[Desktop Entry]\nName=T\nExec={}\nCategories=TerminalEmulator;",
+                exec
+            );
+            fs::write(&term, contents).unwrap();
+        };
+        write_term("sh");
+        let list = dir.join("xdg-terminals.list");
+        let lists = std::slice::from_ref(&list);
+        let app_paths = std::slice::from_ref(&apps);
+        let exec = ovec(&["htop"]);
+        let mut cache = TerminalCache::default();
+        let mut wrap =
+            || cache.get(lists, app_paths).map(|term| term.wrap(&exec));
+        assert_eq!(wrap(), Some(ovec(&["sh", "-e", "htop"])));
+
+        // Changing an entry doesn't change the time of its dir
+        write_term("sh -l");
+        assert_eq!(wrap(), Some(ovec(&["sh", "-e", "htop"])));
+
+        fs::write(&list, "term.desktop\n").unwrap();
+        assert_eq!(wrap(), Some(ovec(&["sh", "-l", "-e", "htop"])));
+    }
+}
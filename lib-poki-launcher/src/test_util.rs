@@ -0,0 +1,75 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Fixtures shared by the tests of the modules.
+use crate::App;
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Create an app with the desktop file ID `id`.
+pub fn app(id: &str, name: &str, exec: &[&str]) -> App {
+    let mut app = App::new(
+        name.to_owned(),
+        "icon".to_owned(),
+        exec.iter().map(|arg| (*arg).to_owned()).collect(),
+        false,
+    );
+    app.id = id.to_owned();
+    app
+}
+
+/// An empty dir in the temp dir, removed with its contents when dropped so
+/// it's cleaned up when a test panics too.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create the dir, `name` must be unique among the tests since they
+    /// run in parallel.
+    pub fn new(name: &str) -> TempDir {
+        let path =
+            env::temp_dir().join(format!("poki-{}-{}", name, process::id()));
+        // Left over by a killed test run
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
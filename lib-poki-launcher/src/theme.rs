@@ -0,0 +1,589 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Themes of the launcher window.
+//!
+//! A theme is a hjson file named `<name>.hjson` in one of the theme
+//! directories of the profile, see `theme_dirs`. Every key is optional,
+//! keys a theme doesn't set are taken from the theme named by its `inherits`
+//! key, or from the default theme:
+//!
+//! ```hjson
+//! {
+//!   inherits: default
+//!   background_color: "#1e1e2e"
+//!   corner_radius: 8
+//! }
+//! ```
+//!
+//! A theme can have dark and light variants named `<name>-dark` and
+//! `<name>-light` which are picked according to the system's color scheme,
+//! see `ColorScheme::from_system`.
+use crate::{profile::Profile, xdg, DIRS};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Themes that are always available, as name and source.
+const BUILTIN_THEMES: &[(&str, &str)] = &[
+    ("default", ""),
+    (
+        "default-light",
+        r##"{
+            background_color: "#f8f8f2"
+            border_color: "#e0e0e0"
+            input_box_color: "#e6e6e6"
+            input_text_color: "#282a36"
+            selected_app_color: "#dcdcdc"
+            app_text_color: "#282a36"
+            app_separator_color: "#7c4dff"
+        }"##,
+    ),
+];
+
+/// The appearance of the launcher window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    /// Font family of all text.
+    ///
+    /// Default: sans-serif
+    pub font_family: String,
+    /// Input box font size.
+    ///
+    /// Default: 13
+    pub input_font_size: i32,
+    /// App list font size.
+    ///
+    /// Default: 20
+    pub app_font_size: i32,
+    /// Window background color.
+    ///
+    /// Default: #282a36
+    pub background_color: Rgba,
+    /// Window border color.
+    ///
+    /// Default: #2e303b
+    pub border_color: Rgba,
+    /// Input box background color.
+    ///
+    /// Default: #44475a
+    pub input_box_color: Rgba,
+    /// Input box text color.
+    ///
+    /// Default: #f8f8f2
+    pub input_text_color: Rgba,
+    /// App list selected app background color.
+    ///
+    /// Default: #44475a
+    pub selected_app_color: Rgba,
+    /// App list text color.
+    ///
+    /// Default: #f8f8f2
+    pub app_text_color: Rgba,
+    /// App list separator color.
+    ///
+    /// Default: #bd93f9
+    pub app_separator_color: Rgba,
+    /// Padding around the input box and the apps in the list in pxs.
+    ///
+    /// Default: 5
+    pub padding: i32,
+    /// Radius of the corners of the window and the input box in pxs.
+    ///
+    /// Default: 0
+    pub corner_radius: i32,
+    /// Ratio between input box height and total window height.
+    ///
+    /// Default: 0.1
+    /// Ex. 0.1 == 10% of total window height
+    pub input_box_ratio: f32,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let color = |hex: &str| hex.parse().unwrap();
+        Theme {
+            font_family: "sans-serif".into(),
+            input_font_size: 13,
+            app_font_size: 20,
+
+            background_color: color("#282a36"),
+            border_color: color("#2e303b"),
+            input_box_color: color("#44475a"),
+            input_text_color: color("#f8f8f2"),
+            selected_app_color: color("#44475a"),
+            app_text_color: color("#f8f8f2"),
+            app_separator_color: color("#bd93f9"),
+
+            padding: 5,
+            corner_radius: 0,
+            input_box_ratio: 0.1,
+        }
+    }
+}
+
+/// Directories the themes of `profile` are loaded from, highest precedence
+/// first.
+///
+/// The theme dir of the profile, then `themes` in the config dir, then
+/// `poki-launcher/themes` in each of $XDG_DATA_DIRS.
+pub fn theme_dirs(profile: &Profile) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = profile
+        .theme_dir()
+        .map(Path::to_owned)
+        .into_iter()
+        .collect();
+    let user_dir = DIRS.config_dir().join("themes");
+    if !dirs.contains(&user_dir) {
+        dirs.push(user_dir);
+    }
+    dirs.extend(
+        xdg::data_dirs()
+            .into_iter()
+            .map(|dir| dir.join("poki-launcher/themes")),
+    );
+    dirs
+}
+
+impl Theme {
+    /// Load the theme `name` from the theme directories of `profile`.
+    ///
+    /// If `scheme` is given and the theme has a variant for it, the variant
+    /// is loaded instead.
+    pub fn load(
+        profile: &Profile,
+        name: &str,
+        scheme: Option<ColorScheme>,
+    ) -> Result<Theme, ThemeError> {
+        Theme::load_from(&theme_dirs(profile), name, scheme)
+    }
+
+    /// Load the theme `name` from `dirs`, see `Theme::load`.
+    pub fn load_from(
+        dirs: &[PathBuf],
+        name: &str,
+        scheme: Option<ColorScheme>,
+    ) -> Result<Theme, ThemeError> {
+        let variant = scheme
+            .map(|scheme| format!("{}-{}", name, scheme))
+            .filter(|variant| find_theme(dirs, variant).is_some());
+        let name = variant.as_deref().unwrap_or(name);
+        load_inherited(dirs, name, &mut HashSet::new())
+    }
+
+    /// Set the value of `key` from a theme file.
+    ///
+    /// Returns why the value is invalid on error.
+    pub(crate) fn set(
+        &mut self,
+        key: &str,
+        value: config::Value,
+    ) -> Result<(), String> {
+        fn size(value: config::Value) -> Result<i32, String> {
+            let value = value.into_int().map_err(|err| err.to_string())?;
+            i32::try_from(value)
+                .ok()
+                .filter(|value| *value >= 0)
+                .ok_or_else(|| format!("{} is not a valid size", value))
+        }
+        fn color(value: config::Value) -> Result<Rgba, String> {
+            let value = value.into_str().map_err(|err| err.to_string())?;
+            value
+                .parse()
+                .map_err(|err: ParseColorError| err.to_string())
+        }
+
+        match key {
+            "font_family" => {
+                self.font_family =
+                    value.into_str().map_err(|err| err.to_string())?
+            }
+            "input_font_size" => self.input_font_size = size(value)?,
+            "app_font_size" => self.app_font_size = size(value)?,
+            "background_color" => self.background_color = color(value)?,
+            "border_color" => self.border_color = color(value)?,
+            "input_box_color" => self.input_box_color = color(value)?,
+            "input_text_color" => self.input_text_color = color(value)?,
+            "selected_app_color" => self.selected_app_color = color(value)?,
+            "app_text_color" => self.app_text_color = color(value)?,
+            "app_separator_color" => self.app_separator_color = color(value)?,
+            "padding" => self.padding = size(value)?,
+            "corner_radius" => self.corner_radius = size(value)?,
+            "input_box_ratio" => {
+                let ratio =
+                    value.into_float().map_err(|err| err.to_string())?;
+                if !(0.0..=1.0).contains(&ratio) {
+                    return Err(format!("{} is not between 0 and 1", ratio));
+                }
+                self.input_box_ratio = ratio as f32;
+            }
+            _ => return Err("Unknown key".to_owned()),
+        }
+        Ok(())
+    }
+}
+
+/// Where a theme was found.
+enum ThemeSource {
+    File(PathBuf),
+    Builtin(&'static str),
+}
+
+/// Find the theme `name` in `dirs` or the builtin themes.
+fn find_theme(dirs: &[PathBuf], name: &str) -> Option<ThemeSource> {
+    dirs.iter()
+        .map(|dir| dir.join(format!("{}.hjson", name)))
+        .find(|path| path.is_file())
+        .map(ThemeSource::File)
+        .or_else(|| {
+            BUILTIN_THEMES
+                .iter()
+                .find(|(builtin, _)| *builtin == name)
+                .map(|(_, source)| ThemeSource::Builtin(source))
+        })
+}
+
+/// Load the theme `name` and the themes it inherits from.
+///
+/// `seen` are the names of the themes that inherit from this one, to detect
+/// cycles.
+fn load_inherited(
+    dirs: &[PathBuf],
+    name: &str,
+    seen: &mut HashSet<String>,
+) -> Result<Theme, ThemeError> {
+    if !seen.insert(name.to_owned()) {
+        return Err(ThemeError::InheritanceCycle(name.to_owned()));
+    }
+    let (path, source) = match find_theme(dirs, name) {
+        Some(ThemeSource::File(path)) => {
+            let source =
+                fs::read_to_string(&path).map_err(|err| ThemeError::Read {
+                    path: path.clone(),
+                    err,
+                })?;
+            (path, source)
+        }
+        Some(ThemeSource::Builtin(source)) => (
+            PathBuf::from(format!("<builtin>/{}", name)),
+            source.to_owned(),
+        ),
+        None => return Err(ThemeError::NotFound(name.to_owned())),
+    };
+    let mut values = parse_theme_file(&path, &source)?;
+
+    let mut theme = match values.remove("inherits") {
+        Some(parent) => {
+            let parent = parent.into_str().map_err(|err| ThemeError::Key {
+                path: path.clone(),
+                key: "inherits".to_owned(),
+                message: err.to_string(),
+            })?;
+            load_inherited(dirs, &parent, seen)?
+        }
+        None => Theme::default(),
+    };
+    // Sorted so the same error is reported every time
+    let mut values: Vec<_> = values.into_iter().collect();
+    values.sort_by(|(left, _), (right, _)| left.cmp(right));
+    for (key, value) in values {
+        theme.set(&key, value).map_err(|message| ThemeError::Key {
+            path: path.clone(),
+            key,
+            message,
+        })?;
+    }
+    Ok(theme)
+}
+
+/// Parse the top level keys of the theme file at `path`.
+fn parse_theme_file(
+    path: &Path,
+    source: &str,
+) -> Result<HashMap<String, config::Value>, ThemeError> {
+    if source.trim().is_empty() {
+        return Ok(HashMap::new());
+    }
+    let mut cfg = config::Config::default();
+    cfg.merge(config::File::from_str(source, config::FileFormat::Hjson))
+        .and_then(|cfg| cfg.clone().try_into())
+        .map_err(|err| ThemeError::Parse {
+            path: path.to_owned(),
+            err,
+        })
+}
+
+/// A color with an alpha channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba {
+    /// The channels as floats between 0 and 1.
+    pub fn to_f32(self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a].map(|channel| channel as f32 / 255.)
+    }
+}
+
+impl FromStr for Rgba {
+    type Err = ParseColorError;
+
+    /// Parse a `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa` hex color.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseColorError(s.to_owned());
+        let hex = s.strip_prefix('#').ok_or_else(invalid)?;
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let digits: Vec<u8> = match hex.len() {
+            3 | 4 => hex
+                .chars()
+                .map(|c| c.to_digit(16).unwrap() as u8 * 0x11)
+                .collect(),
+            6 | 8 => (0..hex.len())
+                .step_by(2)
+                .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).unwrap())
+                .collect(),
+            _ => return Err(invalid()),
+        };
+        Ok(Rgba {
+            r: digits[0],
+            g: digits[1],
+            b: digits[2],
+            a: digits.get(3).copied().unwrap_or(0xff),
+        })
+    }
+}
+
+impl fmt::Display for Rgba {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)?;
+        if self.a != 0xff {
+            write!(f, "{:02x}", self.a)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error parsing a color.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Invalid color `{0}`, expected #rgb, #rgba, #rrggbb or #rrggbbaa")]
+pub struct ParseColorError(String);
+
+/// Whether the system prefers dark or light colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorScheme {
+    Dark,
+    Light,
+}
+
+impl ColorScheme {
+    /// Read the color scheme preferred by the system from the GTK settings
+    /// file, `gtk-4.0/settings.ini` or `gtk-3.0/settings.ini` in the config
+    /// home.
+    ///
+    /// Returns `None` if no preference is set.
+    pub fn from_system() -> Option<ColorScheme> {
+        let config_home = xdg::config_home()?;
+        ["gtk-4.0", "gtk-3.0"].iter().find_map(|dir| {
+            let settings =
+                fs::read_to_string(config_home.join(dir).join("settings.ini"))
+                    .ok()?;
+            ColorScheme::from_gtk_settings(&settings)
+        })
+    }
+
+    /// Get the color scheme from the contents of a GTK `settings.ini`.
+    ///
+    /// Uses `gtk-application-prefer-dark-theme`, or whether the name of the
+    /// GTK theme ends in `-dark`.
+    fn from_gtk_settings(settings: &str) -> Option<ColorScheme> {
+        let value = |key: &str| {
+            settings.lines().find_map(|line| {
+                let (line_key, value) = line.split_once('=')?;
+                Some(value.trim()).filter(|_| line_key.trim() == key)
+            })
+        };
+        match value("gtk-application-prefer-dark-theme") {
+            Some("1") | Some("true") => return Some(ColorScheme::Dark),
+            Some("0") | Some("false") => return Some(ColorScheme::Light),
+            _ => {}
+        }
+        value("gtk-theme-name").map(|theme| {
+            if theme.to_lowercase().ends_with("-dark") {
+                ColorScheme::Dark
+            } else {
+                ColorScheme::Light
+            }
+        })
+    }
+}
+
+impl fmt::Display for ColorScheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColorScheme::Dark => f.write_str("dark"),
+            ColorScheme::Light => f.write_str("light"),
+        }
+    }
+}
+
+/// Error loading a theme.
+#[derive(Debug, Error)]
+pub enum ThemeError {
+    #[error("Theme `{0}` not found")]
+    NotFound(String),
+    #[error("Theme `{0}` inherits from itself")]
+    InheritanceCycle(String),
+    #[error("Error reading theme file {}: {err}", .path.display())]
+    Read { path: PathBuf, err: io::Error },
+    #[error("Error parsing theme file {}: {err}", .path.display())]
+    Parse {
+        path: PathBuf,
+        err: config::ConfigError,
+    },
+    #[error("Invalid `{key}` in theme file {}: {message}", .path.display())]
+    Key {
+        path: PathBuf,
+        key: String,
+        message: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn parse_color() {
+        let color = |r, g, b, a| Rgba { r, g, b, a };
+        assert_eq!("#282a36".parse(), Ok(color(0x28, 0x2a, 0x36, 0xff)));
+        assert_eq!("#282a3680".parse(), Ok(color(0x28, 0x2a, 0x36, 0x80)));
+        assert_eq!("#fa0".parse(), Ok(color(0xff, 0xaa, 0x00, 0xff)));
+        assert_eq!("#fa08".parse(), Ok(color(0xff, 0xaa, 0x00, 0x88)));
+        for invalid in &["282a36", "#282a3", "#28 a36", "#ééé", ""] {
+            assert!(invalid.parse::<Rgba>().is_err(), "{}", invalid);
+        }
+        assert_eq!(color(0x28, 0x2a, 0x36, 0xff).to_string(), "#282a36");
+        assert_eq!(color(0x28, 0x2a, 0x36, 0x80).to_string(), "#282a3680");
+    }
+
+    #[test]
+    fn load() {
+        let dir = TempDir::new("themes");
+        let dirs = vec![dir.to_path_buf()];
+        fs::write(
+            dir.join("base.hjson"),
+            "{\nfont_family: Inter\nbackground_color: \"#000\"\npadding: 8\n}",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("mine.hjson"),
+            "{\ninherits: base\nbackground_color: \"#111\"\n}",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("mine-light.hjson"),
+            "{\ninherits: mine\nbackground_color: \"#eee\"\n}",
+        )
+        .unwrap();
+
+        let theme = Theme::load_from(&dirs, "mine", None).unwrap();
+        assert_eq!(theme.font_family, "Inter");
+        assert_eq!(theme.background_color, "#111".parse().unwrap());
+        assert_eq!(theme.padding, 8);
+        assert_eq!(theme.app_font_size, Theme::default().app_font_size);
+        let theme =
+            Theme::load_from(&dirs, "mine", Some(ColorScheme::Light)).unwrap();
+        assert_eq!(theme.background_color, "#eee".parse().unwrap());
+        assert_eq!(theme.padding, 8);
+        let theme =
+            Theme::load_from(&dirs, "mine", Some(ColorScheme::Dark)).unwrap();
+        assert_eq!(theme.background_color, "#111".parse().unwrap());
+        let theme =
+            Theme::load_from(&dirs, "default", Some(ColorScheme::Light))
+                .unwrap();
+        assert_eq!(theme.background_color, "#f8f8f2".parse().unwrap());
+        assert_eq!(
+            Theme::load_from(&dirs, "default", None).unwrap(),
+            Theme::default()
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let dir = TempDir::new("theme-errors");
+        let dirs = vec![dir.to_path_buf()];
+        let load = |source: &str| {
+            let source = format!("{{\n{}\n}}", source);
+            fs::write(dir.join("test.hjson"), source).unwrap();
+            Theme::load_from(&dirs, "test", None).unwrap_err()
+        };
+
+        match load("padding: 1\nborder_color: \"#12345\"") {
+            ThemeError::Key { path, key, .. } => {
+                assert_eq!(path, dir.join("test.hjson"));
+                assert_eq!(key, "border_color");
+            }
+            err => panic!("Unexpected error {}", err),
+        }
+        match load("paddign: 1") {
+            ThemeError::Key { key, .. } => assert_eq!(key, "paddign"),
+            err => panic!("Unexpected error {}", err),
+        }
+        match load("app_font_size: -3") {
+            ThemeError::Key { key, .. } => assert_eq!(key, "app_font_size"),
+            err => panic!("Unexpected error {}", err),
+        }
+        assert!(matches!(load("padding: ["), ThemeError::Parse { .. }));
+        assert!(matches!(
+            load("inherits: missing"),
+            ThemeError::NotFound(name) if name == "missing"
+        ));
+        assert!(matches!(
+            load("inherits: test"),
+            ThemeError::InheritanceCycle(name) if name == "test"
+        ));
+    }
+
+    #[test]
+    fn gtk_settings() {
+        let scheme = ColorScheme::from_gtk_settings;
+        assert_eq!(
+            scheme("[Settings]\ngtk-application-prefer-dark-theme=1\n"),
+            Some(ColorScheme::Dark)
+        );
+        assert_eq!(
+            scheme("[Settings]\ngtk-theme-name = Adwaita-dark\n"),
+            Some(ColorScheme::Dark)
+        );
+        assert_eq!(
+            scheme("[Settings]\ngtk-application-prefer-dark-theme = false\ngtk-theme-name=Adwaita-dark"),
+            Some(ColorScheme::Light)
+        );
+        assert_eq!(scheme("[Settings]\ngtk-font-name=Inter 11\n"), None);
+    }
+}
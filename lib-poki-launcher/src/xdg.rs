@@ -0,0 +1,56 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use directories_next::BaseDirs;
+use std::env;
+use std::path::PathBuf;
+
+/// Get a list of absolute paths from the colon separated variable `var`,
+/// or `default` if it is unset or empty.
+fn dir_list(var: &str, default: &str) -> Vec<PathBuf> {
+    let dirs: Vec<PathBuf> = env::var_os(var)
+        .map(|value| {
+            env::split_paths(&value)
+                .filter(|path| path.is_absolute())
+                .collect()
+        })
+        .unwrap_or_default();
+    if dirs.is_empty() {
+        env::split_paths(default).collect()
+    } else {
+        dirs
+    }
+}
+
+/// `$XDG_DATA_HOME`, defaults to `~/.local/share`.
+pub fn data_home() -> Option<PathBuf> {
+    BaseDirs::new().map(|dirs| dirs.data_dir().to_owned())
+}
+
+/// `$XDG_DATA_DIRS`, defaults to `/usr/local/share:/usr/share`.
+pub fn data_dirs() -> Vec<PathBuf> {
+    dir_list("XDG_DATA_DIRS", "/usr/local/share:/usr/share")
+}
+
+/// `$XDG_CONFIG_HOME`, defaults to `~/.config`.
+pub fn config_home() -> Option<PathBuf> {
+    BaseDirs::new().map(|dirs| dirs.config_dir().to_owned())
+}
+
+/// `$XDG_CONFIG_DIRS`, defaults to `/etc/xdg`.
+pub fn config_dirs() -> Vec<PathBuf> {
+    dir_list("XDG_CONFIG_DIRS", "/etc/xdg")
+}
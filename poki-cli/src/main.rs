@@ -0,0 +1,489 @@
+/***
+ * This file is part of Poki Launcher.
+ *
+ * Poki Launcher is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * Poki Launcher is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with Poki Launcher.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! `poki`, the command line frontend of Poki Launcher.
+//!
+//! Apps are printed one per line as `<key>\t<name>`, where the key is the
+//! desktop file ID of the app followed by `:<action>` for desktop actions.
+//! Keys can be passed to `poki launch`, ex:
+//!
+//! ```sh
+//! poki search | fzf --with-nth 2.. -d '\t' | cut -f1 | xargs poki launch
+//! ```
+//!
+//! With `--json` the output is JSON instead, apps are listed with their
+//! scores and the positions of the characters matched by the search.
+use chrono::{Local, TimeZone};
+use clap::{Parser, Subcommand};
+use lib_poki_launcher::autostart::{self, AutostartEntry};
+use lib_poki_launcher::daemon::{Daemon, DaemonError};
+use lib_poki_launcher::error::{AppDBError, ConfigError, RunError, ScanError};
+use lib_poki_launcher::{
+    ipc, App, AppKey, AppsDB, Config, Profile, RunOptions, SearchResult,
+    START_GRACE_PERIOD,
+};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::process;
+use thiserror::Error;
+
+/// Command line frontend of Poki Launcher
+#[derive(Debug, Parser)]
+#[clap(name = "poki", version)]
+struct Cli {
+    /// Print JSON instead of text
+    #[clap(long, global = true)]
+    json: bool,
+    /// Use the named profile instead of the default one
+    #[clap(long, global = true)]
+    profile: Option<String>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List all apps, highest ranked first
+    List,
+    /// Search the apps, best match first
+    Search {
+        /// What to search for, lists all apps if empty
+        #[clap(default_value = "")]
+        query: String,
+        /// Maximum number of apps to print
+        #[clap(long, short)]
+        limit: Option<usize>,
+    },
+    /// Launch an app and record the launch
+    Launch {
+        /// Key of the app as printed by `list` or `search`, or a search
+        /// whose best match is launched
+        app: String,
+    },
+    /// Scan for desktop entries again
+    Rescan,
+    /// Print launch statistics
+    Stats {
+        /// Maximum number of apps to print
+        #[clap(long, short, default_value = "10")]
+        limit: usize,
+    },
+    /// Print the config
+    Config {
+        /// Print the full config including default values instead of the
+        /// path to the config file
+        #[clap(long)]
+        dump: bool,
+    },
+    /// Export or import the apps database
+    #[clap(subcommand)]
+    Db(DbCommand),
+    /// Keep the apps in memory and answer the requests of launchers
+    Daemon {
+        /// Socket to listen on instead of the default one
+        #[clap(long)]
+        socket: Option<PathBuf>,
+    },
+    /// Start the apps in the XDG autostart dirs, ex. from the config of a
+    /// window manager
+    Autostart {
+        /// List the entries and whether they would be started instead of
+        /// starting them
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum DbCommand {
+    /// Export the database as JSON
+    Export {
+        /// File to write to, stdout if not given
+        file: Option<PathBuf>,
+    },
+    /// Replace the database with an export
+    Import {
+        /// File to read from, stdin if not given
+        file: Option<PathBuf>,
+    },
+}
+
+fn main() {
+    env_logger::init();
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(()) => {}
+        // The output was piped to a command that exited, ex. `head`
+        Err(CliError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(CliError::Json(err))
+            if err.io_error_kind() == Some(io::ErrorKind::BrokenPipe) => {}
+        Err(err) => {
+            eprintln!("poki: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), CliError> {
+    let profile = match &cli.profile {
+        Some(name) => Profile::named(name),
+        None => Profile::default(),
+    };
+    let config = Config::load(&profile)?;
+    let output = Output { json: cli.json };
+    if let Command::Config { dump } = cli.command {
+        return print_config(&output, &config, &profile, dump);
+    }
+    if let Command::Db(DbCommand::Import { file }) = cli.command {
+        return import_db(config, profile, file);
+    }
+    if let Command::Autostart { dry_run } = cli.command {
+        return run_autostart(&output, &config, dry_run);
+    }
+
+    let (mut apps_db, errors) = AppsDB::init(config, profile)?;
+    match cli.command {
+        Command::List => output.apps(&apps_db.get_ranked_list(None, None)),
+        Command::Search { query, limit } => {
+            let search = Some(query.as_str()).filter(|query| !query.is_empty());
+            output.apps(&apps_db.get_ranked_list(search, limit))
+        }
+        Command::Launch { app } => launch(&mut apps_db, &app),
+        Command::Rescan => output.rescan(&apps_db, &errors),
+        Command::Stats { limit } => output.stats(&apps_db, limit),
+        Command::Daemon { socket } => {
+            let socket = socket.unwrap_or_else(ipc::socket_path);
+            let mut daemon = Daemon::bind(apps_db, &socket)?;
+            daemon.watch()?;
+            Ok(daemon.run()?)
+        }
+        Command::Db(DbCommand::Export { file }) => {
+            let writer: Box<dyn Write> = match file {
+                Some(path) => Box::new(
+                    File::create(&path)
+                        .map_err(|err| CliError::File(path.clone(), err))?,
+                ),
+                None => Box::new(io::stdout()),
+            };
+            let mut writer = BufWriter::new(writer);
+            serde_json::to_writer_pretty(&mut writer, &apps_db)?;
+            writeln!(writer)?;
+            Ok(writer.flush()?)
+        }
+        Command::Config { .. }
+        | Command::Db(DbCommand::Import { .. })
+        | Command::Autostart { .. } => unreachable!(),
+    }
+}
+
+/// Find the app `arg` refers to in `apps_db`.
+///
+/// `arg` is either the key of an app, its desktop file ID without the
+/// `.desktop` suffix, or a search. Returns the app and the search it was
+/// found with, if it was searched for.
+fn find_app<'a>(
+    apps_db: &AppsDB,
+    arg: &'a str,
+) -> Option<(App, Option<&'a str>)> {
+    match apps_db.app(&AppKey::from(arg)) {
+        Some(app) => Some((app, None)),
+        None => apps_db
+            .get_ranked_list(Some(arg), Some(1))
+            .into_iter()
+            .next()
+            .map(|result| (result.app, Some(arg))),
+    }
+}
+
+/// Start the autostart entries, or only list them if `dry_run` is set.
+fn run_autostart(
+    output: &Output,
+    config: &Config,
+    dry_run: bool,
+) -> Result<(), CliError> {
+    let entries = autostart::entries();
+    if dry_run {
+        return output.autostart(&entries);
+    }
+    let failed = autostart::start(&entries, config);
+    for (entry, err) in &failed {
+        eprintln!("poki: {}: {}", entry.name, err);
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(CliError::Autostart(failed.len()))
+    }
+}
+
+/// Launch the app `arg` refers to, see `find_app`.
+fn launch(apps_db: &mut AppsDB, arg: &str) -> Result<(), CliError> {
+    let (app, query) = find_app(apps_db, arg)
+        .ok_or_else(|| CliError::NoApp(arg.to_owned()))?;
+    let options = RunOptions {
+        activation_token: std::env::var("XDG_ACTIVATION_TOKEN").ok(),
+        ..RunOptions::default()
+    };
+    let running = app.run_with_options(&[], &options, &apps_db.config)?;
+    apps_db.record_launch(&app, query)?;
+    running.check(START_GRACE_PERIOD)?;
+    Ok(())
+}
+
+/// Import a DB exported with `poki db export`.
+///
+/// The apps are rescanned afterwards, so apps that aren't installed are
+/// dropped and keep their scores for a while in case they are installed.
+fn import_db(
+    config: Config,
+    profile: Profile,
+    file: Option<PathBuf>,
+) -> Result<(), CliError> {
+    let mut apps_db: AppsDB = match &file {
+        Some(path) => serde_json::from_reader(BufReader::new(
+            File::open(path)
+                .map_err(|err| CliError::File(path.clone(), err))?,
+        ))?,
+        None => serde_json::from_reader(io::stdin().lock())?,
+    };
+    apps_db.config = config.clone();
+    apps_db.with_profile(profile.clone()).overwrite()?;
+    AppsDB::init(config, profile)?;
+    Ok(())
+}
+
+/// Print the config file path, or the full config if `dump` is set.
+fn print_config(
+    output: &Output,
+    config: &Config,
+    profile: &Profile,
+    dump: bool,
+) -> Result<(), CliError> {
+    if dump {
+        // The half life is in days in the config file
+        let config = Config {
+            half_life: config.half_life / (24. * 60. * 60.),
+            ..config.clone()
+        };
+        // JSON is valid hjson, so the dump can be used as config file
+        writeln!(io::stdout(), "{}", serde_json::to_string_pretty(&config)?)?;
+        return Ok(());
+    }
+    let path = profile.config_path().map(|path| path.display().to_string());
+    if output.json {
+        writeln!(io::stdout(), "{}", serde_json::json!({ "path": path }))?;
+    } else if let Some(path) = path {
+        writeln!(io::stdout(), "{}", path)?;
+    }
+    Ok(())
+}
+
+/// Launch statistics of an app printed by `poki stats`.
+#[derive(Debug, Serialize)]
+struct AppStats<'a> {
+    key: String,
+    name: &'a str,
+    launches: usize,
+    /// Time of the last launch in seconds since the epoch.
+    last_used: Option<f64>,
+    frecency: f32,
+}
+
+/// Prints the results of the commands.
+struct Output {
+    json: bool,
+}
+
+impl Output {
+    fn apps(&self, results: &[SearchResult]) -> Result<(), CliError> {
+        let stdout = io::stdout();
+        let mut stdout = BufWriter::new(stdout.lock());
+        if self.json {
+            serde_json::to_writer(&mut stdout, results)?;
+            writeln!(stdout)?;
+        } else {
+            for SearchResult { app, .. } in results {
+                writeln!(stdout, "{}\t{}", app.key(), app.name)?;
+            }
+        }
+        Ok(stdout.flush()?)
+    }
+
+    fn autostart(&self, entries: &[AutostartEntry]) -> Result<(), CliError> {
+        if self.json {
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|entry| {
+                    serde_json::json!({
+                        "name": entry.name,
+                        "path": entry.path,
+                        "start": entry.should_start(),
+                        "status": entry.status.to_string(),
+                    })
+                })
+                .collect();
+            writeln!(io::stdout(), "{}", serde_json::Value::from(entries))?;
+        } else {
+            let stdout = io::stdout();
+            let mut stdout = BufWriter::new(stdout.lock());
+            for entry in entries {
+                writeln!(stdout, "{}\t{}", entry.name, entry.status)?;
+            }
+            stdout.flush()?;
+        }
+        Ok(())
+    }
+
+    fn rescan(
+        &self,
+        apps_db: &AppsDB,
+        errors: &[ScanError],
+    ) -> Result<(), CliError> {
+        let errors: Vec<String> =
+            errors.iter().map(ToString::to_string).collect();
+        if self.json {
+            let result = serde_json::json!({
+                "apps": apps_db.apps.len(),
+                "errors": errors,
+            });
+            writeln!(io::stdout(), "{}", result)?;
+        } else {
+            writeln!(io::stdout(), "Found {} apps", apps_db.apps.len())?;
+            for error in errors {
+                eprintln!("{}", error);
+            }
+        }
+        Ok(())
+    }
+
+    fn stats(&self, apps_db: &AppsDB, limit: usize) -> Result<(), CliError> {
+        let mut stats: Vec<AppStats> = apps_db
+            .apps
+            .iter()
+            .map(|app| {
+                let history = apps_db.history(app);
+                AppStats {
+                    key: app.key().to_string(),
+                    name: &app.name,
+                    launches: history.launches,
+                    last_used: history.last_used,
+                    frecency: apps_db.get_frecency(app),
+                }
+            })
+            .filter(|stats| stats.launches > 0 || stats.frecency > 0.)
+            .collect();
+        stats.sort_by(|left, right| right.frecency.total_cmp(&left.frecency));
+        stats.truncate(limit);
+        let launches = apps_db.launches().len();
+        if self.json {
+            let result = serde_json::json!({
+                "apps": apps_db.apps.len(),
+                "launches": launches,
+                "top": stats,
+            });
+            writeln!(io::stdout(), "{}", result)?;
+            return Ok(());
+        }
+        writeln!(
+            io::stdout(),
+            "{} apps, {} launches",
+            apps_db.apps.len(),
+            launches
+        )?;
+        for stats in stats {
+            let last_used = stats
+                .last_used
+                .and_then(|time| Local.timestamp_opt(time as i64, 0).single())
+                .map_or_else(
+                    || "-".to_owned(),
+                    |time| time.format("%Y-%m-%d %H:%M").to_string(),
+                );
+            writeln!(
+                io::stdout(),
+                "{:>8.2} {:>5} {:>16}  {}",
+                stats.frecency,
+                stats.launches,
+                last_used,
+                stats.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Error running a command.
+#[derive(Debug, Error)]
+enum CliError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    AppsDB(#[from] AppDBError),
+    #[error(transparent)]
+    Run(#[from] RunError),
+    #[error(transparent)]
+    Daemon(#[from] DaemonError),
+    #[error("No app matches `{0}`")]
+    NoApp(String),
+    #[error("{0} autostart entries failed to start")]
+    Autostart(usize),
+    #[error("Error reading or writing {}: {1}", .0.display())]
+    File(PathBuf, io::Error),
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib_poki_launcher::AppAction;
+
+    #[test]
+    fn find() {
+        let mut firefox = App::new(
+            "Firefox".to_owned(),
+            "firefox".to_owned(),
+            vec!["firefox".to_owned()],
+            false,
+        );
+        firefox.id = "firefox.desktop".to_owned();
+        firefox.actions.push(AppAction {
+            id: "new-window".to_owned(),
+            name: "New Window".to_owned(),
+            exec: vec!["firefox".to_owned(), "--new-window".to_owned()],
+            ..AppAction::default()
+        });
+        let apps_db = AppsDB::new(Config::default(), vec![firefox])
+            .with_profile(Profile::in_memory());
+
+        let (app, query) = find_app(&apps_db, "firefox.desktop").unwrap();
+        assert_eq!((app.name.as_str(), query), ("Firefox", None));
+        let (app, _) = find_app(&apps_db, "firefox").unwrap();
+        assert_eq!(app.name, "Firefox");
+        let (app, query) =
+            find_app(&apps_db, "firefox.desktop:new-window").unwrap();
+        assert_eq!(app.action.as_deref(), Some("new-window"));
+        assert_eq!(query, None);
+        let (app, _) = find_app(&apps_db, "firefox:new-window").unwrap();
+        assert_eq!(app.action.as_deref(), Some("new-window"));
+        let (app, query) = find_app(&apps_db, "fire").unwrap();
+        assert_eq!((app.name.as_str(), query), ("Firefox", Some("fire")));
+        assert!(find_app(&apps_db, "zzz").is_none());
+    }
+}
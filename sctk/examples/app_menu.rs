@@ -4,7 +4,7 @@ use rusttype::{point, Font, Scale};
 
 use std::collections::VecDeque;
 
-use lib_poki_launcher::{App, AppsDB, Config, CFG_PATH, DB_PATH};
+use lib_poki_launcher::{App, AppsDB, Config, Profile, CFG_PATH, DB_PATH};
 
 use once_cell::sync::OnceCell;
 use std::sync::Mutex;
@@ -30,14 +30,15 @@ fn with_apps_db<R>(f: impl FnOnce(&mut AppsDB) -> R) -> Option<R> {
 fn main() {
 	env_logger::init();
 
-	let config = match Config::load() {
+	let profile = Profile::default();
+	let config = match Config::load(&profile) {
 		Ok(config) => config,
 		Err(e) => {
 			return;
 		}
 	};
 
-	let apps_db = match AppsDB::init(config) {
+	let apps_db = match AppsDB::init(config, profile) {
 		Ok((apps_db, scan_errors)) => apps_db,
 		Err(e) => {
 			return;
@@ -51,8 +52,8 @@ fn main() {
 	let fonty = Font::try_from_bytes(&font).expect("error constructing a Font from bytes");
 
 	if let Some(list) = with_apps_db(|apps_db| apps_db.get_ranked_list(Some("a"), Some(MAX_APPS_SHOWN))) {
-		for (n, mut el) in list.into_iter().enumerate() {
-			let iconpath = "/usr/share/icons/hicolor/48x48/apps/".to_string() + &el.icon + ".png";
+		for (n, mut el) in list.into_iter().map(|result| result.app).enumerate() {
+			let iconpath = with_apps_db(|apps_db| apps_db.icon_path(&el, 48, 1)).flatten();
 			println!("{:#?}", el);
 			//if let Some(Ok(icon)) = iconli.get(0){
 			//println!("{}, {:?}", el.name, iconpath);
@@ -64,7 +65,7 @@ fn main() {
 			el.name.truncate(40);
 
 			use image::imageops::grayscale;
-			let image = image::open(&iconpath).ok().map(|i| grayscale(&i));
+			let image = iconpath.and_then(|iconpath| image::open(&iconpath).ok()).map(|i| grayscale(&i));
 
 			let mut frame = Frame {
 				pixel_data: vec![0_u8; 600 * 50],